@@ -0,0 +1,223 @@
+//! One websocket client, reconnecting until the run is over
+
+use std::{error::Error, f64::consts::TAU, sync::atomic::Ordering, sync::Arc, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use protocol::{
+    ws::{ClientMessage, MSGPACK_SUBPROTOCOL},
+    PROTOCOL_VERSION,
+};
+use serde::{de::IgnoredAny, Deserialize};
+use tokio::{
+    net::TcpStream,
+    sync::watch,
+    time::{interval, sleep, sleep_until, Instant},
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    report::{Counters, Ticks},
+    Behaviour,
+};
+
+/// How often players steer their cell
+const STEER_EVERY: Duration = Duration::from_millis(200);
+
+/// How long to wait before connecting again after the server closed the connection
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// The parts of the server's messages clients look at
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+enum Incoming {
+    State {
+        tick: u64,
+        size: f64,
+    },
+    View {
+        tick: u64,
+        size: f64,
+    },
+    Joined(IgnoredAny),
+    Left(IgnoredAny),
+    #[serde(other)]
+    Other,
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Why a connection ended
+enum End {
+    /// The run is over
+    Stopped,
+    /// Time for a reconnect storm
+    Churn,
+    /// The server closed the connection
+    Closed,
+}
+
+pub struct Client {
+    pub id: usize,
+    pub behaviour: Behaviour,
+    pub url: String,
+    pub msgpack: bool,
+    pub slow_read: Duration,
+    pub churn_every: Duration,
+    /// Start of the run, which reconnect storms are timed from
+    pub start: Instant,
+    pub counters: Arc<[Counters; 4]>,
+    pub ticks: Arc<Ticks>,
+    pub stop: watch::Receiver<bool>,
+}
+
+impl Client {
+    fn counters(&self) -> &Counters {
+        &self.counters[self.behaviour as usize]
+    }
+
+    /// Keep a connection open until the run is over, opening another whenever one ends
+    pub async fn run(mut self) {
+        loop {
+            match self.connect().await {
+                Ok(End::Stopped) => break,
+                Ok(End::Churn) => continue,
+                Ok(End::Closed) => {
+                    self.counters().errors.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    eprintln!("client {} failed: {}", self.id, e);
+                    self.counters().errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            tokio::select! {
+                _ = sleep(RECONNECT_DELAY) => (),
+                _ = stopped(&mut self.stop) => break,
+            }
+        }
+    }
+
+    async fn connect(&mut self) -> Result<End, Box<dyn Error + Send + Sync>> {
+        let mut request = self.url.as_str().into_client_request()?;
+        if self.msgpack {
+            request.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static(MSGPACK_SUBPROTOCOL),
+            );
+        }
+        let (mut ws, _) = connect_async(request).await?;
+        let counters = Arc::clone(&self.counters);
+        let counters = &counters[self.behaviour as usize];
+        counters.connects.fetch_add(1, Ordering::Relaxed);
+        counters.connected.fetch_add(1, Ordering::Relaxed);
+        let end = self.session(&mut ws).await;
+        counters.connected.fetch_sub(1, Ordering::Relaxed);
+        let _ = ws.close(None).await;
+        end
+    }
+
+    async fn session(&mut self, ws: &mut Socket) -> Result<End, Box<dyn Error + Send + Sync>> {
+        let counters = Arc::clone(&self.counters);
+        let counters = &counters[self.behaviour as usize];
+
+        // The server says hello first, and waits for the same protocol version back
+        send(
+            ws,
+            &ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+            },
+        )
+        .await?;
+        let player = self.behaviour == Behaviour::Player;
+        if player {
+            let name = format!("loadtest-{}", self.id);
+            send(ws, &ClientMessage::Join { name }).await?;
+        }
+
+        // Churning clients all reconnect at the same multiples of `churn_every` since the start
+        let churn_at = match self.behaviour {
+            Behaviour::Churn => {
+                let every = self.churn_every.as_nanos().max(1);
+                let storms = self.start.elapsed().as_nanos() / every + 1;
+                Some(self.start + Duration::from_nanos((storms * every) as u64))
+            }
+            _ => None,
+        };
+        let mut steer = interval(STEER_EVERY);
+        let mut joined = false;
+        let mut size = 0.0;
+        let mut last_tick = None;
+
+        loop {
+            let msg = tokio::select! {
+                _ = stopped(&mut self.stop) => return Ok(End::Stopped),
+                _ = sleep_until(churn_at.unwrap_or(self.start)), if churn_at.is_some() => {
+                    return Ok(End::Churn);
+                }
+                _ = steer.tick(), if joined => {
+                    // Circle around the middle of the biome, each player starting elsewhere
+                    let angle = self.start.elapsed().as_secs_f64() + self.id as f64 * TAU / 16.0;
+                    let target = [
+                        size / 2.0 + size / 3.0 * angle.cos(),
+                        size / 2.0 + size / 3.0 * angle.sin(),
+                    ];
+                    send(ws, &ClientMessage::Steer { target }).await?;
+                    continue;
+                }
+                msg = ws.next() => msg,
+            };
+            let received = std::time::Instant::now();
+            let msg = match msg {
+                Some(msg) => msg?,
+                None => return Ok(End::Closed),
+            };
+            let incoming = match &msg {
+                Message::Text(text) => serde_json::from_str(text).ok(),
+                Message::Binary(data) => rmp_serde::from_slice(data).ok(),
+                Message::Close(_) => return Ok(End::Closed),
+                _ => None,
+            };
+            counters
+                .bytes
+                .fetch_add(msg.len() as u64, Ordering::Relaxed);
+
+            match incoming {
+                Some(Incoming::State { tick, size: s } | Incoming::View { tick, size: s }) => {
+                    size = s;
+                    // Paused simulators repeat their tick, and reset ones start over
+                    if last_tick.is_none_or(|last| tick > last) {
+                        if let Some(last) = last_tick {
+                            counters
+                                .dropped
+                                .fetch_add(tick - last - 1, Ordering::Relaxed);
+                        }
+                        counters.frames.fetch_add(1, Ordering::Relaxed);
+                        counters.observe_latency(self.ticks.latency(tick, received));
+                    }
+                    last_tick = Some(tick);
+                }
+                Some(Incoming::Joined(_)) => joined = true,
+                Some(Incoming::Left(_)) => joined = false,
+                Some(Incoming::Other) | None => (),
+            }
+
+            if self.behaviour == Behaviour::Slow {
+                sleep(self.slow_read).await;
+            }
+        }
+    }
+}
+
+/// Resolves once the run is over
+async fn stopped(stop: &mut watch::Receiver<bool>) {
+    // The sender outlives the clients
+    let _ = stop.wait_for(|x| *x).await;
+}
+
+async fn send(ws: &mut Socket, msg: &ClientMessage) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ws.send(Message::Text(serde_json::to_string(msg)?)).await?;
+    Ok(())
+}
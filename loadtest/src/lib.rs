@@ -0,0 +1,247 @@
+//! Load testing of the server's websockets
+//!
+//! Opens many clients against a running server at once, each [`Behaviour`] in its own way, and
+//! [`Report`]s how many frames they get, how late and how many they miss, along with the memory the
+//! server reports. With `--publish` it also stands in for the simulator, publishing made up frames
+//! for the server to subscribe to, so latencies are measured from the moment a frame is published.
+
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use clap::Parser;
+use tokio::{
+    sync::watch,
+    time::{interval, Instant, MissedTickBehavior},
+};
+
+mod client;
+pub mod publisher;
+mod report;
+
+pub use report::{Report, Row, Ticks};
+
+use client::Client;
+use publisher::Publisher;
+use report::Counters;
+
+/// How a client behaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Behaviour {
+    /// Reads every message as soon as it arrives
+    Viewer,
+    /// Joins and steers a cell around, while reading like a viewer
+    Player,
+    /// Takes `slow_read_ms` to read each message
+    Slow,
+    /// Disconnects and reconnects every `churn_every_ms`, all at once with the other churning clients
+    Churn,
+}
+
+impl Behaviour {
+    pub const ALL: [Behaviour; 4] = [
+        Behaviour::Viewer,
+        Behaviour::Player,
+        Behaviour::Slow,
+        Behaviour::Churn,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Behaviour::Viewer => "viewer",
+            Behaviour::Player => "player",
+            Behaviour::Slow => "slow",
+            Behaviour::Churn => "churn",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(
+    about = "Opens many websockets against a microbiome server and measures how it keeps up",
+    long_about = None
+)]
+pub struct Options {
+    /// Address of the server
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub server: String,
+    /// Room to watch, the server's default room if omitted
+    #[arg(long)]
+    pub room: Option<String>,
+    /// Token every client presents, which has to be a player's for `--players` to join
+    #[arg(long)]
+    pub token: Option<String>,
+    /// Clients to open
+    #[arg(long, default_value_t = 10)]
+    pub clients: usize,
+    /// How many of the clients join and steer a cell
+    #[arg(long, default_value_t = 0)]
+    pub players: usize,
+    /// How many of the clients read slowly
+    #[arg(long, default_value_t = 0)]
+    pub slow: usize,
+    /// How long slow clients take to read each message
+    #[arg(long, default_value_t = 200)]
+    pub slow_read_ms: u64,
+    /// How many of the clients reconnect together, in storms
+    #[arg(long, default_value_t = 0)]
+    pub churn: usize,
+    /// Time between reconnect storms
+    #[arg(long, default_value_t = 1000)]
+    pub churn_every_ms: u64,
+    /// Ask for MessagePack frames instead of JSON
+    #[arg(long)]
+    pub msgpack: bool,
+    /// How long to keep the clients connected
+    #[arg(long, default_value_t = 10_000)]
+    pub duration_ms: u64,
+    /// Time between reports
+    #[arg(long, default_value_t = 1000)]
+    pub report_every_ms: u64,
+    /// Publish made up frames on this zmq endpoint, for a server subscribed to it
+    #[arg(long)]
+    pub publish: Option<String>,
+    /// Frames published per second
+    #[arg(long, default_value_t = 25)]
+    pub publish_fps: u32,
+    /// Cells in each published frame
+    #[arg(long, default_value_t = 100)]
+    pub publish_npcs: usize,
+    /// Food in each published frame
+    #[arg(long, default_value_t = 500)]
+    pub publish_food: usize,
+}
+
+impl Options {
+    /// Behaviour of each client, players first
+    fn behaviours(&self) -> Result<Vec<Behaviour>, String> {
+        let special = self.players + self.slow + self.churn;
+        if special > self.clients {
+            return Err(format!(
+                "players, slow and churn add up to {special}, more than the {} clients",
+                self.clients
+            ));
+        }
+        let mut behaviours = Vec::with_capacity(self.clients);
+        for (behaviour, count) in [
+            (Behaviour::Player, self.players),
+            (Behaviour::Slow, self.slow),
+            (Behaviour::Churn, self.churn),
+            (Behaviour::Viewer, self.clients - special),
+        ] {
+            behaviours.extend(std::iter::repeat_n(behaviour, count));
+        }
+        Ok(behaviours)
+    }
+
+    fn url(&self) -> String {
+        let url = match &self.room {
+            Some(room) => format!("ws://{}/ws/{room}", self.server),
+            None => format!("ws://{}/ws", self.server),
+        };
+        match &self.token {
+            Some(token) => format!("{url}?token={token}"),
+            None => url,
+        }
+    }
+}
+
+/// Run the clients for `duration_ms`, handing a report of each `report_every_ms` to `on_report`
+///
+/// **Returns** a report of the whole run, with the peak memory of the server
+pub async fn run(
+    opts: Options,
+    mut on_report: impl FnMut(&Report),
+) -> Result<Report, Box<dyn Error + Send + Sync>> {
+    let behaviours = opts.behaviours()?;
+    let ticks = Arc::new(Ticks::default());
+
+    let stop_publishing = Arc::new(AtomicBool::new(false));
+    let publisher = match &opts.publish {
+        Some(endpoint) => {
+            let publisher = Publisher::bind(endpoint, opts.publish_npcs, opts.publish_food)?;
+            Some(publisher::spawn(
+                publisher,
+                opts.publish_fps,
+                Arc::clone(&ticks),
+                Arc::clone(&stop_publishing),
+            ))
+        }
+        None => None,
+    };
+
+    let counters: Arc<[Counters; 4]> = Arc::default();
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let start = Instant::now();
+    let url = opts.url();
+    let clients: Vec<_> = behaviours
+        .iter()
+        .enumerate()
+        .map(|(id, &behaviour)| {
+            let client = Client {
+                id,
+                behaviour,
+                url: url.clone(),
+                msgpack: opts.msgpack,
+                slow_read: Duration::from_millis(opts.slow_read_ms),
+                churn_every: Duration::from_millis(opts.churn_every_ms),
+                start,
+                counters: Arc::clone(&counters),
+                ticks: Arc::clone(&ticks),
+                stop: stop_rx.clone(),
+            };
+            tokio::spawn(client.run())
+        })
+        .collect();
+
+    let duration = Duration::from_millis(opts.duration_ms);
+    let mut reports = interval(Duration::from_millis(opts.report_every_ms));
+    reports.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick is immediate
+    reports.tick().await;
+    let mut last = report::Snapshot::take(&counters);
+    let mut last_at = start;
+    let mut peak_memory = None;
+    while start.elapsed() < duration {
+        reports.tick().await;
+        let now = Instant::now();
+        let snapshot = report::Snapshot::take(&counters);
+        let memory = report::server_memory(&opts.server).await;
+        peak_memory = peak_memory.max(memory);
+        on_report(&Report::new(
+            now - start,
+            now - last_at,
+            &behaviours,
+            &last,
+            &snapshot,
+            memory,
+        ));
+        last = snapshot;
+        last_at = now;
+    }
+
+    let _ = stop_tx.send(true);
+    for client in clients {
+        client.await?;
+    }
+    stop_publishing.store(true, Ordering::Relaxed);
+    if let Some(publisher) = publisher {
+        publisher.join().map_err(|_| "the publisher panicked")??;
+    }
+
+    let elapsed = start.elapsed();
+    let end = report::Snapshot::take(&counters);
+    Ok(Report::new(
+        elapsed,
+        elapsed,
+        &behaviours,
+        &report::Snapshot::default(),
+        &end,
+        peak_memory,
+    ))
+}
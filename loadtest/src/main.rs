@@ -0,0 +1,16 @@
+use std::process;
+
+use clap::Parser;
+use microbiome_loadtest::{run, Options};
+
+#[tokio::main]
+async fn main() {
+    let opts = Options::parse();
+    match run(opts, |report| println!("{report}")).await {
+        Ok(report) => println!("over the whole run, with the peak server memory:\n{report}"),
+        Err(e) => {
+            eprintln!("load test failed: {e}");
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! A stand-in for the simulator, publishing made up frames the way it does
+
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    sim::{SimMessage, TOPIC},
+    FoodState, NpcKind, NpcState, WorldState,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::Ticks;
+
+/// Width and height of the made up biome
+const SIZE: f64 = 500.0;
+
+/// Distance cells move each frame
+const SPEED: f64 = 2.0;
+
+/// A zmq PUB socket publishing frames of cells drifting around a biome
+pub struct Publisher {
+    socket: zmq::Socket,
+    endpoint: String,
+    state: WorldState,
+}
+
+impl Publisher {
+    /// Bind a PUB socket at `endpoint`, which may end in `:*` to pick any free port
+    pub fn bind(
+        endpoint: &str,
+        npcs: usize,
+        food: usize,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let socket = zmq::Context::new().socket(zmq::PUB)?;
+        socket.bind(endpoint)?;
+        let endpoint = socket
+            .get_last_endpoint()?
+            .map_err(|_| "the bound endpoint is not UTF-8")?;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut pos = || [rng.gen_range(0.0..SIZE), rng.gen_range(0.0..SIZE)];
+        let npcs = (0..npcs as u64)
+            .map(|id| {
+                let angle = id as f64;
+                NpcState {
+                    id,
+                    pos: pos(),
+                    mass: 10.0,
+                    radius: 10f64.sqrt(),
+                    color: "#3b82f6".to_string(),
+                    kind: NpcKind::Linear {
+                        dir: [angle.cos(), angle.sin()],
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        let food = (0..food as u64)
+            .map(|id| FoodState {
+                id: npcs.len() as u64 + id,
+                pos: pos(),
+                mass: 1.0,
+                radius: 1.0,
+                color: "#22c55e".to_string(),
+            })
+            .collect();
+
+        Ok(Self {
+            socket,
+            endpoint,
+            state: WorldState {
+                tick: 0,
+                size: SIZE,
+                cell_perception_radius: 200.0,
+                food_perception_radius: 200.0,
+                npcs,
+                food,
+            },
+        })
+    }
+
+    /// Endpoint the socket is bound to, with the port it picked
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Move every cell along and publish the next frame
+    ///
+    /// **Returns** the tick of the frame
+    pub fn publish(&mut self) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        self.state.tick += 1;
+        for npc in &mut self.state.npcs {
+            if let NpcKind::Linear { dir } = npc.kind {
+                npc.pos[0] = (npc.pos[0] + dir[0] * SPEED).rem_euclid(SIZE);
+                npc.pos[1] = (npc.pos[1] + dir[1] * SPEED).rem_euclid(SIZE);
+            }
+        }
+        let msg = SimMessage::State {
+            state: self.state.clone(),
+        };
+        self.send_raw(&msg.to_frames(TOPIC)?)?;
+        Ok(self.state.tick)
+    }
+
+    /// Publish frames as they are, such as ones the server can't read
+    pub fn send_raw<T: AsRef<[u8]>>(&self, frames: &[T]) -> Result<(), zmq::Error> {
+        self.socket
+            .send_multipart(frames.iter().map(AsRef::as_ref), zmq::DONTWAIT)
+    }
+}
+
+/// Publish `fps` frames a second on a thread of its own until `stop`, noting when each tick was
+/// published in `ticks`
+pub fn spawn(
+    mut publisher: Publisher,
+    fps: u32,
+    ticks: Arc<Ticks>,
+    stop: Arc<AtomicBool>,
+) -> thread::JoinHandle<Result<(), Box<dyn Error + Send + Sync>>> {
+    let frame = Duration::from_secs_f64(1.0 / fps.max(1) as f64);
+    thread::spawn(move || {
+        let mut next = Instant::now();
+        while !stop.load(Ordering::Relaxed) {
+            // Before publishing, so the first client to get the frame finds it
+            ticks.published(publisher.state.tick + 1, Instant::now());
+            publisher.publish()?;
+            next += frame;
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,305 @@
+//! What the clients saw, counted as they go and reported at intervals
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::Behaviour;
+
+/// Latencies are bucketed by powers of 2^(1/4) microseconds, so percentiles are at most 19% high
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+/// Enough buckets for latencies of over an hour
+const BUCKETS: usize = 128;
+
+/// Ticks whose times are kept, more than any client should lag by
+const KEEP_TICKS: usize = 1000;
+
+/// How long to wait for the server's metrics
+const METRICS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// When each tick was published, or first received by any client when the simulator isn't ours
+#[derive(Debug, Default)]
+pub struct Ticks {
+    seen: Mutex<BTreeMap<u64, Instant>>,
+}
+
+impl Ticks {
+    pub fn published(&self, tick: u64, at: Instant) {
+        let mut seen = self.seen.lock().unwrap();
+        seen.insert(tick, at);
+        if seen.len() > KEEP_TICKS {
+            seen.pop_first();
+        }
+    }
+
+    /// Time from `tick` being published to it being received
+    pub fn latency(&self, tick: u64, received: Instant) -> Duration {
+        let mut seen = self.seen.lock().unwrap();
+        let at = *seen.entry(tick).or_insert(received);
+        if seen.len() > KEEP_TICKS {
+            seen.pop_first();
+        }
+        received.saturating_duration_since(at)
+    }
+}
+
+/// Counts of one kind of client, updated by each of them
+#[derive(Debug)]
+pub struct Counters {
+    /// Clients connected right now
+    pub connected: AtomicU64,
+    /// Connections made, counting reconnects
+    pub connects: AtomicU64,
+    /// Connections that failed or that the server closed
+    pub errors: AtomicU64,
+    /// Frames of new ticks
+    pub frames: AtomicU64,
+    /// Ticks missing between two frames
+    pub dropped: AtomicU64,
+    /// Bytes of every message received
+    pub bytes: AtomicU64,
+    latency: [AtomicU64; BUCKETS],
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            connected: AtomicU64::new(0),
+            connects: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            latency: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Counters {
+    pub fn observe_latency(&self, latency: Duration) {
+        let us = latency.as_micros().max(1) as f64;
+        let bucket = ((us.log2() * BUCKETS_PER_DOUBLING) as usize).min(BUCKETS - 1);
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Upper bound of a latency bucket
+fn bucket_bound(bucket: usize) -> Duration {
+    let us = 2f64.powf((bucket + 1) as f64 / BUCKETS_PER_DOUBLING);
+    Duration::from_micros(us.ceil() as u64)
+}
+
+/// Values of the counters of one kind of client at a point in time
+#[derive(Debug, Clone, Default)]
+struct Totals {
+    connected: u64,
+    connects: u64,
+    errors: u64,
+    frames: u64,
+    dropped: u64,
+    bytes: u64,
+    latency: Vec<u64>,
+}
+
+/// Values of the counters of every kind of client at a point in time
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot([Totals; 4]);
+
+impl Snapshot {
+    pub fn take(counters: &[Counters; 4]) -> Self {
+        Self(std::array::from_fn(|i| {
+            let c = &counters[i];
+            Totals {
+                connected: c.connected.load(Ordering::Relaxed),
+                connects: c.connects.load(Ordering::Relaxed),
+                errors: c.errors.load(Ordering::Relaxed),
+                frames: c.frames.load(Ordering::Relaxed),
+                dropped: c.dropped.load(Ordering::Relaxed),
+                bytes: c.bytes.load(Ordering::Relaxed),
+                latency: c
+                    .latency
+                    .iter()
+                    .map(|x| x.load(Ordering::Relaxed))
+                    .collect(),
+            }
+        }))
+    }
+}
+
+/// What each kind of client saw over a span of the run
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Time since the start of the run
+    pub elapsed: Duration,
+    /// Time the report covers
+    pub span: Duration,
+    /// A row for each behaviour some clients have
+    pub rows: Vec<Row>,
+    /// Resident memory the server reported at the end of the span, or its peak over the whole run
+    pub server_memory: Option<u64>,
+}
+
+/// What the clients of one behaviour saw over a span of the run
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub behaviour: Behaviour,
+    pub clients: usize,
+    /// Clients connected at the end of the span
+    pub connected: u64,
+    /// Connections made, counting reconnects
+    pub connects: u64,
+    /// Connections that failed or that the server closed
+    pub errors: u64,
+    /// Frames of new ticks
+    pub frames: u64,
+    /// Ticks missing between two frames
+    pub dropped: u64,
+    pub bytes: u64,
+    /// Latency percentiles, none without frames
+    pub p50: Option<Duration>,
+    pub p99: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl Row {
+    /// Share of the ticks the clients missed
+    pub fn drop_rate(&self) -> f64 {
+        match self.frames + self.dropped {
+            0 => 0.0,
+            ticks => self.dropped as f64 / ticks as f64,
+        }
+    }
+}
+
+impl Report {
+    pub fn new(
+        elapsed: Duration,
+        span: Duration,
+        behaviours: &[Behaviour],
+        from: &Snapshot,
+        to: &Snapshot,
+        server_memory: Option<u64>,
+    ) -> Self {
+        let rows = Behaviour::ALL
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &behaviour)| {
+                let clients = behaviours.iter().filter(|x| **x == behaviour).count();
+                if clients == 0 {
+                    return None;
+                }
+                let (from, to) = (&from.0[i], &to.0[i]);
+                let latency: Vec<u64> = to
+                    .latency
+                    .iter()
+                    .enumerate()
+                    .map(|(b, n)| n - from.latency.get(b).copied().unwrap_or(0))
+                    .collect();
+                Some(Row {
+                    behaviour,
+                    clients,
+                    connected: to.connected,
+                    connects: to.connects - from.connects,
+                    errors: to.errors - from.errors,
+                    frames: to.frames - from.frames,
+                    dropped: to.dropped - from.dropped,
+                    bytes: to.bytes - from.bytes,
+                    p50: percentile(&latency, 0.5),
+                    p99: percentile(&latency, 0.99),
+                    max: percentile(&latency, 1.0),
+                })
+            })
+            .collect();
+        Self {
+            elapsed,
+            span,
+            rows,
+            server_memory,
+        }
+    }
+}
+
+/// Upper bound of the bucket the `p` quantile falls in
+fn percentile(counts: &[u64], p: f64) -> Option<Duration> {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let rank = ((total as f64 * p).ceil() as u64).max(1);
+    let mut seen = 0;
+    counts.iter().enumerate().find_map(|(bucket, n)| {
+        seen += n;
+        (seen >= rank).then(|| bucket_bound(bucket))
+    })
+}
+
+fn mib(bytes: f64) -> f64 {
+    bytes / (1024.0 * 1024.0)
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.span.as_secs_f64().max(f64::EPSILON);
+        write!(f, "{:>6.1}s", self.elapsed.as_secs_f64())?;
+        match self.server_memory {
+            Some(bytes) => writeln!(f, "  server memory {:.1} MiB", mib(bytes as f64))?,
+            None => writeln!(f, "  server memory unknown")?,
+        }
+        for row in &self.rows {
+            write!(
+                f,
+                "  {:>7} {:>5}/{:<5} connected  {:>5.1} fps  {:>5.1}% dropped",
+                row.behaviour.name(),
+                row.connected,
+                row.clients,
+                row.frames as f64 / row.clients as f64 / secs,
+                row.drop_rate() * 100.0,
+            )?;
+            match (row.p50, row.p99, row.max) {
+                (Some(p50), Some(p99), Some(max)) => {
+                    write!(f, "  latency p50 {p50:.1?} p99 {p99:.1?} max {max:.1?}")?
+                }
+                _ => write!(f, "  latency -")?,
+            }
+            writeln!(
+                f,
+                "  {:.2} MiB/s  {} connects  {} errors",
+                mib(row.bytes as f64) / secs,
+                row.connects,
+                row.errors
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Resident memory the server reports on `/metrics`, if it does
+pub async fn server_memory(server: &str) -> Option<u64> {
+    let body = timeout(METRICS_TIMEOUT, async {
+        let mut stream = TcpStream::connect(server).await.ok()?;
+        let request = format!("GET /metrics HTTP/1.0\r\nHost: {server}\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.ok()?;
+        let mut body = String::new();
+        stream.read_to_string(&mut body).await.ok()?;
+        Some(body)
+    })
+    .await
+    .ok()??;
+    body.lines()
+        .find_map(|line| line.strip_prefix("process_resident_memory_bytes "))?
+        .trim()
+        .parse()
+        .ok()
+}
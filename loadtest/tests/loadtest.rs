@@ -0,0 +1,181 @@
+//! Runs the load tester against a server in the same process, fed by the tester's fake simulator
+
+use std::net::{SocketAddr, TcpListener};
+
+use clap::Parser;
+use microbiome_backend::{
+    app::{make_app, serve},
+    config::{Config, Settings},
+};
+use microbiome_loadtest::{run, Behaviour, Options, Report, Row};
+use tokio::sync::watch;
+
+/// A server on a port of its own, shut down when dropped
+struct Server {
+    addr: SocketAddr,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.shutdown.send(true);
+    }
+}
+
+/// Start a server with a single room named `load`, configured by `room`
+async fn start_server(room: &str) -> Server {
+    let settings: Settings = toml::from_str(&format!(
+        r#"
+        static_path = "{}"
+        recordings_path = "{}"
+        player_tokens = ["pawn"]
+
+        [[rooms]]
+        name = "load"
+        {room}
+        "#,
+        env!("CARGO_MANIFEST_DIR"),
+        std::env::temp_dir().display(),
+    ))
+    .unwrap();
+    let config = Config::resolve(settings).unwrap();
+
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let (app, _) = make_app(&config, shutdown_rx.clone()).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, app, shutdown_rx));
+    Server { addr, shutdown }
+}
+
+/// A zmq endpoint on a port that was free a moment ago
+fn free_endpoint() -> String {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    format!("tcp://127.0.0.1:{port}")
+}
+
+/// Start a server subscribed to the fake simulator, and run the tester with `args` against it,
+/// publishing small frames
+async fn run_published(args: &[&str]) -> (Vec<Report>, Report) {
+    let endpoint = free_endpoint();
+    let server = start_server(&format!("sub_at = \"{endpoint}\"")).await;
+    let mut all = vec![
+        "microbiome-loadtest".to_string(),
+        format!("--server={}", server.addr),
+        "--room=load".to_string(),
+        format!("--publish={endpoint}"),
+        "--publish-fps=20".to_string(),
+        "--publish-npcs=10".to_string(),
+        "--publish-food=20".to_string(),
+        "--report-every-ms=500".to_string(),
+    ];
+    all.extend(args.iter().map(|x| x.to_string()));
+
+    let mut reports = Vec::new();
+    let total = run(Options::parse_from(all), |r| reports.push(r.clone()))
+        .await
+        .unwrap();
+    (reports, total)
+}
+
+fn row(report: &Report, behaviour: Behaviour) -> &Row {
+    report
+        .rows
+        .iter()
+        .find(|x| x.behaviour == behaviour)
+        .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn viewers_get_the_published_frames() {
+    let (reports, total) = run_published(&["--clients=8", "--duration-ms=2000"]).await;
+
+    let viewers = row(&total, Behaviour::Viewer);
+    assert_eq!(viewers.clients, 8);
+    assert_eq!(viewers.connects, 8);
+    assert_eq!(viewers.errors, 0);
+    // Frames only flow once the server's subscription catches up, give it a second
+    assert!(viewers.frames >= 8 * 10, "{total}");
+    assert!(viewers.drop_rate() < 0.5, "{total}");
+    assert!(viewers.p50.is_some() && viewers.p50 <= viewers.max);
+
+    let last = reports.last().unwrap();
+    assert_eq!(row(last, Behaviour::Viewer).connected, 8, "{last}");
+    if cfg!(target_os = "linux") {
+        assert!(total.server_memory.is_some());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn churning_clients_reconnect_together() {
+    let (_, total) = run_published(&[
+        "--clients=6",
+        "--churn=4",
+        "--churn-every-ms=500",
+        "--duration-ms=2000",
+    ])
+    .await;
+
+    let churn = row(&total, Behaviour::Churn);
+    // A connection each, then one per storm
+    assert!(churn.connects >= 4 * 4, "{total}");
+    assert_eq!(churn.errors, 0, "{total}");
+    let viewers = row(&total, Behaviour::Viewer);
+    assert_eq!(viewers.connects, 2, "{total}");
+    assert_eq!(viewers.errors, 0, "{total}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_clients_fall_behind_alone() {
+    let (_, total) = run_published(&[
+        "--clients=4",
+        "--slow=2",
+        "--slow-read-ms=500",
+        "--duration-ms=2000",
+    ])
+    .await;
+
+    let slow = row(&total, Behaviour::Slow);
+    let viewers = row(&total, Behaviour::Viewer);
+    assert!(viewers.frames > slow.frames * 2, "{total}");
+    assert!(slow.p50 > viewers.p50, "{total}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn measures_embedded_rooms_without_publishing() {
+    let server = start_server("embedded = true\nseed = 1").await;
+    let opts = Options::parse_from([
+        "microbiome-loadtest".to_string(),
+        format!("--server={}", server.addr),
+        "--room=load".to_string(),
+        "--clients=3".to_string(),
+        "--players=1".to_string(),
+        "--token=pawn".to_string(),
+        "--duration-ms=1500".to_string(),
+    ]);
+    let total = run(opts, |_| ()).await.unwrap();
+
+    for behaviour in [Behaviour::Viewer, Behaviour::Player] {
+        let row = row(&total, behaviour);
+        assert!(row.frames > 0, "{total}");
+        assert_eq!(row.errors, 0, "{total}");
+        // Measured from the first client to get each tick
+        assert!(row.max.is_some(), "{total}");
+    }
+}
+
+#[tokio::test]
+async fn refuses_more_special_clients_than_clients() {
+    let opts = Options::parse_from([
+        "microbiome-loadtest",
+        "--clients=2",
+        "--slow=2",
+        "--churn=1",
+    ]);
+    let err = run(opts, |_| ()).await.unwrap_err();
+    assert!(err.to_string().contains("more than the 2 clients"), "{err}");
+}
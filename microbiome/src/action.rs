@@ -0,0 +1,12 @@
+use crate::{Action, P2, V2};
+
+/// Unit direction a cell at `pos` should move in to follow an action, or zero to stay put
+pub fn direction(action: &Action, pos: P2) -> V2 {
+    let dir = match (action.target, action.dir) {
+        // Don't jitter around a target that has been reached
+        (Some([x, y]), _) => (P2::new(x, y) - pos).try_normalize(1.0),
+        (None, Some([x, y])) => V2::new(x, y).try_normalize(f64::EPSILON),
+        (None, None) => None,
+    };
+    dir.unwrap_or_else(V2::zeros)
+}
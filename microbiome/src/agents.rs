@@ -0,0 +1,401 @@
+//! Protocol for driving cells from processes outside the simulator
+//!
+//! Clients connect a REQ socket to the agents endpoint and send one JSON request at a time,
+//! each answered with one JSON response:
+//!
+//! - `{"msg": "register", "version": 1}` spawns a cell and answers with its first observation, as long as
+//!   the agent speaks the simulator's protocol version
+//! - `{"msg": "act", "action": {"dir": [1.0, 0.0]}}` sets the action of the agent's cells. In lockstep
+//!   mode the answer is held back until the world has advanced, in free-running mode it comes right away.
+//!   Another `act` while one is held back is refused.
+//! - `{"msg": "leave"}` removes the agent's cells
+//!
+//! Observations come as `{"msg": "observation", "agent": 3, "observation": {...}}`. Once every cell of
+//! an agent has been eaten the answer is `{"msg": "dead", "agent": 3}` and the agent has to register again.
+//! So does an agent that goes [`AgentOptions::max_missed_ticks`] without a request, whose cells are
+//! removed.
+
+use std::{collections::HashMap, error::Error, time::Duration};
+
+use protocol::PROTOCOL_VERSION;
+
+use crate::{Action, Microbiome};
+
+pub use protocol::agents::{Request, Response};
+
+/// When the world advances relative to the agents' actions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Wait for every agent to act, or for the timeout, before each step
+    Lockstep,
+    /// Step at the normal frame rate, using the last action each agent sent
+    FreeRunning,
+}
+
+#[derive(Debug, Clone)]
+pub struct AgentOptions {
+    pub mode: Mode,
+    /// How long to wait for an agent's action in lockstep mode
+    pub timeout: Duration,
+    /// Action used for agents that have not acted in time
+    pub default_action: Action,
+    /// Ticks an agent may go without a request before its cells are removed and it is dropped
+    pub max_missed_ticks: u64,
+}
+
+#[derive(Debug)]
+struct Agent {
+    owner: u64,
+    /// Envelope of a request waiting on the world to advance
+    waiting: Option<Vec<Vec<u8>>>,
+    /// Whether the agent acted since the last step, or has nothing left to act for
+    acted: bool,
+    /// Tick of the agent's last request
+    last_request: u64,
+}
+
+/// Registered agents and the ROUTER socket they talk to
+pub struct AgentHub {
+    sock: zmq::Socket,
+    opts: AgentOptions,
+    agents: HashMap<Vec<u8>, Agent>,
+}
+
+impl AgentHub {
+    pub fn bind(
+        context: &zmq::Context,
+        endpoint: &str,
+        opts: AgentOptions,
+    ) -> Result<Self, zmq::Error> {
+        let sock = context.socket(zmq::ROUTER)?;
+        sock.bind(endpoint)?;
+
+        Ok(Self {
+            sock,
+            opts,
+            agents: HashMap::new(),
+        })
+    }
+
+    pub fn socket(&self) -> &zmq::Socket {
+        &self.sock
+    }
+
+    pub fn options(&self) -> &AgentOptions {
+        &self.opts
+    }
+
+    /// Whether the world should hold off on advancing until more agents act
+    pub fn waiting_for_actions(&self) -> bool {
+        self.opts.mode == Mode::Lockstep && self.agents.values().any(|x| !x.acted)
+    }
+
+    fn send(&self, envelope: &[Vec<u8>], response: &Response) -> Result<(), Box<dyn Error>> {
+        let payload = serde_json::to_vec(response)?;
+        let frames = envelope
+            .iter()
+            .map(Vec::as_slice)
+            .chain([payload.as_slice()]);
+        self.sock.send_multipart(frames, 0)?;
+        Ok(())
+    }
+
+    fn observe(mb: &Microbiome, owner: u64) -> Response {
+        match mb.observe(owner) {
+            Some(observation) => Response::Observation {
+                agent: owner,
+                observation,
+            },
+            None => Response::Dead { agent: owner },
+        }
+    }
+
+    /// Handle every request that has arrived, without blocking
+    pub fn recv(&mut self, mb: &mut Microbiome) -> Result<(), Box<dyn Error>> {
+        loop {
+            let mut envelope = match self.sock.recv_multipart(zmq::DONTWAIT) {
+                Ok(frames) => frames,
+                Err(zmq::Error::EAGAIN) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let payload = envelope.pop().unwrap_or_default();
+            let Some(identity) = envelope.first().cloned() else {
+                continue;
+            };
+
+            let request = match serde_json::from_slice::<Request>(&payload) {
+                Ok(request) => request,
+                Err(e) => {
+                    let message = format!("invalid request: {e}");
+                    self.send(&envelope, &Response::Error { message })?;
+                    continue;
+                }
+            };
+
+            if let Some(agent) = self.agents.get_mut(&identity) {
+                agent.last_request = mb.elapsed();
+            }
+            match (request, self.agents.get_mut(&identity)) {
+                (Request::Register { .. }, Some(_)) => {
+                    let message = "already registered".to_string();
+                    self.send(&envelope, &Response::Error { message })?;
+                }
+                (Request::Register { version }, None) if version != PROTOCOL_VERSION => {
+                    let message = format!(
+                        "protocol version {version} is not supported, expected {PROTOCOL_VERSION}"
+                    );
+                    self.send(&envelope, &Response::Error { message })?;
+                }
+                (Request::Register { .. }, None) => {
+                    let owner = mb.spawn_controlled(None);
+                    mb.set_action(owner, self.opts.default_action);
+                    let agent = Agent {
+                        owner,
+                        waiting: None,
+                        acted: false,
+                        last_request: mb.elapsed(),
+                    };
+                    self.agents.insert(identity, agent);
+                    self.send(&envelope, &Self::observe(mb, owner))?;
+                }
+                // A DEALER can send again before its held request was answered
+                (Request::Act { .. }, Some(agent)) if agent.waiting.is_some() => {
+                    let message = "still waiting for the world to advance".to_string();
+                    self.send(&envelope, &Response::Error { message })?;
+                }
+                (Request::Act { action }, Some(agent)) => {
+                    let owner = agent.owner;
+                    if !mb.set_action(owner, action) {
+                        self.agents.remove(&identity);
+                        self.send(&envelope, &Response::Dead { agent: owner })?;
+                    } else if self.opts.mode == Mode::Lockstep {
+                        agent.acted = true;
+                        agent.waiting = Some(envelope);
+                    } else {
+                        self.send(&envelope, &Self::observe(mb, owner))?;
+                    }
+                }
+                (Request::Leave, Some(agent)) => {
+                    mb.remove_owner(agent.owner);
+                    if let Some(waiting) = agent.waiting.take() {
+                        let message = "left before the world advanced".to_string();
+                        self.send(&waiting, &Response::Error { message })?;
+                    }
+                    self.agents.remove(&identity);
+                    self.send(&envelope, &Response::Left)?;
+                }
+                (Request::Act { .. } | Request::Leave, None) => {
+                    let message = "not registered".to_string();
+                    self.send(&envelope, &Response::Error { message })?;
+                }
+            }
+        }
+    }
+
+    /// Drop the agents that went silent, and fall back to the default action for those that have
+    /// not acted in time
+    pub fn before_step(&mut self, mb: &mut Microbiome) {
+        // Processes that died without leaving would keep their cells and hold up every step
+        let tick = mb.elapsed();
+        let max_missed_ticks = self.opts.max_missed_ticks;
+        self.agents.retain(|_, agent| {
+            let silent = agent.waiting.is_none()
+                && tick.saturating_sub(agent.last_request) >= max_missed_ticks;
+            if silent {
+                mb.remove_owner(agent.owner);
+            }
+            !silent
+        });
+
+        if self.opts.mode != Mode::Lockstep {
+            return;
+        }
+
+        for agent in self.agents.values().filter(|x| !x.acted) {
+            mb.set_action(agent.owner, self.opts.default_action);
+        }
+    }
+
+    /// Answer the agents waiting on the world to advance
+    pub fn after_step(&mut self, mb: &Microbiome) -> Result<(), Box<dyn Error>> {
+        let mut dead = Vec::new();
+
+        for (identity, agent) in &mut self.agents {
+            let response = Self::observe(mb, agent.owner);
+            let is_dead = matches!(response, Response::Dead { .. });
+
+            // Dead agents find out with their next request, and shouldn't hold up the world until then
+            agent.acted = is_dead;
+            if let Some(envelope) = agent.waiting.take() {
+                let payload = serde_json::to_vec(&response)?;
+                let frames = envelope
+                    .iter()
+                    .map(Vec::as_slice)
+                    .chain([payload.as_slice()]);
+                self.sock.send_multipart(frames, 0)?;
+                if is_dead {
+                    dead.push(identity.clone());
+                }
+            }
+        }
+
+        for identity in dead {
+            self.agents.remove(&identity);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::Config;
+
+    const TIMEOUT_MS: i64 = 1000;
+
+    /// A hub on an endpoint of its own, over an empty biome
+    fn hub(context: &zmq::Context, name: &str, mode: Mode) -> (AgentHub, Microbiome) {
+        let opts = AgentOptions {
+            mode,
+            timeout: Duration::from_secs(1),
+            default_action: Action::default(),
+            max_missed_ticks: 3,
+        };
+        let hub = AgentHub::bind(context, &format!("inproc://agents-{name}"), opts).unwrap();
+        let config = Config {
+            initial_num_npcs: 0,
+            initial_food_supply: 0,
+            food_spawn_rate: 0,
+            ..Config::default()
+        };
+        (hub, Microbiome::with_config(config, 0))
+    }
+
+    /// An agent talking to the hub over a DEALER, which unlike a REQ may send at any time
+    fn agent(context: &zmq::Context, name: &str) -> zmq::Socket {
+        let sock = context.socket(zmq::DEALER).unwrap();
+        sock.connect(&format!("inproc://agents-{name}")).unwrap();
+        sock
+    }
+
+    /// Send a request and let the hub handle it
+    fn send(
+        agent: &zmq::Socket,
+        hub: &mut AgentHub,
+        mb: &mut Microbiome,
+        request: serde_json::Value,
+    ) {
+        agent
+            .send(serde_json::to_vec(&request).unwrap(), 0)
+            .unwrap();
+        assert_eq!(hub.socket().poll(zmq::POLLIN, TIMEOUT_MS).unwrap(), 1);
+        hub.recv(mb).unwrap();
+    }
+
+    /// The next response, if one arrives within `timeout_ms`
+    fn response(agent: &zmq::Socket, timeout_ms: i64) -> Option<Response> {
+        if agent.poll(zmq::POLLIN, timeout_ms).unwrap() == 0 {
+            return None;
+        }
+        Some(serde_json::from_slice(&agent.recv_bytes(0).unwrap()).unwrap())
+    }
+
+    fn register() -> serde_json::Value {
+        json!({"msg": "register", "version": PROTOCOL_VERSION})
+    }
+
+    fn act() -> serde_json::Value {
+        json!({"msg": "act", "action": {"dir": [1.0, 0.0]}})
+    }
+
+    #[test]
+    fn agents_join_act_and_leave() {
+        let context = zmq::Context::new();
+        let (mut hub, mut mb) = hub(&context, "join", Mode::FreeRunning);
+        let agent = agent(&context, "join");
+
+        send(&agent, &mut hub, &mut mb, register());
+        let Some(Response::Observation { agent: owner, .. }) = response(&agent, TIMEOUT_MS) else {
+            panic!("expected an observation");
+        };
+        assert_eq!(mb.cells().filter(|x| x.owner() == Some(owner)).count(), 1);
+
+        send(&agent, &mut hub, &mut mb, register());
+        assert!(matches!(
+            response(&agent, TIMEOUT_MS),
+            Some(Response::Error { .. })
+        ));
+        send(&agent, &mut hub, &mut mb, act());
+        assert!(matches!(
+            response(&agent, TIMEOUT_MS),
+            Some(Response::Observation { .. })
+        ));
+
+        send(&agent, &mut hub, &mut mb, json!({"msg": "leave"}));
+        assert!(matches!(response(&agent, TIMEOUT_MS), Some(Response::Left)));
+        assert_eq!(mb.num_cells(), 0);
+        send(&agent, &mut hub, &mut mb, act());
+        assert!(matches!(
+            response(&agent, TIMEOUT_MS),
+            Some(Response::Error { .. })
+        ));
+    }
+
+    #[test]
+    fn lockstep_answers_once_the_world_advanced() {
+        let context = zmq::Context::new();
+        let (mut hub, mut mb) = hub(&context, "lockstep", Mode::Lockstep);
+        let agent = agent(&context, "lockstep");
+        send(&agent, &mut hub, &mut mb, register());
+        response(&agent, TIMEOUT_MS).unwrap();
+        assert!(hub.waiting_for_actions());
+
+        send(&agent, &mut hub, &mut mb, act());
+        assert!(response(&agent, 50).is_none());
+        assert!(!hub.waiting_for_actions());
+
+        // The held request stays held, the new one is refused
+        send(&agent, &mut hub, &mut mb, act());
+        assert!(matches!(
+            response(&agent, TIMEOUT_MS),
+            Some(Response::Error { .. })
+        ));
+
+        hub.before_step(&mut mb);
+        mb.step();
+        hub.after_step(&mb).unwrap();
+        assert!(matches!(
+            response(&agent, TIMEOUT_MS),
+            Some(Response::Observation { .. })
+        ));
+        assert!(response(&agent, 50).is_none());
+        assert!(hub.waiting_for_actions());
+    }
+
+    #[test]
+    fn silent_agents_are_dropped() {
+        let context = zmq::Context::new();
+        let (mut hub, mut mb) = hub(&context, "silent", Mode::FreeRunning);
+        let agent = agent(&context, "silent");
+        send(&agent, &mut hub, &mut mb, register());
+        response(&agent, TIMEOUT_MS).unwrap();
+
+        for _ in 0..3 {
+            hub.before_step(&mut mb);
+            assert_eq!(mb.num_cells(), 1);
+            mb.step();
+            hub.after_step(&mb).unwrap();
+        }
+        hub.before_step(&mut mb);
+        assert_eq!(mb.num_cells(), 0);
+
+        send(&agent, &mut hub, &mut mb, act());
+        assert!(matches!(
+            response(&agent, TIMEOUT_MS),
+            Some(Response::Error { .. })
+        ));
+    }
+}
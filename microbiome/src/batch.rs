@@ -0,0 +1,388 @@
+//! Headless runs of the microbiome for experiments, without any sockets
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{Config, Event, Microbiome, Stats};
+
+/// Options for a single headless run
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Number of ticks to simulate
+    pub ticks: u64,
+    /// Record stats every this many ticks, or never if 0
+    pub stats_every: u64,
+    /// Whether to record every event
+    pub record_events: bool,
+    /// Stop early once a single cell is left
+    pub until_monopoly: bool,
+}
+
+/// Final state of a run
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub seed: u64,
+    pub ticks: u64,
+    pub npcs: usize,
+    pub food: usize,
+    pub npc_mass: f64,
+    pub max_npc_mass: f64,
+    /// Tick at which a single cell was left, if it happened
+    pub monopoly_tick: Option<u64>,
+}
+
+/// Everything recorded during a run
+#[derive(Debug, Clone)]
+pub struct RunOutput {
+    pub stats: Vec<Stats>,
+    pub events: Vec<Event>,
+    pub summary: Summary,
+}
+
+/// Simulate a microbiome for a fixed number of ticks
+pub fn run(config: Config, seed: u64, opts: &RunOptions) -> RunOutput {
+    let mut mb = Microbiome::with_config(config, seed);
+    let mut stats = Vec::new();
+    let mut events = Vec::new();
+    let mut monopoly_tick = None;
+
+    if opts.stats_every > 0 {
+        stats.push(mb.stats());
+    }
+
+    while mb.elapsed() < opts.ticks {
+        mb.step();
+
+        for event in mb.events() {
+            if let Event::Monopoly { tick, .. } = event {
+                monopoly_tick.get_or_insert(*tick);
+            }
+        }
+        if opts.record_events {
+            events.extend_from_slice(mb.events());
+        }
+        if opts.stats_every > 0 && mb.elapsed().is_multiple_of(opts.stats_every) {
+            stats.push(mb.stats());
+        }
+        if opts.until_monopoly && monopoly_tick.is_some() {
+            break;
+        }
+    }
+
+    let last = mb.stats();
+    RunOutput {
+        stats,
+        events,
+        summary: Summary {
+            seed,
+            ticks: last.tick,
+            npcs: last.npcs,
+            food: last.food,
+            npc_mass: last.npc_mass,
+            max_npc_mass: last.max_npc_mass,
+            monopoly_tick,
+        },
+    }
+}
+
+/// A grid of config parameters and seeds to sweep over
+///
+/// ```toml
+/// seeds = [1, 2, 3]
+///
+/// [params]
+/// eat_diff = [2.0, 5.0, 10.0]
+/// initial_num_npcs = [10, 20]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Grid {
+    pub seeds: Vec<u64>,
+    #[serde(default)]
+    pub params: BTreeMap<String, Vec<Value>>,
+}
+
+impl Grid {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    /// Every combination of parameter values, in a stable order
+    fn combinations(&self) -> Vec<Map<String, Value>> {
+        let mut combos = vec![Map::new()];
+        for (key, values) in &self.params {
+            combos = combos
+                .into_iter()
+                .flat_map(|combo| {
+                    values.iter().map(move |value| {
+                        let mut combo = combo.clone();
+                        combo.insert(key.clone(), value.clone());
+                        combo
+                    })
+                })
+                .collect();
+        }
+        combos
+    }
+}
+
+/// One row of a sweep's results table
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepRow {
+    #[serde(flatten)]
+    pub params: Map<String, Value>,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+/// Run every combination of the grid against a base config in parallel
+///
+/// Fails before running anything if any combination is not a valid config.
+pub fn sweep(
+    base: &Config,
+    grid: &Grid,
+    opts: &RunOptions,
+) -> Result<Vec<SweepRow>, Box<dyn Error>> {
+    let mut trials = Vec::new();
+    for params in grid.combinations() {
+        let mut config = base.clone();
+        let invalid = |e| format!("invalid parameters {}: {e}", Value::Object(params.clone()));
+        for (key, value) in &params {
+            config.set(key, value.clone()).map_err(invalid)?;
+        }
+        config.validate().map_err(invalid)?;
+        for &seed in &grid.seeds {
+            trials.push((params.clone(), config.clone(), seed));
+        }
+    }
+
+    let opts = RunOptions {
+        stats_every: 0,
+        record_events: false,
+        ..opts.clone()
+    };
+
+    Ok(trials
+        .into_par_iter()
+        .map(|(params, config, seed)| SweepRow {
+            params,
+            summary: run(config, seed, &opts).summary,
+        })
+        .collect())
+}
+
+/// Output file formats, inferred from the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("csv") => Ok(Format::Csv),
+            Some("jsonl" | "ndjson") => Ok(Format::Jsonl),
+            _ => Err(format!(
+                "unsupported output format for {}, use .csv or .jsonl",
+                path.display()
+            )
+            .into()),
+        }
+    }
+}
+
+/// Write rows to a CSV or JSONL file, depending on its extension
+///
+/// CSV columns are the union of every row's fields, in order of first appearance.
+pub fn write_table<T: Serialize>(path: &Path, rows: &[T]) -> Result<(), Box<dyn Error>> {
+    let format = Format::from_path(path)?;
+    let mut out = BufWriter::new(File::create(path)?);
+
+    match format {
+        Format::Jsonl => {
+            for row in rows {
+                serde_json::to_writer(&mut out, row)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        Format::Csv => {
+            let rows = rows
+                .iter()
+                .map(|row| match serde_json::to_value(row)? {
+                    Value::Object(map) => Ok(map),
+                    _ => Err("only structs can be written as csv rows".into()),
+                })
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+            let mut headers = Vec::<&str>::new();
+            for key in rows.iter().flat_map(|row| row.keys()) {
+                if !headers.contains(&key.as_str()) {
+                    headers.push(key);
+                }
+            }
+
+            let mut writer = csv::Writer::from_writer(out);
+            writer.write_record(&headers)?;
+            for row in &rows {
+                writer.write_record(headers.iter().map(|key| match row.get(*key) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(s)) => s.clone(),
+                    Some(value) => value.to_string(),
+                }))?;
+            }
+            writer.flush()?;
+            return Ok(());
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn opts(ticks: u64) -> RunOptions {
+        RunOptions {
+            ticks,
+            stats_every: 0,
+            record_events: false,
+            until_monopoly: false,
+        }
+    }
+
+    /// A file in the temp dir for this test only
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("microbiome-batch-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn runs_repeat_with_the_same_seed() {
+        let opts = RunOptions {
+            stats_every: 10,
+            record_events: true,
+            ..opts(50)
+        };
+        let a = run(Config::default(), 3, &opts);
+        let b = run(Config::default(), 3, &opts);
+        let json = |x: &RunOutput| serde_json::to_value(&x.summary).unwrap();
+        assert_eq!(json(&a), json(&b));
+        assert_eq!(a.stats.len(), 6);
+        assert_eq!(
+            serde_json::to_value(&a.stats).unwrap(),
+            serde_json::to_value(&b.stats).unwrap()
+        );
+        assert_eq!(a.events.len(), b.events.len());
+    }
+
+    #[test]
+    fn grids_sweep_every_combination() {
+        let grid: Grid = toml::from_str(
+            r#"
+            seeds = [1, 2]
+
+            [params]
+            eat_diff = [2.0, 5.0]
+            initial_num_npcs = [3, 4, 5]
+            "#,
+        )
+        .unwrap();
+        let combos = grid.combinations();
+        let expected = [(2.0, 3), (2.0, 4), (2.0, 5), (5.0, 3), (5.0, 4), (5.0, 5)]
+            .map(|(eat_diff, npcs)| json!({"eat_diff": eat_diff, "initial_num_npcs": npcs}));
+        assert_eq!(combos.len(), expected.len());
+        for (combo, expected) in combos.iter().zip(&expected) {
+            assert_eq!(&Value::Object(combo.clone()), expected);
+        }
+
+        let rows = sweep(&Config::default(), &grid, &opts(5)).unwrap();
+        assert_eq!(rows.len(), 12);
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(&Value::Object(row.params.clone()), &expected[i / 2]);
+            assert_eq!(row.summary.seed, grid.seeds[i % 2]);
+            assert_eq!(row.summary.ticks, 5);
+        }
+    }
+
+    #[test]
+    fn sweeps_refuse_invalid_grids_up_front() {
+        let grid: Grid = toml::from_str(
+            r#"
+            seeds = [1]
+
+            [params]
+            size = [500.0, 5.0]
+            "#,
+        )
+        .unwrap();
+        let e = sweep(&Config::default(), &grid, &opts(5)).unwrap_err();
+        assert!(e.to_string().contains("size"), "{e}");
+    }
+
+    #[derive(Serialize)]
+    struct Row {
+        name: &'static str,
+        value: Option<f64>,
+        #[serde(flatten)]
+        extra: Map<String, Value>,
+    }
+
+    fn rows() -> Vec<Row> {
+        let mut extra = Map::new();
+        extra.insert("count".to_string(), json!(3));
+        vec![
+            Row {
+                name: "a, b",
+                value: Some(1.5),
+                extra: Map::new(),
+            },
+            Row {
+                name: "c",
+                value: None,
+                extra,
+            },
+        ]
+    }
+
+    #[test]
+    fn tables_are_written_as_csv() {
+        let path = temp_file("table.csv");
+        write_table(&path, &rows()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(text, "name,value,count\n\"a, b\",1.5,\nc,,3\n");
+    }
+
+    #[test]
+    fn tables_are_written_as_jsonl() {
+        let path = temp_file("table.jsonl");
+        write_table(&path, &rows()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                r#"{"name":"a, b","value":1.5}"#,
+                r#"{"name":"c","value":null,"count":3}"#,
+            ]
+        );
+        assert!(write_table(&temp_file("table.txt"), &rows()).is_err());
+    }
+}
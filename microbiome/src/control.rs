@@ -0,0 +1,263 @@
+//! Commands for driving a running microbiome from the outside
+
+use std::time::{Duration, Instant};
+
+use nalgebra::point;
+use protocol::{sim::SimMessage, PROTOCOL_VERSION};
+
+use crate::{Config, Microbiome};
+
+pub use protocol::control::{Command, EntityKind, Reply};
+
+/// How often a simulation reports its metrics
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most ticks a single [`Command::Step`] may advance, so one command can't hold the simulator up
+pub const MAX_STEP_TICKS: u64 = 10_000;
+
+/// A microbiome that can be paused and controlled with [`Command`]s
+#[derive(Debug)]
+pub struct Simulation {
+    mb: Microbiome,
+    config: Config,
+    paused: bool,
+    /// Steps taken and the time they took since metrics were last reported
+    steps: u32,
+    step_time: Duration,
+    last_metrics: Instant,
+}
+
+impl Simulation {
+    pub fn new(mb: Microbiome) -> Self {
+        Self {
+            config: mb.config().clone(),
+            mb,
+            paused: false,
+            steps: 0,
+            step_time: Duration::ZERO,
+            last_metrics: Instant::now(),
+        }
+    }
+
+    pub fn microbiome(&self) -> &Microbiome {
+        &self.mb
+    }
+
+    pub fn microbiome_mut(&mut self) -> &mut Microbiome {
+        &mut self.mb
+    }
+
+    /// Simulate `mb` from now on, keeping the observers of the old one
+    fn replace(&mut self, mut mb: Microbiome) {
+        mb.observers = std::mem::take(&mut self.mb.observers);
+        self.mb = mb;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Advance one tick unless paused
+    ///
+    /// **Returns** whether the microbiome stepped
+    pub fn tick(&mut self) -> bool {
+        if !self.paused {
+            let start = Instant::now();
+            self.mb.step();
+            self.step_time += start.elapsed();
+            self.steps += 1;
+        }
+        !self.paused
+    }
+
+    /// **Returns** the population and average step time, once every [`METRICS_INTERVAL`]
+    pub fn metrics(&mut self) -> Option<SimMessage> {
+        if self.last_metrics.elapsed() < METRICS_INTERVAL {
+            return None;
+        }
+        let tick_seconds = if self.steps > 0 {
+            self.step_time.as_secs_f64() / self.steps as f64
+        } else {
+            0.0
+        };
+        self.steps = 0;
+        self.step_time = Duration::ZERO;
+        self.last_metrics = Instant::now();
+        Some(SimMessage::Metrics {
+            stats: self.mb.stats(),
+            tick_seconds,
+        })
+    }
+
+    fn status(&self) -> Reply {
+        Reply::Status {
+            tick: self.mb.elapsed(),
+            paused: self.paused,
+        }
+    }
+
+    fn owner_status(&self, owner: u64, found: bool) -> Reply {
+        if found {
+            self.status()
+        } else {
+            Reply::error(format!("no cells left for owner {owner}"))
+        }
+    }
+
+    pub fn handle(&mut self, cmd: Command) -> Reply {
+        match cmd {
+            Command::Hello { version } if version != PROTOCOL_VERSION => Reply::error(format!(
+                "protocol version {version} is not supported, expected {PROTOCOL_VERSION}"
+            )),
+            Command::Hello { .. } => Reply::Hello {
+                version: PROTOCOL_VERSION,
+            },
+            Command::Pause => {
+                self.paused = true;
+                self.status()
+            }
+            Command::Resume => {
+                self.paused = false;
+                self.status()
+            }
+            Command::Step { ticks } if ticks > MAX_STEP_TICKS => Reply::error(format!(
+                "can't step more than {MAX_STEP_TICKS} ticks at once, asked for {ticks}"
+            )),
+            Command::Step { ticks } => {
+                for _ in 0..ticks {
+                    self.mb.step();
+                }
+                self.status()
+            }
+            Command::Reset { seed } => {
+                if let Err(e) = self.config.validate() {
+                    return Reply::error(e);
+                }
+                let seed = seed.unwrap_or_else(rand::random);
+                self.replace(Microbiome::with_config(self.config.clone(), seed));
+                self.status()
+            }
+            Command::Spawn { entity, pos, mass } => {
+                let pos = pos.map(|[x, y]| point![x, y]);
+                let spawned = match entity {
+                    EntityKind::Npc => self.mb.spawn_npc(pos, mass),
+                    EntityKind::Food => self.mb.spawn_food(pos, mass),
+                };
+                match spawned {
+                    Ok(id) => Reply::Spawned { id },
+                    Err(e) => Reply::error(e),
+                }
+            }
+            Command::Remove { id } => {
+                if self.mb.remove(id) {
+                    self.status()
+                } else {
+                    Reply::error(format!("no entity with id {id}"))
+                }
+            }
+            Command::GetStats => Reply::Stats {
+                stats: self.mb.stats(),
+            },
+            Command::GetConfig => Reply::Config {
+                config: self.config.clone(),
+            },
+            Command::SetConfig { values } => {
+                let mut config = self.config.clone();
+                for (key, value) in values {
+                    if let Err(e) = config.set(&key, value) {
+                        return Reply::error(e);
+                    }
+                }
+                self.mb.set_config(config.clone());
+                self.config = config;
+                Reply::Config {
+                    config: self.config.clone(),
+                }
+            }
+            Command::TakeSnapshot => Reply::Snapshot {
+                snapshot: Box::new(self.mb.snapshot()),
+            },
+            Command::LoadSnapshot { snapshot } => {
+                if let Err(e) = snapshot.config.validate() {
+                    return Reply::error(e);
+                }
+                self.replace(Microbiome::from_snapshot(*snapshot));
+                self.config = self.mb.config().clone();
+                self.status()
+            }
+            Command::Join { name } => Reply::Spawned {
+                id: self.mb.spawn_controlled(Some(name)),
+            },
+            Command::Steer { owner, action } => {
+                let found = self.mb.set_action(owner, action);
+                self.owner_status(owner, found)
+            }
+            Command::Split { owner } => {
+                let found = self.mb.split(owner);
+                self.owner_status(owner, found)
+            }
+            Command::Eject { owner } => {
+                let found = self.mb.eject(owner);
+                self.owner_status(owner, found)
+            }
+            Command::Leave { owner } => {
+                let found = self.mb.remove_owner(owner);
+                self.owner_status(owner, found)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::invariants::SIZE;
+
+    fn simulation() -> Simulation {
+        Simulation::new(Microbiome::with_config(Config::default(), 0))
+    }
+
+    #[test]
+    fn invalid_configs_are_refused() {
+        let mut sim = simulation();
+        let values = [
+            ("size", json!(5.0)),
+            ("base_speed", json!(-1.0)),
+            ("cell_perception_radius", json!(-10.0)),
+            ("initial_num_npcs", json!(10_000_000)),
+            ("eat_diff", json!("a lot")),
+        ];
+        for (key, value) in values {
+            let cmd = Command::SetConfig {
+                values: [(key.to_string(), value)].into_iter().collect(),
+            };
+            let reply = sim.handle(cmd);
+            assert!(matches!(reply, Reply::Error { .. }), "{key}: {reply:?}");
+        }
+        assert_eq!(sim.config, Config::default());
+
+        // Still steps and resets with the config it had
+        let reply = sim.handle(Command::Step { ticks: 3 });
+        assert!(matches!(reply, Reply::Status { tick: 3, .. }), "{reply:?}");
+        let reply = sim.handle(Command::Reset { seed: Some(1) });
+        assert!(matches!(reply, Reply::Status { tick: 0, .. }), "{reply:?}");
+        sim.handle(Command::Step { ticks: 3 });
+        assert_eq!(sim.microbiome().elapsed(), 3);
+    }
+
+    #[test]
+    fn invalid_snapshots_are_refused() {
+        let mut sim = simulation();
+        sim.handle(Command::Step { ticks: 2 });
+        let mut snapshot = sim.microbiome().snapshot();
+        snapshot.config.size = 5.0;
+        let reply = sim.handle(Command::LoadSnapshot {
+            snapshot: Box::new(snapshot),
+        });
+        assert!(matches!(reply, Reply::Error { .. }), "{reply:?}");
+        assert_eq!(sim.microbiome().elapsed(), 2);
+        assert_eq!(sim.microbiome().config().size, SIZE);
+    }
+}
@@ -1,28 +1,30 @@
+use protocol::FoodState;
 use quadtree::Point;
-use serde::{ser::SerializeStruct, Serialize};
+use rand::Rng;
 
 use crate::{invariants::radius, util::random_cell, P2};
 
 #[derive(Debug, Clone)]
 pub struct Food {
+    pub id: u64,
     pub pos: P2,
     pub mass: f64,
     pub color: String,
 }
 
-impl Default for Food {
-    fn default() -> Self {
-        let (pos, mass, color) = random_cell(1..=3);
-        Self { pos, mass, color }
-    }
-}
-
 impl Food {
-    fn new() -> Self {
-        Self::default()
+    /// Spawn food at a random position within a biome of the given size
+    pub fn new(id: u64, rng: &mut impl Rng, size: f64) -> Self {
+        let (pos, mass, color) = random_cell(rng, size, 1..=3);
+        Self {
+            id,
+            pos,
+            mass,
+            color,
+        }
     }
 
-    fn radius(&self) -> f64 {
+    pub fn radius(&self) -> f64 {
         radius(self.mass)
     }
 }
@@ -33,16 +35,25 @@ impl Point for Food {
     }
 }
 
-impl Serialize for Food {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut state = serializer.serialize_struct("Food", 4)?;
-        state.serialize_field("pos", &self.pos)?;
-        state.serialize_field("radius", &self.radius())?;
-        state.serialize_field("mass", &self.mass)?;
-        state.serialize_field("color", &self.color)?;
-        state.end()
+impl From<&Food> for FoodState {
+    fn from(food: &Food) -> Self {
+        Self {
+            id: food.id,
+            pos: food.pos.into(),
+            mass: food.mass,
+            radius: food.radius(),
+            color: food.color.clone(),
+        }
+    }
+}
+
+impl From<FoodState> for Food {
+    fn from(state: FoodState) -> Self {
+        Self {
+            id: state.id,
+            pos: state.pos.into(),
+            mass: state.mass,
+            color: state.color,
+        }
     }
 }
@@ -2,4 +2,4 @@ mod food;
 mod npc;
 
 pub use food::Food;
-pub use npc::NPC;
+pub use npc::{NPCKind, NPC};
@@ -1,71 +1,119 @@
 use nalgebra::{self as na, vector};
+use protocol::{NpcKind, NpcState};
 use quadtree::Point;
-use serde::{ser::SerializeStruct, Serialize};
+use rand::Rng;
 
 use crate::{
-    invariants::{radius, speed, EAT_DIFF, SIZE},
+    action::direction,
+    invariants::{radius, speed, NPC_SPAWN_MASS},
     util::{random_cell, restrict_cell_to_bounds, WeightedPoint},
-    Frame, P2, V2,
+    Action, Config, Frame, P2, V2,
 };
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub enum NPCKind {
-    Linear { dir: V2 },
-    Advanced { dir: V2 },
+    Linear {
+        dir: V2,
+    },
+    Advanced {
+        dir: V2,
+    },
+    /// Moved by an agent or player outside the simulator
+    Controlled {
+        owner: u64,
+        name: Option<String>,
+        action: Action,
+    },
 }
 
 #[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct NPC {
+    pub id: u64,
     pub pos: P2,
     pub mass: f64,
     pub color: String,
     pub kind: NPCKind,
 }
 
-impl Default for NPC {
-    fn default() -> Self {
-        let (pos, mass, color) = random_cell(20..=30);
+impl NPC {
+    /// Spawn an NPC at a random position within a biome of the given size
+    pub fn new(id: u64, rng: &mut impl Rng, size: f64) -> Self {
+        let (pos, mass, color) = random_cell(rng, size, NPC_SPAWN_MASS);
+        let dir = V2::new(rng.gen(), rng.gen()) - vector![0.5, 0.5];
         Self {
+            id,
             pos,
             mass,
             color,
             // kind: NPCKind::Linear {
-            //     dir: (V2::new_random() - vector![0.5, 0.5]).normalize(),
+            //     dir: dir.normalize(),
             // },
             kind: NPCKind::Advanced {
-                dir: (V2::new_random() - vector![0.5, 0.5]).normalize(),
+                dir: dir.normalize(),
             },
         }
     }
-}
 
-impl NPC {
-    pub fn new() -> Self {
-        Self::default()
+    /// Spawn a cell controlled from outside the simulator
+    pub fn controlled(
+        id: u64,
+        owner: u64,
+        name: Option<String>,
+        rng: &mut impl Rng,
+        size: f64,
+    ) -> Self {
+        Self {
+            kind: NPCKind::Controlled {
+                owner,
+                name,
+                action: Action::default(),
+            },
+            ..Self::new(id, rng, size)
+        }
+    }
+
+    /// Owner of the cell, if it is controlled from outside the simulator
+    pub fn owner(&self) -> Option<u64> {
+        match self.kind {
+            NPCKind::Controlled { owner, .. } => Some(owner),
+            _ => None,
+        }
+    }
+
+    /// Direction the cell is heading in, for placing split off cells and ejected mass
+    pub fn heading(&self) -> V2 {
+        let dir = match &self.kind {
+            NPCKind::Linear { dir } | NPCKind::Advanced { dir } => *dir,
+            NPCKind::Controlled { action, .. } => direction(action, self.pos),
+        };
+        dir.try_normalize(f64::EPSILON).unwrap_or_else(V2::x)
     }
 
     pub fn radius(&self) -> f64 {
         radius(self.mass)
     }
 
-    pub fn speed(&self) -> f64 {
-        speed(self.mass)
+    pub fn speed(&self, base_speed: f64) -> f64 {
+        speed(self.mass, base_speed)
     }
 
-    pub fn step(&mut self, frame: &Frame) {
-        let speed = self.speed();
+    pub fn step(&mut self, frame: &Frame, config: &Config) {
+        let speed = self.speed(config.base_speed);
+        let size = config.size;
         let limit = self.radius() * 0.9;
         let dir = match &mut self.kind {
             NPCKind::Linear { dir } => {
                 let next_pos = self.pos + *dir * speed;
                 if next_pos.x <= limit {
                     dir.x = dir.x.abs();
-                } else if next_pos.x >= SIZE - limit {
+                } else if next_pos.x >= size - limit {
                     dir.x = -dir.x.abs();
                 }
                 if next_pos.y <= limit {
                     dir.y = dir.y.abs();
-                } else if next_pos.y >= SIZE - limit {
+                } else if next_pos.y >= size - limit {
                     dir.y = -dir.y.abs();
                 }
                 *dir
@@ -74,23 +122,23 @@ impl NPC {
                 let mut prey = &WeightedPoint::new(P2::origin(), 0.0);
                 let mut predators = Vec::with_capacity(frame.npcs.len());
                 for npc in &frame.npcs {
-                    if self.mass > npc.mass + EAT_DIFF {
+                    if self.mass > npc.mass + config.eat_diff {
                         if npc.mass > prey.mass {
                             prey = npc;
                         }
-                    } else if self.mass < npc.mass - EAT_DIFF {
+                    } else if self.mass < npc.mass - config.eat_diff {
                         predators.push(npc);
                     }
                 }
 
-                if predators.len() > 0 {
+                if !predators.is_empty() {
                     // Find the unweighted center of all predators in the vicinity and run away from that
                     let sum = predators.into_iter().map(|x| x.pos - self.pos).sum::<V2>();
                     *dir = -sum.normalize();
                 } else if prey.mass > 0.0 {
                     // Find the largest prey and chase him
                     *dir = (prey.pos - self.pos).normalize()
-                } else if frame.food.len() > 0 {
+                } else if !frame.food.is_empty() {
                     let mut food = &frame.food[0];
                     let mut min_dist = f64::MAX;
                     for f in &frame.food {
@@ -108,10 +156,11 @@ impl NPC {
 
                 *dir
             }
+            NPCKind::Controlled { action, .. } => direction(action, self.pos),
         };
 
         self.pos += dir * speed;
-        self.pos = restrict_cell_to_bounds(self.pos, self.radius());
+        self.pos = restrict_cell_to_bounds(self.pos, self.radius(), size);
     }
 }
 
@@ -121,17 +170,53 @@ impl Point for NPC {
     }
 }
 
-impl Serialize for NPC {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut state = serializer.serialize_struct("NPC", 5)?;
-        state.serialize_field("pos", &self.pos)?;
-        state.serialize_field("radius", &self.radius())?;
-        state.serialize_field("mass", &self.mass)?;
-        state.serialize_field("pos", &self.pos)?;
-        state.serialize_field("color", &self.color)?;
-        state.end()
+impl From<&NPC> for NpcState {
+    fn from(npc: &NPC) -> Self {
+        let kind = match &npc.kind {
+            NPCKind::Linear { dir } => NpcKind::Linear { dir: (*dir).into() },
+            NPCKind::Advanced { dir } => NpcKind::Advanced { dir: (*dir).into() },
+            NPCKind::Controlled {
+                owner,
+                name,
+                action,
+            } => NpcKind::Controlled {
+                owner: *owner,
+                name: name.clone(),
+                action: *action,
+            },
+        };
+        Self {
+            id: npc.id,
+            pos: npc.pos.into(),
+            mass: npc.mass,
+            radius: npc.radius(),
+            color: npc.color.clone(),
+            kind,
+        }
+    }
+}
+
+impl From<NpcState> for NPC {
+    fn from(state: NpcState) -> Self {
+        let kind = match state.kind {
+            NpcKind::Linear { dir } => NPCKind::Linear { dir: dir.into() },
+            NpcKind::Advanced { dir } => NPCKind::Advanced { dir: dir.into() },
+            NpcKind::Controlled {
+                owner,
+                name,
+                action,
+            } => NPCKind::Controlled {
+                owner,
+                name,
+                action,
+            },
+        };
+        Self {
+            id: state.id,
+            pos: state.pos.into(),
+            mass: state.mass,
+            color: state.color,
+            kind,
+        }
     }
 }
@@ -1,17 +1,8 @@
-/// Size of the biome
-pub const SIZE: f64 = 500.0;
-
-/// Base speed of cells, decreases with mass
-pub const BASE_SPEED: f64 = 4.0;
-
-/// Perception radius of cells for other cells
-pub const CELL_PERCEPTION_RADIUS: f64 = 200.0;
-
-/// Perception radius of cells for food
-pub const FOOD_PERCEPTION_RADIUS: f64 = 50.0;
-
-/// How much bigger a cell must be to eat another
-pub const EAT_DIFF: f64 = 5.0;
+/// Defaults of the [`Config`](crate::Config), which can be changed at runtime
+pub use protocol::config::{
+    BASE_SPEED, CELL_PERCEPTION_RADIUS, EAT_DIFF, FOOD_PERCEPTION_RADIUS, FOOD_SPAWN_RATE,
+    INITIAL_FOOD_SUPPLY, INITIAL_NUM_NPCS, NPC_SPAWN_MASS, SIZE,
+};
 
 /// Max frame rate to send through pub socket
 pub const FPS: u64 = 30;
@@ -19,14 +10,14 @@ pub const FPS: u64 = 30;
 /// Frame duration
 pub const FRAME_DURATION: u64 = 1000 / FPS;
 
-/// Number of NPCs spawned initially
-pub const INITIAL_NUM_NPCS: usize = 10;
+/// Minimum mass of a controlled cell to split in two
+pub const MIN_SPLIT_MASS: f64 = 40.0;
 
-/// Number of food cells to start with
-pub const INITIAL_FOOD_SUPPLY: usize = 20;
+/// Mass a controlled cell ejects at once
+pub const EJECT_MASS: f64 = 3.0;
 
-/// How much food is spawned per second
-pub const FOOD_SPAWN_RATE: u64 = 10;
+/// Minimum mass of a controlled cell to eject mass
+pub const MIN_EJECT_MASS: f64 = 20.0;
 
 /// Base mass decay rate, increases with mass
 pub const BASE_MASS_DECAY_RATE: f64 = 1.0;
@@ -36,12 +27,12 @@ pub fn radius(mass: f64) -> f64 {
     mass.sqrt()
 }
 
-/// Calculate speed from mass
-pub fn speed(mass: f64) -> f64 {
-    BASE_SPEED / ((mass / 50.0).sqrt() + 1.0)
+/// Calculate speed from mass, given the speed of a massless cell
+pub fn speed(mass: f64, base_speed: f64) -> f64 {
+    base_speed / ((mass / 50.0).sqrt() + 1.0)
 }
 
 /// Calculate mass decay from mass
-pub fn mass_decay(mass: f64) -> f64 {
+pub fn mass_decay(_mass: f64) -> f64 {
     todo!()
 }
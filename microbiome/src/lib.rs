@@ -1,23 +1,37 @@
-use entities::{Food, NPC};
-use invariants::{
-    CELL_PERCEPTION_RADIUS, EAT_DIFF, FOOD_PERCEPTION_RADIUS, FOOD_SPAWN_RATE, FPS,
-    INITIAL_FOOD_SUPPLY, INITIAL_NUM_NPCS, SIZE,
-};
+use entities::{Food, NPCKind, NPC};
+use invariants::{EJECT_MASS, FPS, MIN_EJECT_MASS, MIN_SPLIT_MASS};
 use nalgebra::{point, Point2, Vector2};
+use observe::Observers;
+use protocol::{FoodState, NpcState};
 use quadtree::{
     shapes::{Circle, Rect, Shape},
     QuadTree,
 };
-use serde::Serialize;
-use util::{QTIndexMassItem, WeightedPoint};
+use query::{CellView, EntityView, FoodView};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use snapshot::{rng_from_state, rng_state};
+use util::{restrict_cell_to_bounds, QTIndexMassItem, WeightedPoint};
 
+mod action;
+pub mod agents;
+pub mod batch;
+pub mod control;
 mod entities;
 pub mod invariants;
+pub mod observe;
+pub mod query;
+mod snapshot;
 mod util;
 
+pub use protocol::{Action, Body, Config, Event, Observation, Snapshot, Stats, WorldState};
+
 type P2 = Point2<f64>;
 type V2 = Vector2<f64>;
 
+/// Cells pinned to the same spot against a wall would make a capacity of 1 subdivide forever
+const NPC_INDEX_CAPACITY: usize = 4;
+
 /// A frame of the microbiome perceived by a cell
 #[derive(Debug, Clone)]
 pub struct Frame {
@@ -31,125 +45,562 @@ impl Frame {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct Microbiome {
     // agent: Cell,
+    config: Config,
+    rng: ChaCha8Rng,
+    next_id: u64,
+    events: Vec<Event>,
     boundary: Rect,
+    /// Heaviest first, as they take their steps
     npcs: Vec<NPC>,
+    /// Where each of `npcs` is, by index
+    npc_index: QuadTree<QTIndexMassItem>,
     food: QuadTree<Food>,
     elapsed: u64,
+    observers: Observers,
+}
+
+impl Default for Microbiome {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Microbiome {
+    /// Create a microbiome with the default config and a random seed
     pub fn new() -> Self {
-        let boundary = Rect::new(point![0.0, 0.0], point![SIZE, SIZE]);
-        let mut food = QuadTree::new(boundary, 10);
-        for _ in 0..INITIAL_FOOD_SUPPLY {
-            food.insert(&Food::default());
-        }
+        Self::with_config(Config::default(), rand::random())
+    }
 
-        Self {
+    /// Create a microbiome with the given config, deterministically seeded
+    pub fn with_config(config: Config, seed: u64) -> Self {
+        let boundary = Rect::new(point![0.0, 0.0], point![config.size, config.size]);
+        let mut mb = Self {
             // agent: Cell::default(),
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            next_id: 0,
+            events: Vec::new(),
             boundary,
-            npcs: std::iter::repeat_with(NPC::default)
-                .take(INITIAL_NUM_NPCS)
-                .collect(),
-            food,
+            npcs: Vec::with_capacity(config.initial_num_npcs),
+            npc_index: QuadTree::new(boundary, NPC_INDEX_CAPACITY),
+            food: QuadTree::new(boundary, 10),
             elapsed: 0,
+            observers: Observers::default(),
+            config,
+        };
+
+        for _ in 0..mb.config.initial_food_supply {
+            let id = mb.next_id();
+            let food = Food::new(id, &mut mb.rng, mb.config.size);
+            mb.food.insert(&food);
+        }
+        for _ in 0..mb.config.initial_num_npcs {
+            let id = mb.next_id();
+            let npc = NPC::new(id, &mut mb.rng, mb.config.size);
+            mb.npcs.push(npc);
         }
+        mb.index_npcs();
+
+        mb
     }
 
-    fn get_perceived_frame<'a>(&'a self, pos: P2) -> Frame {
-        let area = Circle::new(pos, CELL_PERCEPTION_RADIUS);
-        let food = self
+    /// Restore a microbiome exactly as it was when the snapshot was taken
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let size = snapshot.config.size;
+        let boundary = Rect::new(point![0.0, 0.0], point![size, size]);
+        let mut food = QuadTree::new(boundary, 10);
+        let snapshot_food = snapshot
             .food
-            .query_ref(&area)
             .into_iter()
-            .map(|x| WeightedPoint::new(x.pos, x.mass))
-            .collect();
-        let npcs = self
-            .npcs
-            .iter()
-            .filter(|x| area.contains(&x.pos))
-            .map(|x| WeightedPoint::new(x.pos, x.mass))
-            .collect(); // O(n) is fine for now because there are not very many NPCs
-        Frame::new(npcs, food)
+            .map(Food::from)
+            .collect::<Vec<_>>();
+        food.insert_many(&snapshot_food);
+
+        let mut mb = Self {
+            config: snapshot.config,
+            rng: rng_from_state(&snapshot.rng),
+            next_id: snapshot.next_id,
+            events: Vec::new(),
+            boundary,
+            npcs: snapshot.npcs.into_iter().map(NPC::from).collect(),
+            npc_index: QuadTree::new(boundary, NPC_INDEX_CAPACITY),
+            food,
+            elapsed: snapshot.elapsed,
+            observers: Observers::default(),
+        };
+        mb.index_npcs();
+        mb
     }
 
-    pub fn step(&mut self) {
-        // Spawn food
-        if self.elapsed % (FPS / FOOD_SPAWN_RATE) == 0 {
-            let food = Food::default();
-            self.food.insert(&food);
+    /// Capture the complete state of the microbiome
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            config: self.config.clone(),
+            rng: rng_state(&self.rng),
+            next_id: self.next_id,
+            elapsed: self.elapsed,
+            npcs: self.npcs.iter().map(NpcState::from).collect(),
+            food: self
+                .food
+                .query_ref(&self.boundary)
+                .into_iter()
+                .map(FoodState::from)
+                .collect(),
         }
+    }
 
-        // ---- Update NPCs ----
-        self.npcs
-            .sort_unstable_by(|a, b| b.mass.partial_cmp(&a.mass).unwrap());
+    /// What viewers need to draw the biome as it is now
+    pub fn state(&self) -> WorldState {
+        WorldState {
+            tick: self.elapsed,
+            size: self.config.size,
+            cell_perception_radius: self.config.cell_perception_radius,
+            food_perception_radius: self.config.food_perception_radius,
+            npcs: self.npcs.iter().map(NpcState::from).collect(),
+            food: self
+                .food
+                .query_ref(&self.boundary)
+                .into_iter()
+                .map(FoodState::from)
+                .collect(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-        let mut npc_qt = QuadTree::new(self.boundary, 1);
+    /// Change the config of a running microbiome
+    ///
+    /// The size of the biome is fixed once created, so it is left as is.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = Config {
+            size: self.config.size,
+            ..config
+        };
+    }
+
+    /// Number of steps taken so far
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    /// Events that occurred during the most recent step
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Current population and mass metrics
+    pub fn stats(&self) -> Stats {
+        let food = self.food.query_ref(&self.boundary);
+        Stats {
+            tick: self.elapsed,
+            npcs: self.npcs.len(),
+            food: food.len(),
+            npc_mass: self.npcs.iter().map(|x| x.mass).sum(),
+            max_npc_mass: self.npcs.iter().map(|x| x.mass).fold(0.0, f64::max),
+            food_mass: food.into_iter().map(|x| x.mass).sum(),
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Order the cells heaviest first and index where they are, after any were added, removed,
+    /// moved or changed mass
+    fn index_npcs(&mut self) {
+        self.npcs
+            .sort_unstable_by(|a, b| b.mass.partial_cmp(&a.mass).unwrap().then(a.id.cmp(&b.id)));
+        self.npc_index = QuadTree::new(self.boundary, NPC_INDEX_CAPACITY);
         let npc_ixs_masses = self
             .npcs
             .iter()
             .enumerate()
             .map(|(i, x)| QTIndexMassItem::new(x.pos, x.mass, i))
             .collect::<Vec<_>>();
-        npc_qt.insert_many(&npc_ixs_masses);
+        self.npc_index.insert_many(&npc_ixs_masses);
+    }
 
-        let frames = self
+    fn check_bounds(&self, pos: Option<P2>) -> Result<(), String> {
+        match pos {
+            Some(pos) if !self.boundary.contains(&pos) => Err(format!(
+                "position ({}, {}) is outside of the biome",
+                pos.x, pos.y
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_mass(mass: Option<f64>) -> Result<(), String> {
+        match mass {
+            Some(mass) if !(mass.is_finite() && mass > 0.0) => {
+                Err(format!("mass {mass} is not a positive number"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Tell the observers about an entity about to be added
+    fn spawned(&mut self, entity: EntityView<'_>, parent: Option<u64>) {
+        let tick = self.elapsed;
+        self.observers.each(|x| x.on_spawn(tick, entity, parent));
+    }
+
+    /// Tell the observers about a cell taken out from outside of a step
+    fn removed(&mut self, npc: &NPC) {
+        let tick = self.elapsed;
+        self.observers
+            .each(|x| x.on_death(tick, CellView::new(npc), None));
+    }
+
+    /// Spawn an NPC, at a random position and mass unless given
+    ///
+    /// **Returns** the id of the new NPC
+    pub fn spawn_npc(&mut self, pos: Option<P2>, mass: Option<f64>) -> Result<u64, String> {
+        self.check_bounds(pos)?;
+        Self::check_mass(mass)?;
+        let id = self.next_id();
+        let mut npc = NPC::new(id, &mut self.rng, self.config.size);
+        npc.pos = pos.unwrap_or(npc.pos);
+        npc.mass = mass.unwrap_or(npc.mass);
+        self.spawned(EntityView::Cell(CellView::new(&npc)), None);
+        self.npcs.push(npc);
+        self.index_npcs();
+        Ok(id)
+    }
+
+    /// Spawn a piece of food, at a random position and mass unless given
+    ///
+    /// **Returns** the id of the new food
+    pub fn spawn_food(&mut self, pos: Option<P2>, mass: Option<f64>) -> Result<u64, String> {
+        self.check_bounds(pos)?;
+        Self::check_mass(mass)?;
+        let id = self.next_id();
+        let mut food = Food::new(id, &mut self.rng, self.config.size);
+        food.pos = pos.unwrap_or(food.pos);
+        food.mass = mass.unwrap_or(food.mass);
+        self.spawned(EntityView::Food(FoodView::new(&food)), None);
+        self.food.insert(&food);
+        Ok(id)
+    }
+
+    /// Remove an NPC or piece of food by id
+    ///
+    /// **Returns** whether anything was removed
+    pub fn remove(&mut self, id: u64) -> bool {
+        if let Some(i) = self.npcs.iter().position(|x| x.id == id) {
+            let npc = self.npcs.remove(i);
+            self.removed(&npc);
+            self.index_npcs();
+            return true;
+        }
+        self.food.delete_filter(&self.boundary, |x| x.id == id) > 0
+    }
+
+    /// Spawn a cell controlled from outside the simulator
+    ///
+    /// **Returns** the owner id, used to steer, observe and remove the owner's cells
+    pub fn spawn_controlled(&mut self, name: Option<String>) -> u64 {
+        let id = self.next_id();
+        let cell = NPC::controlled(id, id, name, &mut self.rng, self.config.size);
+        self.spawned(EntityView::Cell(CellView::new(&cell)), None);
+        self.npcs.push(cell);
+        self.index_npcs();
+        id
+    }
+
+    /// Set the action of every cell of an owner
+    ///
+    /// **Returns** whether the owner has any cells left
+    pub fn set_action(&mut self, owner: u64, new_action: Action) -> bool {
+        let mut found = false;
+        for npc in &mut self.npcs {
+            if let NPCKind::Controlled {
+                owner: o, action, ..
+            } = &mut npc.kind
+            {
+                if *o == owner {
+                    *action = new_action;
+                    found = true;
+                }
+            }
+        }
+        found
+    }
+
+    /// Split every cell of an owner that is big enough into two halves
+    ///
+    /// **Returns** whether the owner has any cells left
+    pub fn split(&mut self, owner: u64) -> bool {
+        let mut found = false;
+        let mut halves = Vec::new();
+        for npc in self.npcs.iter_mut().filter(|x| x.owner() == Some(owner)) {
+            found = true;
+            if npc.mass < MIN_SPLIT_MASS {
+                continue;
+            }
+
+            npc.mass /= 2.0;
+            let mut half = npc.clone();
+            let pos = npc.pos + npc.heading() * npc.radius() * 2.0;
+            half.pos = restrict_cell_to_bounds(pos, half.radius(), self.config.size);
+            halves.push(half);
+        }
+
+        for mut half in halves {
+            // Until now the half has the id of the cell it split off from
+            let parent = half.id;
+            half.id = self.next_id();
+            self.spawned(EntityView::Cell(CellView::new(&half)), Some(parent));
+            self.npcs.push(half);
+        }
+        self.index_npcs();
+        found
+    }
+
+    /// Eject a bit of mass as food ahead of every cell of an owner that is big enough
+    ///
+    /// **Returns** whether the owner has any cells left
+    pub fn eject(&mut self, owner: u64) -> bool {
+        let mut found = false;
+        let mut ejected = Vec::new();
+        for npc in self.npcs.iter_mut().filter(|x| x.owner() == Some(owner)) {
+            found = true;
+            if npc.mass < MIN_EJECT_MASS {
+                continue;
+            }
+
+            npc.mass -= EJECT_MASS;
+            let distance = npc.radius() + invariants::radius(EJECT_MASS) + 1.0;
+            let pos = npc.pos + npc.heading() * distance;
+            ejected.push((npc.id, pos, npc.color.clone()));
+        }
+
+        for (parent, pos, color) in ejected {
+            let food = Food {
+                id: self.next_id(),
+                pos: restrict_cell_to_bounds(pos, 0.0, self.config.size),
+                mass: EJECT_MASS,
+                color,
+            };
+            self.spawned(EntityView::Food(FoodView::new(&food)), Some(parent));
+            self.food.insert(&food);
+        }
+        self.index_npcs();
+        found
+    }
+
+    /// Remove every cell of an owner
+    ///
+    /// **Returns** whether anything was removed
+    pub fn remove_owner(&mut self, owner: u64) -> bool {
+        let (removed, kept): (Vec<_>, _) = std::mem::take(&mut self.npcs)
+            .into_iter()
+            .partition(|x| x.owner() == Some(owner));
+        self.npcs = kept;
+        for npc in &removed {
+            self.removed(npc);
+        }
+        self.index_npcs();
+        !removed.is_empty()
+    }
+
+    /// What an owner's cells perceive, or `None` if it has no cells left
+    pub fn observe(&self, owner: u64) -> Option<Observation> {
+        let body = |id, pos: P2, mass| Body {
+            id,
+            pos: pos.into(),
+            mass,
+            radius: invariants::radius(mass),
+        };
+
+        let mut cells = self
             .npcs
             .iter()
-            .map(|x| self.get_perceived_frame(x.pos))
+            .filter(|x| x.owner() == Some(owner))
+            .map(|x| body(x.id, x.pos, x.mass))
             .collect::<Vec<_>>();
+        cells.sort_unstable_by(|a, b| b.mass.total_cmp(&a.mass).then(a.id.cmp(&b.id)));
+        let center = point![cells.first()?.pos[0], cells[0].pos[1]];
 
-        let mut frames = Vec::with_capacity(self.npcs.len());
-        for npc in &self.npcs {
-            let food_area = Circle::new(npc.pos, FOOD_PERCEPTION_RADIUS);
-            let food = self
-                .food
-                .query_ref(&food_area)
-                .into_iter()
-                .map(|x| WeightedPoint::new(x.pos, x.mass))
-                .collect();
-            let npc_area = Circle::new(npc.pos, CELL_PERCEPTION_RADIUS);
-            let npcs = npc_qt
-                .query_ref(&npc_area)
-                .into_iter()
-                .map(|x| WeightedPoint::new(x.pos, x.mass))
-                .collect();
-            frames.push(Frame::new(npcs, food))
+        let cell_area = Circle::new(center, self.config.cell_perception_radius);
+        let npcs = self
+            .npcs
+            .iter()
+            .filter(|x| x.owner() != Some(owner) && cell_area.contains(&x.pos))
+            .map(|x| body(x.id, x.pos, x.mass))
+            .collect(); // O(n) is fine for now because there are not very many NPCs
+
+        let food_area = Circle::new(center, self.config.food_perception_radius);
+        let mut food = self
+            .food
+            .query_ref(&food_area)
+            .into_iter()
+            .map(|x| body(x.id, x.pos, x.mass))
+            .collect::<Vec<_>>();
+        food.sort_unstable_by_key(|x| x.id);
+
+        Some(Observation {
+            tick: self.elapsed,
+            cells,
+            npcs,
+            food,
+        })
+    }
+
+    fn get_perceived_frame(&self, pos: P2, npc_qt: &QuadTree<QTIndexMassItem>) -> Frame {
+        let food_area = Circle::new(pos, self.config.food_perception_radius);
+        let mut food = self.food.query_ref(&food_area);
+        // The layout of the quadtree depends on its history, so order by id to stay reproducible
+        food.sort_unstable_by_key(|x| x.id);
+        let food = food
+            .into_iter()
+            .map(|x| WeightedPoint::new(x.pos, x.mass))
+            .collect();
+        let npc_area = Circle::new(pos, self.config.cell_perception_radius);
+        let npcs = npc_qt
+            .query_ref(&npc_area)
+            .into_iter()
+            .map(|x| WeightedPoint::new(x.pos, x.mass))
+            .collect();
+        Frame::new(npcs, food)
+    }
+
+    pub fn step(&mut self) {
+        self.events.clear();
+        let tick = self.elapsed;
+        self.notify(|x, mb| x.on_tick_start(tick, mb));
+
+        // Spawn food
+        let spawn_interval = FPS
+            .checked_div(self.config.food_spawn_rate)
+            .map(|x| x.max(1));
+        if spawn_interval.is_some_and(|x| self.elapsed.is_multiple_of(x)) {
+            let _ = self.spawn_food(None, None);
         }
 
-        let mut npcs_eaten = vec![false; self.npcs.len()];
+        // ---- Update NPCs ----
+        // Cells are indexed heaviest first since the last step or change to them
+        let frames = self
+            .npcs
+            .iter()
+            .map(|x| self.get_perceived_frame(x.pos, &self.npc_index))
+            .collect::<Vec<_>>();
+
+        let ids = self.npcs.iter().map(|x| x.id).collect::<Vec<_>>();
+        let num_npcs = self.npcs.len();
+
+        // Who ate each cell, if any did
+        let mut eaten_by = vec![None; self.npcs.len()];
         for (i, npc) in self.npcs.iter_mut().enumerate() {
-            if npcs_eaten[i] {
+            if eaten_by[i].is_some() {
                 continue;
             };
 
             let frame = &frames[i];
-            npc.step(frame);
+            let from = npc.pos;
+            npc.step(frame, &self.config);
+            self.observers
+                .each(|x| x.on_cell_moved(tick, CellView::new(npc), from));
 
             let mut area = Circle::new(npc.pos, npc.radius());
 
-            let eaten = self.food.pop(&area);
-            if eaten.len() > 0 {
-                npc.mass += eaten.into_iter().map(|f| f.mass).sum::<f64>();
+            let mut eaten = self.food.pop(&area);
+            eaten.sort_unstable_by_key(|x| x.id);
+            if !eaten.is_empty() {
+                for food in eaten {
+                    npc.mass += food.mass;
+                    let event = Event::AteFood {
+                        tick,
+                        cell: npc.id,
+                        food: food.id,
+                        mass: food.mass,
+                    };
+                    self.observers.each(|x| x.on_eat(&event));
+                    self.events.push(event);
+                }
                 area.set_radius(npc.radius());
             }
 
-            let eaten = npc_qt.query_filter(&area, |x| x.ix != i && x.mass < npc.mass - EAT_DIFF);
-            if eaten.len() > 0 {
-                npc.mass += eaten.iter().map(|x| x.mass).sum::<f64>();
-                for e in eaten {
-                    npcs_eaten[e.ix] = true;
-                }
+            // A cell eaten earlier in the step is gone, so it can't feed another
+            let eaten = self.npc_index.query_filter(&area, |x| {
+                x.ix != i && eaten_by[x.ix].is_none() && x.mass < npc.mass - self.config.eat_diff
+            });
+            for e in eaten {
+                npc.mass += e.mass;
+                eaten_by[e.ix] = Some(npc.id);
+                let event = Event::AteCell {
+                    tick,
+                    cell: npc.id,
+                    prey: ids[e.ix],
+                    mass: e.mass,
+                };
+                self.observers.each(|x| x.on_eat(&event));
+                self.events.push(event);
+            }
+        }
+
+        for (npc, by) in self.npcs.iter().zip(&eaten_by) {
+            if by.is_some() {
+                self.observers
+                    .each(|x| x.on_death(tick, CellView::new(npc), *by));
             }
         }
+        let mut eaten = eaten_by.into_iter();
+        self.npcs.retain(|_| eaten.next().unwrap().is_none());
+        self.index_npcs();
 
-        let mut eaten = npcs_eaten.into_iter();
-        self.npcs.retain(|_| !eaten.next().unwrap());
+        if num_npcs > 1 && self.npcs.len() == 1 {
+            self.events.push(Event::Monopoly {
+                tick,
+                cell: self.npcs[0].id,
+            });
+        }
         // ---------------------
 
         self.elapsed += 1;
+        self.notify(|x, mb| x.on_tick_end(tick, mb));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_are_eaten_once() {
+        let config = Config {
+            base_speed: 0.0,
+            initial_num_npcs: 0,
+            initial_food_supply: 0,
+            food_spawn_rate: 0,
+            ..Config::default()
+        };
+        let mut mb = Microbiome::with_config(config, 0);
+        // Both big cells reach the small one between them, but not each other
+        let first = mb
+            .spawn_npc(Some(point![100.0, 100.0]), Some(100.0))
+            .unwrap();
+        let second = mb
+            .spawn_npc(Some(point![115.0, 100.0]), Some(90.0))
+            .unwrap();
+        let prey = mb.spawn_npc(Some(point![108.0, 100.0]), Some(5.0)).unwrap();
+
+        mb.step();
+        assert_eq!(mb.stats().npc_mass, 195.0);
+        assert_eq!(mb.cell(first).unwrap().mass(), 105.0);
+        assert_eq!(mb.cell(second).unwrap().mass(), 90.0);
+        assert!(mb.cell(prey).is_none());
+        let eats = mb
+            .events()
+            .iter()
+            .filter(|x| matches!(x, Event::AteCell { .. }))
+            .count();
+        assert_eq!(eats, 1);
     }
 }
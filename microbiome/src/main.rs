@@ -1,37 +1,402 @@
 use std::{
     env,
     error::Error,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread,
     time::{Duration, Instant},
 };
 
-use microbiome::{invariants::FRAME_DURATION, Microbiome};
+use clap::{Args, Parser, Subcommand};
+use microbiome::{
+    agents::{AgentHub, AgentOptions, Mode},
+    batch::{self, Grid, RunOptions},
+    control::{self, Reply, Simulation},
+    invariants::FRAME_DURATION,
+    Action, Config, Microbiome,
+};
+use protocol::sim::{self, SimMessage};
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut mb = Microbiome::new();
+#[derive(Parser)]
+#[command(about = "A place for cells to thrive and compete")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run forever, publishing frames to `MB_PUBSUB`, taking commands on `MB_CONTROL` and agents on
+    /// `MB_AGENTS` (the default)
+    Serve(ServeArgs),
+    /// Simulate a fixed number of ticks and write stats and events to disk
+    Run(RunArgs),
+    /// Run a grid of config parameters and seeds in parallel and write one results table
+    Sweep(SweepArgs),
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// Seed for the random number generator, random if omitted
+    #[arg(long)]
+    seed: Option<u64>,
+    /// TOML file overriding the default config
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Topic to publish frames on, to tell simulators apart when they share a subscriber
+    #[arg(long, default_value = sim::TOPIC)]
+    topic: String,
+    /// Step at the frame rate with the last action of each agent, instead of waiting for them
+    #[arg(long)]
+    free_running: bool,
+    /// How long to wait for agents to act in lockstep mode
+    #[arg(long, default_value_t = 100)]
+    agent_timeout_ms: u64,
+    /// JSON action for agents that have not acted in time, e.g. '{"dir":[0,0]}'
+    #[arg(long, value_parser = parse_action)]
+    default_action: Option<Action>,
+    /// Ticks an agent may go without a request before its cells are removed
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    agent_max_missed_ticks: u64,
+    /// Write a snapshot of the microbiome to this JSON file when stopped with ctrl-c or SIGTERM
+    #[arg(long)]
+    snapshot_on_exit: Option<PathBuf>,
+}
+
+fn parse_action(s: &str) -> Result<Action, serde_json::Error> {
+    serde_json::from_str(s)
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Number of ticks to simulate
+    #[arg(long)]
+    ticks: u64,
+    /// Seed for the random number generator
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// TOML file overriding the default config
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Write stats to this .csv or .jsonl file
+    #[arg(long)]
+    stats: Option<PathBuf>,
+    /// Record stats every this many ticks
+    #[arg(long, default_value_t = 1)]
+    stats_every: u64,
+    /// Write events to this .csv or .jsonl file
+    #[arg(long)]
+    events: Option<PathBuf>,
+    /// Stop early once a single cell is left
+    #[arg(long)]
+    until_monopoly: bool,
+}
+
+#[derive(Args)]
+struct SweepArgs {
+    /// TOML file with the seeds and parameter values to sweep over
+    #[arg(long)]
+    grid: PathBuf,
+    /// Number of ticks to simulate per run
+    #[arg(long)]
+    ticks: u64,
+    /// TOML file overriding the default config for every run
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Write the results table to this .csv or .jsonl file
+    #[arg(long)]
+    out: PathBuf,
+    /// Number of runs to simulate in parallel, defaults to the number of cpus
+    #[arg(long)]
+    jobs: Option<usize>,
+    /// Stop each run early once a single cell is left
+    #[arg(long)]
+    until_monopoly: bool,
+}
+
+/// Read a config from a TOML file, filling missing fields with defaults
+fn read_config(path: Option<&PathBuf>) -> Result<Config, Box<dyn Error>> {
+    let Some(path) = path else {
+        return Ok(Config::default());
+    };
+    let config: Config = toml::from_str(&fs::read_to_string(path)?)?;
+    config
+        .validate()
+        .map_err(|e| format!("invalid config in {}: {e}", path.display()))?;
+    Ok(config)
+}
+
+/// How long to wait before binding the control socket again after that failed
+const REBIND_DELAY: Duration = Duration::from_secs(1);
+
+/// The REP socket commands arrive on, bound again whenever it fails
+///
+/// A REP socket that received a request but couldn't answer it refuses any further request, so
+/// rather than dealing with what state it was left in, it's replaced with a new one.
+struct ControlSocket {
+    endpoint: String,
+    sock: Option<zmq::Socket>,
+    /// When to try binding again, if the socket is missing
+    rebind_at: Instant,
+}
+
+impl ControlSocket {
+    fn bind(context: &zmq::Context, endpoint: String) -> Result<Self, zmq::Error> {
+        let sock = Self::open(context, &endpoint)?;
+        Ok(Self {
+            endpoint,
+            sock: Some(sock),
+            rebind_at: Instant::now(),
+        })
+    }
+
+    fn open(context: &zmq::Context, endpoint: &str) -> Result<zmq::Socket, zmq::Error> {
+        let sock = context.socket(zmq::REP)?;
+        // Leave nothing behind to hold on to the endpoint once replaced
+        sock.set_linger(0)?;
+        sock.bind(endpoint)?;
+        Ok(sock)
+    }
+
+    /// The socket, bound again first if it failed
+    fn socket(&mut self, context: &zmq::Context) -> Option<&zmq::Socket> {
+        if self.sock.is_none() && Instant::now() >= self.rebind_at {
+            match Self::open(context, &self.endpoint) {
+                Ok(sock) => self.sock = Some(sock),
+                Err(e) => {
+                    eprintln!("failed to bind the control socket {}: {e}", self.endpoint);
+                    self.rebind_at = Instant::now() + REBIND_DELAY;
+                }
+            }
+        }
+        self.sock.as_ref()
+    }
+
+    /// Answer every request that has arrived, replacing the socket if that fails
+    fn answer(&mut self, sim: &mut Simulation) {
+        let Some(sock) = &self.sock else {
+            return;
+        };
+        if let Err(e) = handle_controls(sim, sock) {
+            eprintln!("control socket failed, binding it again: {e}");
+            self.sock = None;
+        }
+    }
+}
+
+/// Answer every request waiting on the control socket
+fn handle_controls(sim: &mut Simulation, control_sock: &zmq::Socket) -> Result<(), Box<dyn Error>> {
+    while control_sock.poll(zmq::POLLIN, 0)? > 0 {
+        let msg = control_sock.recv_bytes(0)?;
+        let reply = match serde_json::from_slice::<control::Command>(&msg) {
+            Ok(cmd) => sim.handle(cmd),
+            Err(e) => Reply::error(format!("invalid command: {e}")),
+        };
+        control_sock.send(serde_json::to_vec(&reply)?, 0)?;
+    }
+    Ok(())
+}
+
+/// Publish the frame of the latest tick, along with its events and the metrics when they are due
+fn publish(
+    pub_sock: &zmq::Socket,
+    topic: &str,
+    sim: &mut Simulation,
+    stepped: bool,
+) -> Result<(), Box<dyn Error>> {
+    let msg = SimMessage::State {
+        state: sim.microbiome().state(),
+    };
+    pub_sock.send_multipart(msg.to_frames(topic)?, zmq::DONTWAIT)?;
+    if stepped && !sim.microbiome().events().is_empty() {
+        let msg = SimMessage::Events {
+            events: sim.microbiome().events().to_vec(),
+        };
+        pub_sock.send_multipart(msg.to_frames(topic)?, zmq::DONTWAIT)?;
+    }
+    if let Some(msg) = sim.metrics() {
+        pub_sock.send_multipart(msg.to_frames(topic)?, zmq::DONTWAIT)?;
+    }
+    Ok(())
+}
+
+fn serve(args: ServeArgs) -> Result<(), Box<dyn Error>> {
+    let config = read_config(args.config.as_ref())?;
+    let mb = Microbiome::with_config(config, args.seed.unwrap_or_else(rand::random));
+    let mut sim = Simulation::new(mb);
 
     let pub_to = env::var("MB_PUBSUB").expect("MB_PUBSUB must be set");
     let context = zmq::Context::new();
     let pub_sock = context.socket(zmq::PUB)?;
     pub_sock.bind(&pub_to)?;
 
-    let frame_duration = Duration::from_millis(FRAME_DURATION as u64);
+    let mut control = match env::var("MB_CONTROL") {
+        Ok(control_at) => Some(ControlSocket::bind(&context, control_at)?),
+        Err(_) => None,
+    };
 
-    loop {
+    let mut agents = match env::var("MB_AGENTS") {
+        Ok(agents_at) => {
+            let opts = AgentOptions {
+                mode: if args.free_running {
+                    Mode::FreeRunning
+                } else {
+                    Mode::Lockstep
+                },
+                timeout: Duration::from_millis(args.agent_timeout_ms),
+                default_action: args.default_action.unwrap_or_default(),
+                max_missed_ticks: args.agent_max_missed_ticks,
+            };
+            Some(AgentHub::bind(&context, &agents_at, opts)?)
+        }
+        Err(_) => None,
+    };
+
+    let frame_duration = Duration::from_millis(FRAME_DURATION);
+
+    // Finish the current step and close the sockets instead of dying mid-frame
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_handler = Arc::clone(&stop);
+    ctrlc::set_handler(move || stop_handler.store(true, Ordering::Relaxed))?;
+
+    while !stop.load(Ordering::Relaxed) {
         let start = Instant::now();
 
-        mb.step();
+        // Answer control and agent requests until the next step is due
+        loop {
+            let due = match &agents {
+                Some(hub) if hub.waiting_for_actions() => frame_duration.max(hub.options().timeout),
+                _ => frame_duration,
+            };
+            let Some(remaining) = due.checked_sub(start.elapsed()) else {
+                break;
+            };
+
+            let mut items = Vec::with_capacity(2);
+            if let Some(sock) = control.as_mut().and_then(|x| x.socket(&context)) {
+                items.push(sock.as_poll_item(zmq::POLLIN));
+            }
+            if let Some(hub) = &agents {
+                items.push(hub.socket().as_poll_item(zmq::POLLIN));
+            }
+            if items.is_empty() {
+                thread::sleep(remaining);
+                break;
+            }
+            match zmq::poll(&mut items, remaining.as_millis() as i64) {
+                Ok(_) => (),
+                // A signal arrived while waiting, the outer loop checks whether to stop
+                Err(zmq::Error::EINTR) => break,
+                Err(e) => {
+                    eprintln!("failed to poll for requests: {e}");
+                    break;
+                }
+            };
 
-        let state = serde_json::to_vec(&mb)?;
-        pub_sock.send_multipart(
-            ["mb_state".as_bytes(), "state".as_bytes(), &state],
-            zmq::DONTWAIT,
-        )?;
+            if let Some(control) = &mut control {
+                control.answer(&mut sim);
+            }
+            if let Some(hub) = &mut agents {
+                if let Err(e) = hub.recv(sim.microbiome_mut()) {
+                    eprintln!("failed to receive from agents: {e}");
+                }
+            }
+        }
 
-        if let Some(sleep_duration) = frame_duration.checked_sub(start.elapsed()) {
-            thread::sleep(sleep_duration);
+        if let Some(hub) = &mut agents {
+            hub.before_step(sim.microbiome_mut());
+        }
+        let stepped = sim.tick();
+        if stepped {
+            if let Some(hub) = &mut agents {
+                if let Err(e) = hub.after_step(sim.microbiome()) {
+                    eprintln!("failed to answer agents: {e}");
+                }
+            }
+        }
+
+        if let Err(e) = publish(&pub_sock, &args.topic, &mut sim, stepped) {
+            eprintln!("failed to publish: {e}");
         }
     }
 
+    if let Some(path) = &args.snapshot_on_exit {
+        fs::write(path, serde_json::to_vec(&sim.microbiome().snapshot())?)?;
+        println!(
+            "wrote a snapshot at tick {} to {}",
+            sim.microbiome().elapsed(),
+            path.display()
+        );
+    }
     Ok(())
 }
+
+fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let config = read_config(args.config.as_ref())?;
+    let opts = RunOptions {
+        ticks: args.ticks,
+        stats_every: if args.stats.is_some() {
+            args.stats_every
+        } else {
+            0
+        },
+        record_events: args.events.is_some(),
+        until_monopoly: args.until_monopoly,
+    };
+
+    let output = batch::run(config, args.seed, &opts);
+
+    if let Some(path) = &args.stats {
+        batch::write_table(path, &output.stats)?;
+    }
+    if let Some(path) = &args.events {
+        batch::write_table(path, &output.events)?;
+    }
+    println!("{}", serde_json::to_string(&output.summary)?);
+
+    Ok(())
+}
+
+fn sweep(args: SweepArgs) -> Result<(), Box<dyn Error>> {
+    let config = read_config(args.config.as_ref())?;
+    let grid = Grid::from_file(&args.grid)?;
+    // Fail before spending minutes simulating
+    batch::Format::from_path(&args.out)?;
+
+    if let Some(jobs) = args.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
+    }
+
+    let opts = RunOptions {
+        ticks: args.ticks,
+        stats_every: 0,
+        record_events: false,
+        until_monopoly: args.until_monopoly,
+    };
+
+    let rows = batch::sweep(&config, &grid, &opts)?;
+    batch::write_table(&args.out, &rows)?;
+    println!("wrote {} runs to {}", rows.len(), args.out.display());
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        None => serve(cli.serve),
+        Some(Command::Serve(args)) => serve(args),
+        Some(Command::Run(args)) => run(args),
+        Some(Command::Sweep(args)) => sweep(args),
+    }
+}
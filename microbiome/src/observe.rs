@@ -0,0 +1,267 @@
+//! Hooks into the steps of a microbiome, for instrumentation that would otherwise fork `step`
+//!
+//! Observers only get to look, never to change anything, so a run goes the same with or without
+//! them.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use nalgebra::Point2;
+
+use crate::{
+    query::{CellView, EntityView},
+    Event, Microbiome,
+};
+
+/// Callbacks made while a microbiome steps, each doing nothing unless implemented
+///
+/// Within a step they come in this order: `on_tick_start`, a food spawn if it is time for one,
+/// then for each cell heaviest first its move and what it ate, the deaths of the cells eaten and
+/// last `on_tick_end`. Spawns and deaths from outside of a step are reported too, with the tick
+/// the microbiome is at.
+#[allow(unused_variables)]
+pub trait Observer: Send {
+    /// Before anything happens in the step of `tick`
+    fn on_tick_start(&mut self, tick: u64, mb: &Microbiome) {}
+
+    /// A cell moved from `from` to where it is now
+    fn on_cell_moved(&mut self, tick: u64, cell: CellView<'_>, from: Point2<f64>) {}
+
+    /// A cell ate, `event` being [`Event::AteFood`] or [`Event::AteCell`]
+    fn on_eat(&mut self, event: &Event) {}
+
+    /// A cell or piece of food was added, split off or ejected by `parent` if it has one
+    fn on_spawn(&mut self, tick: u64, entity: EntityView<'_>, parent: Option<u64>) {}
+
+    /// A cell is about to be taken out of the biome, eaten by `eaten_by` or else removed
+    fn on_death(&mut self, tick: u64, cell: CellView<'_>, eaten_by: Option<u64>) {}
+
+    /// After everything in the step of `tick`, with [`Microbiome::events`] holding its events
+    fn on_tick_end(&mut self, tick: u64, mb: &Microbiome) {}
+}
+
+/// An observer shared with whoever reads what it found between steps
+impl<O: Observer> Observer for Arc<Mutex<O>> {
+    fn on_tick_start(&mut self, tick: u64, mb: &Microbiome) {
+        self.lock().unwrap().on_tick_start(tick, mb)
+    }
+
+    fn on_cell_moved(&mut self, tick: u64, cell: CellView<'_>, from: Point2<f64>) {
+        self.lock().unwrap().on_cell_moved(tick, cell, from)
+    }
+
+    fn on_eat(&mut self, event: &Event) {
+        self.lock().unwrap().on_eat(event)
+    }
+
+    fn on_spawn(&mut self, tick: u64, entity: EntityView<'_>, parent: Option<u64>) {
+        self.lock().unwrap().on_spawn(tick, entity, parent)
+    }
+
+    fn on_death(&mut self, tick: u64, cell: CellView<'_>, eaten_by: Option<u64>) {
+        self.lock().unwrap().on_death(tick, cell, eaten_by)
+    }
+
+    fn on_tick_end(&mut self, tick: u64, mb: &Microbiome) {
+        self.lock().unwrap().on_tick_end(tick, mb)
+    }
+}
+
+/// Observers registered on a microbiome, in the order they were added
+#[derive(Default)]
+pub(crate) struct Observers(Vec<Box<dyn Observer>>);
+
+impl Observers {
+    /// Make a callback on every observer
+    pub(crate) fn each(&mut self, mut f: impl FnMut(&mut dyn Observer)) {
+        for observer in &mut self.0 {
+            f(observer.as_mut());
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl Microbiome {
+    /// Call `observer` back during every step from now on, after those added before it
+    ///
+    /// Observers stay through a reset or a loaded snapshot of a [`crate::control::Simulation`],
+    /// but are not part of snapshots themselves.
+    pub fn add_observer(&mut self, observer: impl Observer + 'static) {
+        self.observers.0.push(Box::new(observer));
+    }
+
+    /// Stop calling back every observer
+    pub fn clear_observers(&mut self) {
+        self.observers.0.clear();
+    }
+
+    /// Call back the observers with the whole microbiome, which they are taken out of meanwhile
+    pub(crate) fn notify(&mut self, f: impl Fn(&mut dyn Observer, &Microbiome)) {
+        let mut observers = std::mem::take(&mut self.observers);
+        observers.each(|x| f(x, self));
+        self.observers = observers;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::point;
+
+    use super::*;
+    use crate::{
+        control::{Command, Simulation},
+        Config,
+    };
+
+    /// Writes down every callback, one line each
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Observer for Log {
+        fn on_tick_start(&mut self, tick: u64, _: &Microbiome) {
+            self.0.push(format!("start {tick}"));
+        }
+
+        fn on_cell_moved(&mut self, tick: u64, cell: CellView<'_>, _: Point2<f64>) {
+            self.0.push(format!("move {tick} {}", cell.id()));
+        }
+
+        fn on_eat(&mut self, event: &Event) {
+            self.0.push(match event {
+                Event::AteFood { cell, food, .. } => format!("eat {cell} food {food}"),
+                Event::AteCell { cell, prey, .. } => format!("eat {cell} cell {prey}"),
+                event => panic!("not an eat: {event:?}"),
+            });
+        }
+
+        fn on_spawn(&mut self, tick: u64, entity: EntityView<'_>, parent: Option<u64>) {
+            let kind = match entity {
+                EntityView::Cell(_) => "cell",
+                EntityView::Food(_) => "food",
+            };
+            self.0
+                .push(format!("spawn {tick} {kind} {} {parent:?}", entity.id()));
+        }
+
+        fn on_death(&mut self, tick: u64, cell: CellView<'_>, eaten_by: Option<u64>) {
+            self.0
+                .push(format!("death {tick} {} {eaten_by:?}", cell.id()));
+        }
+
+        fn on_tick_end(&mut self, tick: u64, _: &Microbiome) {
+            self.0.push(format!("end {tick}"));
+        }
+    }
+
+    /// A biome of cells that stay where they are put
+    fn still() -> Microbiome {
+        let config = Config {
+            base_speed: 0.0,
+            initial_num_npcs: 0,
+            initial_food_supply: 0,
+            ..Config::default()
+        };
+        Microbiome::with_config(config, 1)
+    }
+
+    #[test]
+    fn callbacks_come_in_the_documented_order() {
+        let mut mb = still();
+        let big = mb
+            .spawn_npc(Some(point![100.0, 100.0]), Some(100.0))
+            .unwrap();
+        let prey = mb.spawn_npc(Some(point![108.0, 100.0]), Some(5.0)).unwrap();
+        let other = mb
+            .spawn_npc(Some(point![300.0, 300.0]), Some(50.0))
+            .unwrap();
+        // Within reach, but not right under the cell, which would leave it no way to head
+        let food = mb
+            .spawn_food(Some(point![302.0, 300.0]), Some(1.0))
+            .unwrap();
+        let log = Arc::new(Mutex::new(Log::default()));
+        mb.add_observer(Arc::clone(&log));
+
+        mb.step();
+        let spawned = food + 1;
+        let expected = [
+            "start 0".to_string(),
+            format!("spawn 0 food {spawned} None"),
+            format!("move 0 {big}"),
+            format!("eat {big} cell {prey}"),
+            format!("move 0 {other}"),
+            format!("eat {other} food {food}"),
+            format!("death 0 {prey} Some({big})"),
+            "end 0".to_string(),
+        ];
+        assert_eq!(log.lock().unwrap().0, expected);
+
+        // From outside of a step, with the tick the biome is at
+        log.lock().unwrap().0.clear();
+        let id = mb.spawn_npc(None, None).unwrap();
+        mb.remove(id);
+        assert_eq!(
+            log.lock().unwrap().0,
+            [
+                format!("spawn 1 cell {id} None"),
+                format!("death 1 {id} None")
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_observers_are_read_between_steps() {
+        let mut mb = still();
+        let first = Arc::new(Mutex::new(Log::default()));
+        let second = Arc::new(Mutex::new(Log::default()));
+        mb.add_observer(Arc::clone(&first));
+        mb.add_observer(Arc::clone(&second));
+
+        for tick in 0..3 {
+            mb.step();
+            for log in [&first, &second] {
+                let log = log.lock().unwrap();
+                assert_eq!(log.0.last().unwrap(), &format!("end {tick}"));
+            }
+        }
+        assert_eq!(first.lock().unwrap().0, second.lock().unwrap().0);
+
+        mb.clear_observers();
+        let seen = first.lock().unwrap().0.len();
+        mb.step();
+        assert_eq!(first.lock().unwrap().0.len(), seen);
+    }
+
+    #[test]
+    fn observers_stay_through_resets_and_snapshots() {
+        let mut mb = still();
+        let log = Arc::new(Mutex::new(Log::default()));
+        mb.add_observer(Arc::clone(&log));
+        let mut sim = Simulation::new(mb);
+        let snapshot = sim.microbiome().snapshot();
+
+        sim.handle(Command::Step { ticks: 2 });
+        sim.handle(Command::Reset { seed: Some(2) });
+        sim.handle(Command::Step { ticks: 1 });
+        sim.handle(Command::LoadSnapshot {
+            snapshot: Box::new(snapshot),
+        });
+        sim.handle(Command::Step { ticks: 1 });
+
+        let ends = log
+            .lock()
+            .unwrap()
+            .0
+            .iter()
+            .filter(|x| x.starts_with("end"))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(ends, ["end 0", "end 1", "end 0", "end 0"]);
+    }
+}
@@ -0,0 +1,456 @@
+//! Read-only views of what is in a microbiome, and where
+//!
+//! Regions and nearest neighbours are looked up in the food quadtree and the index of cells kept
+//! between steps, so they don't scan the whole biome. Results are ordered by id unless said
+//! otherwise, to stay reproducible.
+
+use nalgebra::{distance, Point2};
+use quadtree::shapes::{Circle, Rect, Shape};
+
+use crate::{
+    entities::{Food, NPCKind, NPC},
+    invariants, Microbiome, P2,
+};
+
+/// A cell as it is now
+#[derive(Debug, Clone, Copy)]
+pub struct CellView<'a> {
+    npc: &'a NPC,
+}
+
+impl<'a> CellView<'a> {
+    pub(crate) fn new(npc: &'a NPC) -> Self {
+        Self { npc }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.npc.id
+    }
+
+    pub fn pos(&self) -> Point2<f64> {
+        self.npc.pos
+    }
+
+    pub fn mass(&self) -> f64 {
+        self.npc.mass
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.npc.radius()
+    }
+
+    pub fn color(&self) -> &'a str {
+        &self.npc.color
+    }
+
+    /// Owner of the cell, if it is controlled from outside the simulator
+    pub fn owner(&self) -> Option<u64> {
+        self.npc.owner()
+    }
+
+    /// Name of the player controlling the cell, if it gave one
+    pub fn name(&self) -> Option<&'a str> {
+        match &self.npc.kind {
+            NPCKind::Controlled { name, .. } => name.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// A piece of food as it is now
+#[derive(Debug, Clone, Copy)]
+pub struct FoodView<'a> {
+    food: &'a Food,
+}
+
+impl<'a> FoodView<'a> {
+    pub(crate) fn new(food: &'a Food) -> Self {
+        Self { food }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.food.id
+    }
+
+    pub fn pos(&self) -> Point2<f64> {
+        self.food.pos
+    }
+
+    pub fn mass(&self) -> f64 {
+        self.food.mass
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.food.radius()
+    }
+
+    pub fn color(&self) -> &'a str {
+        &self.food.color
+    }
+}
+
+/// A cell or a piece of food
+#[derive(Debug, Clone, Copy)]
+pub enum EntityView<'a> {
+    Cell(CellView<'a>),
+    Food(FoodView<'a>),
+}
+
+impl EntityView<'_> {
+    pub fn id(&self) -> u64 {
+        match self {
+            EntityView::Cell(x) => x.id(),
+            EntityView::Food(x) => x.id(),
+        }
+    }
+
+    pub fn pos(&self) -> Point2<f64> {
+        match self {
+            EntityView::Cell(x) => x.pos(),
+            EntityView::Food(x) => x.pos(),
+        }
+    }
+
+    pub fn mass(&self) -> f64 {
+        match self {
+            EntityView::Cell(x) => x.mass(),
+            EntityView::Food(x) => x.mass(),
+        }
+    }
+
+    pub fn radius(&self) -> f64 {
+        invariants::radius(self.mass())
+    }
+}
+
+/// Part of the biome, holding the entities whose center is inside it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    /// Between two opposite corners, edges included
+    Rect {
+        from: Point2<f64>,
+        to: Point2<f64>,
+    },
+    Circle {
+        center: Point2<f64>,
+        radius: f64,
+    },
+}
+
+impl Region {
+    /// Look up what is inside the region with `query`, which takes the quadtree shape of it
+    fn query<T>(&self, query: impl QueryShape<T>) -> Vec<T> {
+        match *self {
+            Region::Rect { from, to } => {
+                let start = P2::new(from.x.min(to.x), from.y.min(to.y));
+                let end = P2::new(from.x.max(to.x), from.y.max(to.y));
+                query.query(&Rect::new(start, end))
+            }
+            Region::Circle { center, radius } => query.query(&Circle::new(center, radius)),
+        }
+    }
+}
+
+/// A lookup in a quadtree that works with any shape, as closures can't be generic
+trait QueryShape<T> {
+    fn query<S: Shape>(self, shape: &S) -> Vec<T>;
+}
+
+struct Cells<'a>(&'a Microbiome);
+
+impl<'a> QueryShape<CellView<'a>> for Cells<'a> {
+    fn query<S: Shape>(self, shape: &S) -> Vec<CellView<'a>> {
+        let mb = self.0;
+        let mut cells = mb
+            .npc_index
+            .query_ref(shape)
+            .into_iter()
+            .map(|x| CellView {
+                npc: &mb.npcs[x.ix],
+            })
+            .collect::<Vec<_>>();
+        cells.sort_unstable_by_key(|x| x.id());
+        cells
+    }
+}
+
+struct FoodIn<'a>(&'a Microbiome);
+
+impl<'a> QueryShape<FoodView<'a>> for FoodIn<'a> {
+    fn query<S: Shape>(self, shape: &S) -> Vec<FoodView<'a>> {
+        let mut food = self
+            .0
+            .food
+            .query_ref(shape)
+            .into_iter()
+            .map(|food| FoodView { food })
+            .collect::<Vec<_>>();
+        food.sort_unstable_by_key(|x| x.id());
+        food
+    }
+}
+
+/// The `k` of `found` closest to `point`, closest first and by id among those as close
+fn closest<'a>(point: P2, k: usize, mut found: Vec<EntityView<'a>>) -> Vec<EntityView<'a>> {
+    found.sort_unstable_by(|a, b| {
+        distance(&point, &a.pos())
+            .total_cmp(&distance(&point, &b.pos()))
+            .then(a.id().cmp(&b.id()))
+    });
+    found.truncate(k);
+    found
+}
+
+impl Microbiome {
+    /// Every cell, heaviest first
+    pub fn cells(&self) -> impl Iterator<Item = CellView<'_>> {
+        self.npcs.iter().map(|npc| CellView { npc })
+    }
+
+    /// Every piece of food
+    pub fn food(&self) -> impl Iterator<Item = FoodView<'_>> {
+        FoodIn(self).query(&self.boundary).into_iter()
+    }
+
+    /// Number of cells, see [`Microbiome::stats`] for their mass
+    pub fn num_cells(&self) -> usize {
+        self.npcs.len()
+    }
+
+    /// Number of pieces of food, see [`Microbiome::stats`] for their mass
+    pub fn num_food(&self) -> usize {
+        self.food.query_ref(&self.boundary).len()
+    }
+
+    pub fn cell(&self, id: u64) -> Option<CellView<'_>> {
+        // O(n) is fine for now because there are not very many NPCs
+        self.cells().find(|x| x.id() == id)
+    }
+
+    /// The cell or piece of food with `id`
+    pub fn entity(&self, id: u64) -> Option<EntityView<'_>> {
+        if let Some(cell) = self.cell(id) {
+            return Some(EntityView::Cell(cell));
+        }
+        let food = self
+            .food
+            .query_ref_filter(&self.boundary, |x| x.id == id)
+            .pop()?;
+        Some(EntityView::Food(FoodView { food }))
+    }
+
+    pub fn cells_in(&self, region: &Region) -> Vec<CellView<'_>> {
+        region.query(Cells(self))
+    }
+
+    pub fn food_in(&self, region: &Region) -> Vec<FoodView<'_>> {
+        region.query(FoodIn(self))
+    }
+
+    /// Cells and food in the region, cells first
+    pub fn entities_in(&self, region: &Region) -> Vec<EntityView<'_>> {
+        let cells = self.cells_in(region).into_iter().map(EntityView::Cell);
+        let food = self.food_in(region).into_iter().map(EntityView::Food);
+        cells.chain(food).collect()
+    }
+
+    /// The `k` cells closest to `point`, closest first
+    pub fn nearest_cells(&self, point: Point2<f64>, k: usize) -> Vec<CellView<'_>> {
+        let found = self.nearest_in(point, k, |region| {
+            let cells = self.cells_in(region).into_iter();
+            cells.map(EntityView::Cell).collect()
+        });
+        found
+            .into_iter()
+            .filter_map(|x| match x {
+                EntityView::Cell(cell) => Some(cell),
+                EntityView::Food(_) => None,
+            })
+            .collect()
+    }
+
+    /// The `k` pieces of food closest to `point`, closest first
+    pub fn nearest_food(&self, point: Point2<f64>, k: usize) -> Vec<FoodView<'_>> {
+        let found = self.nearest_in(point, k, |region| {
+            let food = self.food_in(region).into_iter();
+            food.map(EntityView::Food).collect()
+        });
+        found
+            .into_iter()
+            .filter_map(|x| match x {
+                EntityView::Food(food) => Some(food),
+                EntityView::Cell(_) => None,
+            })
+            .collect()
+    }
+
+    /// The `k` cells or pieces of food closest to `point`, closest first
+    pub fn nearest(&self, point: Point2<f64>, k: usize) -> Vec<EntityView<'_>> {
+        self.nearest_in(point, k, |region| self.entities_in(region))
+    }
+
+    /// Search ever larger circles around `point` with `search` until one holds `k` entities or
+    /// covers the whole biome, as any closer than the `k`th are in it too
+    fn nearest_in<'a>(
+        &'a self,
+        point: P2,
+        k: usize,
+        search: impl Fn(&Region) -> Vec<EntityView<'a>>,
+    ) -> Vec<EntityView<'a>> {
+        if k == 0 {
+            return Vec::new();
+        }
+        // Far enough from anywhere in or around the biome to reach all of it
+        let corners = [
+            P2::new(0.0, 0.0),
+            P2::new(self.config.size, 0.0),
+            P2::new(0.0, self.config.size),
+            P2::new(self.config.size, self.config.size),
+        ];
+        let farthest = corners
+            .iter()
+            .map(|x| distance(&point, x))
+            .fold(0.0, f64::max);
+        let mut radius = self.config.cell_perception_radius.min(farthest).max(1.0);
+        loop {
+            let region = Region::Circle {
+                center: point,
+                radius,
+            };
+            let found = search(&region);
+            if found.len() >= k || radius >= farthest {
+                return closest(point, k, found);
+            }
+            radius = (radius * 2.0).min(farthest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::point;
+
+    use super::*;
+    use crate::Config;
+
+    /// An empty biome, for the tests to place everything in themselves
+    fn empty() -> Microbiome {
+        let config = Config {
+            initial_num_npcs: 0,
+            initial_food_supply: 0,
+            ..Config::default()
+        };
+        Microbiome::with_config(config, 0)
+    }
+
+    /// Check that every cell is found where it is, and nothing else is
+    fn assert_indexed(mb: &Microbiome) {
+        let mut ids = mb.cells().map(|x| x.id()).collect::<Vec<_>>();
+        ids.sort_unstable();
+        let everywhere = Region::Rect {
+            from: point![0.0, 0.0],
+            to: point![mb.config.size, mb.config.size],
+        };
+        let found = mb.cells_in(&everywhere);
+        assert_eq!(found.iter().map(|x| x.id()).collect::<Vec<_>>(), ids);
+
+        for cell in mb.cells() {
+            let here = Region::Rect {
+                from: cell.pos(),
+                to: cell.pos(),
+            };
+            let found = mb.cells_in(&here);
+            let found = found.iter().find(|x| x.id() == cell.id()).unwrap();
+            assert_eq!(found.pos(), cell.pos());
+            assert_eq!(found.mass(), cell.mass());
+        }
+    }
+
+    #[test]
+    fn nearest_returns_everything_when_asked_for_more() {
+        let mut mb = empty();
+        let a = mb.spawn_npc(Some(point![10.0, 10.0]), Some(5.0)).unwrap();
+        let b = mb.spawn_npc(Some(point![450.0, 450.0]), Some(5.0)).unwrap();
+        let food = mb.spawn_food(Some(point![20.0, 10.0]), Some(1.0)).unwrap();
+
+        let cells = mb.nearest_cells(point![0.0, 0.0], 10);
+        assert_eq!(cells.iter().map(|x| x.id()).collect::<Vec<_>>(), [a, b]);
+        let all = mb.nearest(point![0.0, 0.0], 10);
+        assert_eq!(all.iter().map(|x| x.id()).collect::<Vec<_>>(), [a, food, b]);
+        assert!(mb.nearest(point![0.0, 0.0], 0).is_empty());
+    }
+
+    #[test]
+    fn nearest_breaks_ties_by_id() {
+        let mut mb = empty();
+        let center = point![250.0, 250.0];
+        let mut ids = Vec::new();
+        for pos in [[260.0, 250.0], [240.0, 250.0], [250.0, 260.0]] {
+            let id = mb.spawn_npc(Some(pos.into()), Some(5.0)).unwrap();
+            ids.push(id);
+        }
+        let far = mb.spawn_npc(Some(point![250.0, 280.0]), Some(5.0)).unwrap();
+
+        let found = mb.nearest_cells(center, 2);
+        assert_eq!(found.iter().map(|x| x.id()).collect::<Vec<_>>(), ids[..2]);
+        let found = mb.nearest_cells(center, 4);
+        let mut expected = ids.clone();
+        expected.push(far);
+        assert_eq!(found.iter().map(|x| x.id()).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn rects_take_their_corners_in_either_order() {
+        let mut mb = empty();
+        let inside = mb.spawn_npc(Some(point![100.0, 200.0]), Some(5.0)).unwrap();
+        let edge = mb
+            .spawn_food(Some(point![150.0, 250.0]), Some(1.0))
+            .unwrap();
+        mb.spawn_npc(Some(point![300.0, 200.0]), Some(5.0)).unwrap();
+
+        let corners = [
+            (point![50.0, 150.0], point![150.0, 250.0]),
+            (point![150.0, 250.0], point![50.0, 150.0]),
+            (point![50.0, 250.0], point![150.0, 150.0]),
+            (point![150.0, 150.0], point![50.0, 250.0]),
+        ];
+        for (from, to) in corners {
+            let found = mb.entities_in(&Region::Rect { from, to });
+            let ids = found.iter().map(|x| x.id()).collect::<Vec<_>>();
+            assert_eq!(ids, [inside, edge], "from {from} to {to}");
+        }
+    }
+
+    #[test]
+    fn index_follows_splits_ejects_and_removals() {
+        let mut mb = empty();
+        for i in 0..20 {
+            let pos = point![20.0 * i as f64 + 10.0, 490.0 - 20.0 * i as f64];
+            mb.spawn_npc(Some(pos), Some(5.0 + i as f64)).unwrap();
+        }
+        let owner = mb.spawn_controlled(None);
+        for npc in mb.npcs.iter_mut().filter(|x| x.owner() == Some(owner)) {
+            npc.mass = 200.0;
+        }
+        mb.index_npcs();
+        assert_indexed(&mb);
+
+        assert!(mb.split(owner));
+        assert_eq!(mb.cells().filter(|x| x.owner() == Some(owner)).count(), 2);
+        assert_indexed(&mb);
+
+        let food = mb.num_food();
+        assert!(mb.eject(owner));
+        assert_eq!(mb.num_food(), food + 2);
+        assert_indexed(&mb);
+
+        let id = mb.cells().find(|x| x.owner().is_none()).unwrap().id();
+        assert!(mb.remove(id));
+        assert!(mb.cell(id).is_none());
+        assert_indexed(&mb);
+
+        assert!(mb.remove_owner(owner));
+        assert_eq!(mb.num_cells(), 19);
+        assert_indexed(&mb);
+    }
+}
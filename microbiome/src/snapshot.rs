@@ -0,0 +1,22 @@
+use protocol::RngState;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Capture the position of the rng, since its own serde impl uses a `u128` that can't pass through
+/// tagged enums
+pub fn rng_state(rng: &ChaCha8Rng) -> RngState {
+    RngState {
+        seed: rng.get_seed(),
+        stream: rng.get_stream(),
+        // 2^64 words is far more than a simulation will ever draw
+        word_pos: rng.get_word_pos() as u64,
+    }
+}
+
+/// Recreate an rng at the position it was captured at
+pub fn rng_from_state(state: &RngState) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::from_seed(state.seed);
+    rng.set_stream(state.stream);
+    rng.set_word_pos(state.word_pos as u128);
+    rng
+}
@@ -3,31 +3,31 @@ use quadtree::Point;
 use rand::{distributions::uniform::SampleRange, Rng};
 use random_color::RandomColor;
 
-use crate::{invariants::SIZE, P2};
+use crate::P2;
 
-/// Generate a random position and mass from a given range
+/// Generate a random position within a biome of the given size and a mass from a given range
 ///
 /// **Returns** (pos, mass, color)
-pub fn random_cell(mass_range: impl SampleRange<i32>) -> (P2, f64, String) {
-    let mut rng = rand::thread_rng();
+pub fn random_cell(
+    rng: &mut impl Rng,
+    size: f64,
+    mass_range: impl SampleRange<i32>,
+) -> (P2, f64, String) {
     let mass = rng.gen_range(mass_range) as f64;
-    let pos = na::point![
-        rng.gen::<f64>() * SIZE as f64,
-        rng.gen::<f64>() * SIZE as f64
-    ];
-    let color = random_color();
+    let pos = na::point![rng.gen::<f64>() * size, rng.gen::<f64>() * size];
+    let color = random_color(rng);
     (pos, mass, color)
 }
 
 /// Generate a random hex color
-pub fn random_color() -> String {
-    RandomColor::new().to_hex()
+pub fn random_color(rng: &mut impl Rng) -> String {
+    RandomColor::new().seed(rng.gen::<u64>()).to_hex()
 }
 
-pub fn restrict_cell_to_bounds(point: P2, radius: f64) -> P2 {
+pub fn restrict_cell_to_bounds(point: P2, radius: f64, size: f64) -> P2 {
     point![
-        point.x.clamp(radius, SIZE - radius),
-        point.y.clamp(radius, SIZE - radius),
+        point.x.clamp(radius, size - radius),
+        point.y.clamp(radius, size - radius),
     ]
 }
 
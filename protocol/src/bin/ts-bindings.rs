@@ -0,0 +1,59 @@
+//! Generate TypeScript definitions of the protocol, into `ui/src/protocol` unless another directory is given
+
+use std::{env, error::Error, fs, path::PathBuf};
+
+use microbiome_protocol::{
+    agents::{Request, Response},
+    control::{Command, Reply},
+    http::{ApiError, RecordingInfo, ResetRequest, RoomInfo, SimState, SnapshotInfo, StepRequest},
+    sim::SimMessage,
+    ws::{ClientMessage, ServerMessage, JSON_SUBPROTOCOL, MSGPACK_SUBPROTOCOL},
+    PROTOCOL_VERSION,
+};
+use ts_rs::TS;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let out_dir = match env::args().nth(1) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../ui/src/protocol"),
+    };
+
+    // Start over so types that were removed don't linger
+    if out_dir.exists() {
+        fs::remove_dir_all(&out_dir)?;
+    }
+
+    let cfg = ts_rs::Config::new()
+        // Ids and ticks stay well below 2^53, and JSON.parse gives numbers anyway
+        .with_large_int("number")
+        .with_array_tuple_limit(4)
+        .with_out_dir(&out_dir);
+
+    ClientMessage::export_all(&cfg)?;
+    ServerMessage::export_all(&cfg)?;
+    SimMessage::export_all(&cfg)?;
+    Command::export_all(&cfg)?;
+    Reply::export_all(&cfg)?;
+    Request::export_all(&cfg)?;
+    Response::export_all(&cfg)?;
+    RoomInfo::export_all(&cfg)?;
+    SimState::export_all(&cfg)?;
+    StepRequest::export_all(&cfg)?;
+    ResetRequest::export_all(&cfg)?;
+    SnapshotInfo::export_all(&cfg)?;
+    ApiError::export_all(&cfg)?;
+    RecordingInfo::export_all(&cfg)?;
+
+    fs::write(
+        out_dir.join("version.ts"),
+        format!(
+            "// This file was generated by ts-bindings. Do not edit this file manually.\n\n\
+             export const PROTOCOL_VERSION = {PROTOCOL_VERSION};\n\n\
+             export const JSON_SUBPROTOCOL = \"{JSON_SUBPROTOCOL}\";\n\n\
+             export const MSGPACK_SUBPROTOCOL = \"{MSGPACK_SUBPROTOCOL}\";\n"
+        ),
+    )?;
+
+    println!("wrote TypeScript bindings to {}", out_dir.display());
+    Ok(())
+}
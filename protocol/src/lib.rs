@@ -0,0 +1,22 @@
+//! Messages exchanged between the simulator, the server and the UI
+//!
+//! - The simulator publishes [`sim::SimMessage`]s on its PUB socket
+//! - Control clients such as the server drive the simulator with [`control::Command`]s, each answered
+//!   with a [`control::Reply`]
+//! - Agents play cells with [`agents::Request`]s, each answered with an [`agents::Response`]
+//! - Browsers talk to the server with [`ws::ClientMessage`]s and [`ws::ServerMessage`]s, and read
+//!   the bodies in [`http`] from its HTTP API
+//!
+//! Every channel carries [`PROTOCOL_VERSION`] up front: in the frames of published messages, in the
+//! `hello` of control clients and browsers, and in the `register` of agents. Peers speaking another
+//! version are refused instead of misreading each other.
+//!
+//! TypeScript definitions for the UI are generated from these types with
+//! `cargo run -p microbiome-protocol --bin ts-bindings`.
+
+pub mod v1;
+
+pub use v1::*;
+
+/// Version of the protocol in [`v1`], bumped with every breaking change
+pub const PROTOCOL_VERSION: u32 = 1;
@@ -0,0 +1,35 @@
+//! Messages of agents driving cells over the simulator's ROUTER socket
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{Action, Observation};
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "msg", rename_all = "snake_case")]
+pub enum Request {
+    /// Spawn a cell for the agent, refused unless `version` is the simulator's protocol version
+    Register {
+        version: u32,
+    },
+    Act {
+        action: Action,
+    },
+    Leave,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "msg", rename_all = "snake_case")]
+pub enum Response {
+    Observation {
+        agent: u64,
+        observation: Observation,
+    },
+    Dead {
+        agent: u64,
+    },
+    Left,
+    Error {
+        message: String,
+    },
+}
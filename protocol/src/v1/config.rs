@@ -0,0 +1,170 @@
+use std::{error::Error, ops::RangeInclusive};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Size of the biome
+pub const SIZE: f64 = 500.0;
+
+/// Base speed of cells, decreases with mass
+pub const BASE_SPEED: f64 = 4.0;
+
+/// Perception radius of cells for other cells
+pub const CELL_PERCEPTION_RADIUS: f64 = 200.0;
+
+/// Perception radius of cells for food
+pub const FOOD_PERCEPTION_RADIUS: f64 = 50.0;
+
+/// How much bigger a cell must be to eat another
+pub const EAT_DIFF: f64 = 5.0;
+
+/// Number of NPCs spawned initially
+pub const INITIAL_NUM_NPCS: usize = 10;
+
+/// Number of food cells to start with
+pub const INITIAL_FOOD_SUPPLY: usize = 20;
+
+/// How much food is spawned per second
+pub const FOOD_SPAWN_RATE: u64 = 10;
+
+/// Masses of the cells spawned at random
+pub const NPC_SPAWN_MASS: RangeInclusive<i32> = 20..=30;
+
+/// Most cells or pieces of food a config may start with or spawn per second
+pub const MAX_POPULATION: u64 = 100_000;
+
+/// Tunable parameters of a microbiome, defaulting to the constants of this module
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Size of the biome
+    pub size: f64,
+    /// Base speed of cells, decreases with mass
+    pub base_speed: f64,
+    /// Perception radius of cells for other cells
+    pub cell_perception_radius: f64,
+    /// Perception radius of cells for food
+    pub food_perception_radius: f64,
+    /// How much bigger a cell must be to eat another
+    pub eat_diff: f64,
+    /// Number of NPCs spawned initially
+    pub initial_num_npcs: usize,
+    /// Number of food cells to start with
+    pub initial_food_supply: usize,
+    /// How much food is spawned per second
+    pub food_spawn_rate: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            size: SIZE,
+            base_speed: BASE_SPEED,
+            cell_perception_radius: CELL_PERCEPTION_RADIUS,
+            food_perception_radius: FOOD_PERCEPTION_RADIUS,
+            eat_diff: EAT_DIFF,
+            initial_num_npcs: INITIAL_NUM_NPCS,
+            initial_food_supply: INITIAL_FOOD_SUPPLY,
+            food_spawn_rate: FOOD_SPAWN_RATE,
+        }
+    }
+}
+
+impl Config {
+    /// Set a single parameter by name, e.g. `set("eat_diff", 2.0.into())`
+    pub fn set(&mut self, key: &str, value: serde_json::Value) -> Result<(), Box<dyn Error>> {
+        let mut fields = serde_json::to_value(&*self)?;
+        match fields.get_mut(key) {
+            Some(field) => *field = value,
+            None => return Err(format!("unknown config parameter `{key}`").into()),
+        }
+        let config: Self = serde_json::from_value(fields)?;
+        config.validate()?;
+        *self = config;
+        Ok(())
+    }
+
+    /// Check that a microbiome can run with this config
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let numbers = [
+            ("size", self.size),
+            ("base_speed", self.base_speed),
+            ("cell_perception_radius", self.cell_perception_radius),
+            ("food_perception_radius", self.food_perception_radius),
+            ("eat_diff", self.eat_diff),
+        ];
+        for (key, value) in numbers {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(format!("{key} must be a number of at least 0, not {value}").into());
+            }
+        }
+
+        // The radius of a cell is the square root of its mass, and the biome must fit the
+        // largest cell spawned at random
+        let min_size = 2.0 * (*NPC_SPAWN_MASS.end() as f64).sqrt();
+        if self.size < min_size {
+            return Err(format!("size must be at least {min_size}, not {}", self.size).into());
+        }
+
+        let counts = [
+            ("initial_num_npcs", self.initial_num_npcs as u64),
+            ("initial_food_supply", self.initial_food_supply as u64),
+            ("food_spawn_rate", self.food_spawn_rate),
+        ];
+        for (key, value) in counts {
+            if value > MAX_POPULATION {
+                return Err(format!("{key} must be at most {MAX_POPULATION}, not {value}").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parameters_are_set_by_name() {
+        let mut config = Config::default();
+        config.set("eat_diff", json!(2.0)).unwrap();
+        config.set("initial_num_npcs", json!(4)).unwrap();
+        assert_eq!(config.eat_diff, 2.0);
+        assert_eq!(config.initial_num_npcs, 4);
+    }
+
+    #[test]
+    fn unknown_parameters_and_wrong_types_are_refused() {
+        let mut config = Config::default();
+        let e = config.set("gravity", json!(9.81)).unwrap_err();
+        assert_eq!(e.to_string(), "unknown config parameter `gravity`");
+        assert!(config.set("eat_diff", json!("2")).is_err());
+        assert!(config.set("initial_num_npcs", json!(-1)).is_err());
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn configs_a_microbiome_cant_run_with_are_refused() {
+        let mut config = Config::default();
+        for (key, value) in [
+            ("size", json!(5.0)),
+            ("base_speed", json!(-1.0)),
+            ("food_perception_radius", json!(-0.5)),
+            ("initial_food_supply", json!(MAX_POPULATION + 1)),
+            ("food_spawn_rate", json!(u64::MAX)),
+        ] {
+            assert!(config.set(key, value.clone()).is_err(), "{key} = {value}");
+        }
+        assert_eq!(config, Config::default());
+
+        // JSON has no NaN, but a config can still be built with one
+        let config = Config {
+            eat_diff: f64::NAN,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(Config::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+//! Commands for driving a running simulator over its REP socket
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use ts_rs::TS;
+
+use super::{Action, Config, Snapshot, Stats};
+
+/// Kinds of entities that can be spawned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum EntityKind {
+    Npc,
+    Food,
+}
+
+/// Commands accepted on the simulator's control socket
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Command {
+    /// Check that both sides speak the same protocol version, answered with the simulator's version
+    Hello {
+        version: u32,
+    },
+    Pause,
+    Resume,
+    /// Advance a number of ticks right away, even when paused, at most 10000 at once
+    Step {
+        #[serde(default = "one")]
+        ticks: u64,
+    },
+    /// Start over from the current config, with a random seed if none is given
+    Reset {
+        #[ts(optional = nullable)]
+        seed: Option<u64>,
+    },
+    /// Spawn an entity, at a random position and mass unless given
+    Spawn {
+        entity: EntityKind,
+        #[ts(optional = nullable)]
+        pos: Option<[f64; 2]>,
+        #[ts(optional = nullable)]
+        mass: Option<f64>,
+    },
+    Remove {
+        id: u64,
+    },
+    /// Current population and mass metrics
+    GetStats,
+    GetConfig,
+    /// Change config values by name. `size` and the initial populations apply on the next reset.
+    SetConfig {
+        values: Map<String, Value>,
+    },
+    TakeSnapshot,
+    LoadSnapshot {
+        snapshot: Box<Snapshot>,
+    },
+    /// Spawn a cell for a player, answered with the player's owner id
+    Join {
+        name: String,
+    },
+    /// Set the action of every cell of a player
+    Steer {
+        owner: u64,
+        action: Action,
+    },
+    /// Split every cell of a player that is big enough in two
+    Split {
+        owner: u64,
+    },
+    /// Eject a bit of mass from every cell of a player that is big enough
+    Eject {
+        owner: u64,
+    },
+    /// Remove every cell of a player
+    Leave {
+        owner: u64,
+    },
+}
+
+fn one() -> u64 {
+    1
+}
+
+/// Replies sent back for every [`Command`]
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum Reply {
+    Hello { version: u32 },
+    Status { tick: u64, paused: bool },
+    Spawned { id: u64 },
+    Stats { stats: Stats },
+    Config { config: Config },
+    Snapshot { snapshot: Box<Snapshot> },
+    Error { message: String },
+}
+
+impl Reply {
+    pub fn error(message: impl ToString) -> Self {
+        Reply::Error {
+            message: message.to_string(),
+        }
+    }
+}
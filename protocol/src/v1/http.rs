@@ -0,0 +1,74 @@
+//! Bodies of the server's HTTP API
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// A simulator served by the server, listed by `GET /rooms`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct RoomInfo {
+    /// Name to connect to at `/ws/{name}`
+    pub name: String,
+    /// Whether the simulator is publishing
+    pub online: bool,
+    /// Tick of the last frame, if any arrived yet
+    pub tick: Option<u64>,
+    /// Websockets currently in the room
+    pub clients: u64,
+    /// Whether browsers can join as players
+    pub players: bool,
+    /// Id of the recording of the room, while it is being recorded
+    pub recording: Option<u64>,
+}
+
+/// Whether a room's simulator is paused, answered by the `/api/control` endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+pub struct SimState {
+    pub tick: u64,
+    pub paused: bool,
+}
+
+/// Optional body of `POST /api/control/step`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct StepRequest {
+    /// Ticks to advance, 1 if omitted
+    #[ts(optional = nullable)]
+    pub ticks: Option<u64>,
+}
+
+/// Optional body of `POST /api/control/reset`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct ResetRequest {
+    /// Seed to start over with, random if omitted
+    #[ts(optional = nullable)]
+    pub seed: Option<u64>,
+}
+
+/// A snapshot kept by the server, answered by `POST /api/snapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+pub struct SnapshotInfo {
+    /// Id to fetch it with from `GET /api/snapshot/{id}`
+    pub id: u64,
+    pub tick: u64,
+}
+
+/// Body of every failed API request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct ApiError {
+    pub message: String,
+}
+
+/// A recording of a room, listed by `GET /api/recordings` and played back at `/ws/replay/{id}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct RecordingInfo {
+    pub id: u64,
+    pub room: String,
+    /// Unix time in milliseconds
+    pub started_at: u64,
+    pub frames: u64,
+    /// Time of the last frame since the start
+    pub duration_ms: u64,
+    /// Whether the recording was stopped, as opposed to still running or cut short
+    pub finished: bool,
+}
@@ -0,0 +1,15 @@
+//! Version 1 of the protocol
+
+pub mod agents;
+pub mod config;
+pub mod control;
+pub mod http;
+pub mod sim;
+mod world;
+pub mod ws;
+
+pub use config::Config;
+pub use world::{
+    Action, Body, Event, FoodState, NpcKind, NpcState, Observation, RngState, Snapshot, Stats,
+    WorldState,
+};
@@ -0,0 +1,111 @@
+//! Messages published by the simulator on its PUB socket
+
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{Event, Stats, WorldState};
+use crate::PROTOCOL_VERSION;
+
+/// Topic messages are published on unless the simulator is given another one
+pub const TOPIC: &str = "mb_state";
+
+/// Published as three frames: the topic, the protocol version and the JSON message
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "msg", rename_all = "snake_case")]
+pub enum SimMessage {
+    /// The world after a step
+    State { state: WorldState },
+    /// What happened during the step before that state, published when anything did
+    Events { events: Vec<Event> },
+    /// How the simulator is doing, published about once a second
+    Metrics {
+        stats: Stats,
+        /// Average time a step took since the last report, 0 if it was paused throughout
+        tick_seconds: f64,
+    },
+}
+
+impl SimMessage {
+    pub fn to_frames(&self, topic: &str) -> Result<[Vec<u8>; 3], serde_json::Error> {
+        Ok([
+            topic.as_bytes().to_vec(),
+            PROTOCOL_VERSION.to_string().into_bytes(),
+            serde_json::to_vec(self)?,
+        ])
+    }
+
+    /// Read a message from its frames, refusing messages of other topics and protocol versions
+    ///
+    /// Subscriptions match topics by prefix, so a subscriber to `mb_state` also gets `mb_state_2`.
+    pub fn from_frames(frames: &[Vec<u8>], topic: &str) -> Result<Self, Box<dyn Error>> {
+        let [msg_topic, version, payload] = frames else {
+            return Err(format!("expected 3 frames, got {}", frames.len()).into());
+        };
+        if msg_topic != topic.as_bytes() {
+            return Err(format!("unexpected topic {}", String::from_utf8_lossy(msg_topic)).into());
+        }
+        let version = String::from_utf8_lossy(version);
+        if version != PROTOCOL_VERSION.to_string() {
+            return Err(format!(
+                "simulator speaks protocol version {version}, expected {PROTOCOL_VERSION}"
+            )
+            .into());
+        }
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics() -> SimMessage {
+        SimMessage::Metrics {
+            stats: Stats {
+                tick: 12,
+                npcs: 3,
+                ..Stats::default()
+            },
+            tick_seconds: 0.25,
+        }
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = metrics().to_frames(TOPIC).unwrap();
+        assert_eq!(frames[0], TOPIC.as_bytes());
+        assert_eq!(frames[1], PROTOCOL_VERSION.to_string().as_bytes());
+        match SimMessage::from_frames(&frames, TOPIC).unwrap() {
+            SimMessage::Metrics {
+                stats,
+                tick_seconds,
+            } => {
+                assert_eq!(stats.tick, 12);
+                assert_eq!(stats.npcs, 3);
+                assert_eq!(tick_seconds, 0.25);
+            }
+            msg => panic!("unexpected {msg:?}"),
+        }
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let mut frames = metrics().to_frames(TOPIC).unwrap();
+        frames[1] = (PROTOCOL_VERSION + 1).to_string().into_bytes();
+        let e = SimMessage::from_frames(&frames, TOPIC).unwrap_err();
+        assert!(e.to_string().contains("protocol version"), "{e}");
+    }
+
+    #[test]
+    fn other_topics_and_malformed_frames_are_refused() {
+        let frames = metrics().to_frames("mb_state_2").unwrap();
+        assert!(SimMessage::from_frames(&frames, TOPIC).is_err());
+        assert!(SimMessage::from_frames(&frames[..2], "mb_state_2").is_err());
+
+        let mut frames = metrics().to_frames(TOPIC).unwrap();
+        frames[2] = b"{\"msg\": \"nothing\"}".to_vec();
+        assert!(SimMessage::from_frames(&frames, TOPIC).is_err());
+    }
+}
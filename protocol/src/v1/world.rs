@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::Config;
+
+/// Everything needed to draw the biome after a step
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct WorldState {
+    pub tick: u64,
+    /// Width and height of the biome
+    pub size: f64,
+    /// How far cells see other cells
+    pub cell_perception_radius: f64,
+    /// How far cells see food
+    pub food_perception_radius: f64,
+    pub npcs: Vec<NpcState>,
+    pub food: Vec<FoodState>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct NpcState {
+    pub id: u64,
+    pub pos: [f64; 2],
+    pub mass: f64,
+    pub radius: f64,
+    pub color: String,
+    pub kind: NpcKind,
+}
+
+/// How an NPC decides where to go
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NpcKind {
+    Linear {
+        dir: [f64; 2],
+    },
+    Advanced {
+        dir: [f64; 2],
+    },
+    /// Moved by an agent or player outside the simulator
+    Controlled {
+        owner: u64,
+        name: Option<String>,
+        action: Action,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct FoodState {
+    pub id: u64,
+    pub pos: [f64; 2],
+    pub mass: f64,
+    pub radius: f64,
+    pub color: String,
+}
+
+/// How an externally controlled cell should move
+///
+/// A `target` takes precedence over a `dir`. A cell with neither stays put.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+#[serde(default)]
+pub struct Action {
+    /// Direction to move in, normalized by the simulator
+    #[ts(optional = nullable)]
+    pub dir: Option<[f64; 2]>,
+    /// Point in the biome to move towards
+    #[ts(optional = nullable)]
+    pub target: Option<[f64; 2]>,
+}
+
+/// A cell or piece of food as seen by an observer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+pub struct Body {
+    pub id: u64,
+    pub pos: [f64; 2],
+    pub mass: f64,
+    pub radius: f64,
+}
+
+/// What an externally controlled cell perceives, using the same circles as NPCs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct Observation {
+    pub tick: u64,
+    /// The observer's own cells, largest first
+    pub cells: Vec<Body>,
+    /// Other cells within the cell perception radius of the largest own cell
+    pub npcs: Vec<Body>,
+    /// Food within the food perception radius of the largest own cell
+    pub food: Vec<Body>,
+}
+
+/// Complete state of a microbiome, enough to restore it exactly
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct Snapshot {
+    pub config: Config,
+    pub rng: RngState,
+    pub next_id: u64,
+    pub elapsed: u64,
+    pub npcs: Vec<NpcState>,
+    pub food: Vec<FoodState>,
+}
+
+impl Snapshot {
+    /// Tick at which the snapshot was taken
+    pub fn tick(&self) -> u64 {
+        self.elapsed
+    }
+}
+
+/// Position of the simulator's random number generator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TS)]
+pub struct RngState {
+    pub seed: [u8; 32],
+    pub stream: u64,
+    pub word_pos: u64,
+}
+
+/// Population and mass metrics of the microbiome at a point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, TS)]
+pub struct Stats {
+    pub tick: u64,
+    pub npcs: usize,
+    pub food: usize,
+    pub npc_mass: f64,
+    pub max_npc_mass: f64,
+    pub food_mass: f64,
+}
+
+/// Something notable that happened during a step of the microbiome
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A cell ate a piece of food
+    AteFood {
+        tick: u64,
+        cell: u64,
+        food: u64,
+        mass: f64,
+    },
+    /// A cell ate another cell
+    AteCell {
+        tick: u64,
+        cell: u64,
+        prey: u64,
+        mass: f64,
+    },
+    /// Only one cell is left standing
+    Monopoly { tick: u64, cell: u64 },
+}
@@ -0,0 +1,196 @@
+//! Messages between browsers and the server, sent as `{"event": ..., "data": ...}` JSON
+//!
+//! Browsers can ask for [`MSGPACK_SUBPROTOCOL`] instead, to get the same messages as MessagePack in
+//! binary frames.
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{Event, FoodState, NpcState, Stats, WorldState};
+
+/// Websocket subprotocol for messages as JSON in text frames, the default
+pub const JSON_SUBPROTOCOL: &str = "mb.json.v1";
+
+/// Websocket subprotocol for messages as MessagePack in binary frames, with 32 bit floats
+pub const MSGPACK_SUBPROTOCOL: &str = "mb.msgpack.v1";
+
+/// Messages sent by browsers
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// First message of every connection, answering the server's `hello`
+    Hello {
+        version: u32,
+    },
+    /// Spawn a cell controlled by this connection
+    Join {
+        name: String,
+    },
+    /// Move this connection's cells towards a point in the biome
+    Steer {
+        target: [f64; 2],
+    },
+    Split,
+    Eject,
+    /// Remove this connection's cells
+    Leave,
+    /// Only send the part of the biome inside this viewport, as `view` messages
+    View(Viewport),
+    /// Keep the viewport centered on an entity, or on every cell of an owner, until the `id` is null
+    Follow {
+        #[ts(optional = nullable)]
+        id: Option<u64>,
+    },
+    /// Only send what the followed cells perceive, the way the simulator sees it for them
+    Fog {
+        enabled: bool,
+    },
+    /// Go back to receiving the whole biome
+    ClearView,
+    /// Only send these kinds of messages, and frames at most `max_fps` times a second
+    ///
+    /// Clients that never subscribe get every `state` and nothing else.
+    Subscribe {
+        /// Unlimited if null
+        #[ts(optional = nullable)]
+        max_fps: Option<f64>,
+        kinds: Vec<MessageKind>,
+    },
+    /// Play a replay from where it is, or from the start once it ended
+    Play,
+    Pause,
+    /// Jump to a time of a replay
+    Seek {
+        time_ms: u64,
+    },
+    /// Play a replay faster or slower than it was recorded
+    Speed {
+        speed: f64,
+    },
+}
+
+/// Messages a browser can subscribe to, besides those it always gets such as `sim_status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    /// `state`, or `view` once the browser sent a viewport
+    State,
+    /// `events`, never dropped to keep to the frame rate
+    Events,
+    Stats,
+    Leaderboard,
+}
+
+/// Part of the biome a browser is looking at
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+pub struct Viewport {
+    /// Point of the biome in the middle of the view
+    pub center: [f64; 2],
+    /// Screen pixels per unit of the biome
+    pub zoom: f64,
+    /// Width and height of the view in screen pixels
+    pub size: [f64; 2],
+}
+
+impl Viewport {
+    /// Corners of the view in the biome, top left first
+    pub fn bounds(&self) -> ([f64; 2], [f64; 2]) {
+        let half = [
+            self.size[0] / self.zoom / 2.0,
+            self.size[1] / self.zoom / 2.0,
+        ];
+        (
+            [self.center[0] - half[0], self.center[1] - half[1]],
+            [self.center[0] + half[0], self.center[1] + half[1]],
+        )
+    }
+}
+
+/// Entities inside a browser's viewport, with positions and radii in screen pixels from its top left corner
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct ViewState {
+    pub tick: u64,
+    /// Width and height of the biome
+    pub size: f64,
+    /// Point of the biome at the top left corner of the view
+    pub origin: [f64; 2],
+    /// Screen pixels per unit of the biome
+    pub zoom: f64,
+    /// Perception of each followed cell, when in fog of war
+    pub perception: Vec<Perception>,
+    pub npcs: Vec<NpcState>,
+    pub food: Vec<FoodState>,
+}
+
+/// What a cell perceives, in screen pixels like the rest of a [`ViewState`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, TS)]
+pub struct Perception {
+    pub pos: [f64; 2],
+    /// Radius within which it sees other cells
+    pub cells: f64,
+    /// Radius within which it sees food
+    pub food: f64,
+}
+
+/// Messages sent by the server
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message of every connection, carrying the server's protocol version
+    Hello {
+        version: u32,
+    },
+    State(WorldState),
+    /// Replaces `state` once the browser sent a viewport
+    View(ViewState),
+    /// What happened during a step
+    Events(Vec<Event>),
+    /// Population and mass of a frame
+    Stats(Stats),
+    /// Heaviest players and cells of a frame, heaviest first
+    Leaderboard(Vec<LeaderboardEntry>),
+    /// Whether the simulator is publishing, sent on connect and whenever that changes
+    SimStatus {
+        online: bool,
+        /// Tick of the last frame, if any arrived yet
+        tick: Option<u64>,
+    },
+    /// Where a replay is, sent on connect and whenever that changes other than by playing on
+    ReplayStatus {
+        id: u64,
+        time_ms: u64,
+        duration_ms: u64,
+        playing: bool,
+        speed: f64,
+    },
+    /// The connection controls the cells of `owner` now
+    Joined {
+        owner: u64,
+    },
+    /// The connection's cells are gone
+    Left {
+        reason: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// A player with all of its cells, or a cell nobody controls
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+pub struct LeaderboardEntry {
+    /// Owner of a player's cells, or id of the cell
+    pub id: u64,
+    #[ts(optional = nullable)]
+    pub name: Option<String>,
+    pub mass: f64,
+    pub cells: usize,
+}
+
+impl ServerMessage {
+    pub fn error(message: impl ToString) -> Self {
+        ServerMessage::Error {
+            message: message.to_string(),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Checks that the TypeScript bindings of the UI are what the protocol generates
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Every file under `dir` by its path relative to `dir`
+fn read_tree(dir: &Path) -> BTreeMap<PathBuf, String> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(next) = dirs.pop() {
+        for entry in fs::read_dir(next).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let text = fs::read_to_string(&path).unwrap();
+                files.insert(path.strip_prefix(dir).unwrap().to_path_buf(), text);
+            }
+        }
+    }
+    files
+}
+
+#[test]
+fn bindings_are_up_to_date() {
+    let out_dir = std::env::temp_dir().join(format!("mb-ts-bindings-{}", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_ts-bindings"))
+        .arg(&out_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let generated = read_tree(&out_dir);
+    fs::remove_dir_all(&out_dir).unwrap();
+    let checked_in = read_tree(&Path::new(env!("CARGO_MANIFEST_DIR")).join("../ui/src/protocol"));
+
+    let names = |files: &BTreeMap<PathBuf, String>| files.keys().cloned().collect::<Vec<_>>();
+    assert_eq!(
+        names(&checked_in),
+        names(&generated),
+        "bindings were added or removed, run `cargo run -p microbiome-protocol --bin ts-bindings`"
+    );
+    for (path, text) in &generated {
+        assert!(
+            checked_in[path] == *text,
+            "{} is out of date, run `cargo run -p microbiome-protocol --bin ts-bindings`",
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! JSON API over the control interface of a room's simulator
+//!
+//! Every endpoint acts on the default room, or on the one named by a `room` query parameter. Those
+//! that change anything take the admin role.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use protocol::{
+    control::{Command, Reply},
+    http::{ApiError, RecordingInfo, ResetRequest, SimState, SnapshotInfo, StepRequest},
+    Config, Snapshot, Stats,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::{auth::Admin, record, room::Room, shutdown, state::AppState};
+
+/// A failed request, answered with its status and an [`ApiError`] body
+pub struct Error {
+    status: StatusCode,
+    message: String,
+}
+
+impl Error {
+    pub fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let body = ApiError {
+            message: self.message,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, Error>;
+
+/// How long `/api/state/latest?after=` waits for another frame before answering that there is none
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+pub struct RoomQuery {
+    room: Option<String>,
+}
+
+impl RoomQuery {
+    fn room(&self, state: &AppState) -> Result<Arc<Room>, Error> {
+        match &self.room {
+            Some(name) => state
+                .room(name)
+                .cloned()
+                .ok_or_else(|| Error::new(StatusCode::NOT_FOUND, format!("no room named {name}"))),
+            None => Ok(Arc::clone(state.default_room())),
+        }
+    }
+}
+
+/// Send a command to the room's simulator, turning refusals and an unreachable simulator into errors
+async fn request(room: &Room, cmd: Command) -> Result<Reply, Error> {
+    let Some(control) = &room.control else {
+        return Err(Error::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("room {} has no control socket", room.name),
+        ));
+    };
+    match control.request(cmd).await {
+        Ok(Reply::Error { message }) => Err(Error::new(StatusCode::BAD_REQUEST, message)),
+        Ok(reply) => Ok(reply),
+        Err(e) => Err(Error::new(StatusCode::SERVICE_UNAVAILABLE, e)),
+    }
+}
+
+fn unexpected(reply: Reply) -> Error {
+    Error::new(
+        StatusCode::BAD_GATEWAY,
+        format!("unexpected reply from the simulator: {reply:?}"),
+    )
+}
+
+async fn status(room: &Room, cmd: Command) -> ApiResult<SimState> {
+    match request(room, cmd).await? {
+        Reply::Status { tick, paused } => Ok(Json(SimState { tick, paused })),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+pub async fn stats_handler(
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Stats> {
+    let room = query.room(&state)?;
+    match request(&room, Command::GetStats).await? {
+        Reply::Stats { stats } => Ok(Json(stats)),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LatestQuery {
+    /// Tick the client already has, to wait for a frame of another one
+    after: Option<u64>,
+}
+
+/// The room's latest frame, or with `after` the first frame of another tick, answering 204 when
+/// none arrives in time
+pub async fn latest_state_handler(
+    Query(query): Query<RoomQuery>,
+    Query(latest): Query<LatestQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, Error> {
+    let room = query.room(&state)?;
+    let mut frames = room.subscribe_frames();
+    let world = match latest.after {
+        None => frames.borrow().world.clone(),
+        Some(after) => {
+            let newer = frames.wait_for(|frame| {
+                // Ticks start over when the simulator resets, so any other tick is newer
+                frame
+                    .world
+                    .as_ref()
+                    .is_some_and(|x| x.state().tick != after)
+            });
+            let mut shutdown = state.shutdown.clone();
+            tokio::select! {
+                _ = shutdown::requested(&mut shutdown) => None,
+                frame = tokio::time::timeout(LONG_POLL_TIMEOUT, newer) => match frame {
+                    Ok(Ok(frame)) => frame.world.clone(),
+                    _ => None,
+                },
+            }
+        }
+    };
+    match (world, latest.after) {
+        (Some(world), _) => Ok(Json(world.state()).into_response()),
+        (None, Some(_)) => Ok(StatusCode::NO_CONTENT.into_response()),
+        (None, None) => Err(Error::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("room {} has no frame yet", room.name),
+        )),
+    }
+}
+
+async fn config(room: &Room, cmd: Command) -> ApiResult<Config> {
+    match request(room, cmd).await? {
+        Reply::Config { config } => Ok(Json(config)),
+        reply => Err(unexpected(reply)),
+    }
+}
+
+pub async fn get_config_handler(
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Config> {
+    let room = query.room(&state)?;
+    config(&room, Command::GetConfig).await
+}
+
+/// Change the config values named in the body, answering with the whole config
+///
+/// Values the simulator couldn't run with are refused before they reach it.
+pub async fn patch_config_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+    Json(values): Json<Map<String, Value>>,
+) -> ApiResult<Config> {
+    let room = query.room(&state)?;
+    let Json(mut patched) = config(&room, Command::GetConfig).await?;
+    for (key, value) in &values {
+        patched
+            .set(key, value.clone())
+            .map_err(|e| Error::new(StatusCode::BAD_REQUEST, format!("invalid {key}: {e}")))?;
+    }
+    config(&room, Command::SetConfig { values }).await
+}
+
+pub async fn pause_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<SimState> {
+    let room = query.room(&state)?;
+    status(&room, Command::Pause).await
+}
+
+pub async fn resume_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<SimState> {
+    let room = query.room(&state)?;
+    status(&room, Command::Resume).await
+}
+
+/// Read a JSON body that may be left out, for its defaults
+fn optional_body<T: DeserializeOwned + Default>(body: &Bytes) -> Result<T, Error> {
+    if body.is_empty() {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body)
+        .map_err(|e| Error::new(StatusCode::BAD_REQUEST, format!("invalid body: {e}")))
+}
+
+pub async fn step_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+    body: Bytes,
+) -> ApiResult<SimState> {
+    let body: StepRequest = optional_body(&body)?;
+    let ticks = body.ticks.unwrap_or(1);
+    let room = query.room(&state)?;
+    status(&room, Command::Step { ticks }).await
+}
+
+pub async fn reset_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+    body: Bytes,
+) -> ApiResult<SimState> {
+    let body: ResetRequest = optional_body(&body)?;
+    let room = query.room(&state)?;
+    status(&room, Command::Reset { seed: body.seed }).await
+}
+
+/// Take a snapshot of the simulator and keep it on the server
+pub async fn take_snapshot_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<SnapshotInfo>), Error> {
+    let room = query.room(&state)?;
+    let snapshot = match request(&room, Command::TakeSnapshot).await? {
+        Reply::Snapshot { snapshot } => *snapshot,
+        reply => return Err(unexpected(reply)),
+    };
+    let tick = snapshot.tick();
+    let id = room.keep_snapshot(snapshot);
+    Ok((StatusCode::CREATED, Json(SnapshotInfo { id, tick })))
+}
+
+pub async fn get_snapshot_handler(
+    Path(id): Path<u64>,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Snapshot> {
+    match query.room(&state)?.snapshot(id) {
+        Some(snapshot) => Ok(Json(Snapshot::clone(&snapshot))),
+        None => Err(Error::new(
+            StatusCode::NOT_FOUND,
+            format!("no snapshot with id {id}"),
+        )),
+    }
+}
+
+/// Start recording the room's frames to disk
+pub async fn start_recording_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<(StatusCode, Json<RecordingInfo>), Error> {
+    let room = query.room(&state)?;
+    match room.start_recording(&state.recordings).await {
+        Ok(info) => Ok((StatusCode::CREATED, Json(info))),
+        Err(e) => Err(Error::new(StatusCode::CONFLICT, e)),
+    }
+}
+
+pub async fn stop_recording_handler(
+    _: Admin,
+    Query(query): Query<RoomQuery>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<RecordingInfo> {
+    let room = query.room(&state)?;
+    match room.stop_recording().await {
+        Some(Ok(info)) => Ok(Json(info)),
+        Some(Err(e)) => Err(Error::new(StatusCode::INTERNAL_SERVER_ERROR, e)),
+        None => Err(Error::new(
+            StatusCode::CONFLICT,
+            format!("room {} is not being recorded", room.name),
+        )),
+    }
+}
+
+pub async fn recordings_handler(
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<Vec<RecordingInfo>> {
+    match record::list(&state.recordings) {
+        Ok(recordings) => Ok(Json(recordings)),
+        Err(e) => Err(Error::new(StatusCode::INTERNAL_SERVER_ERROR, e)),
+    }
+}
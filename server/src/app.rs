@@ -1,36 +1,160 @@
-use std::{error::Error, path::PathBuf, str::FromStr, sync::Arc};
+use std::{error::Error, io, net::SocketAddr, sync::Arc};
 
-use axum::routing::get;
-use tokio::task::JoinHandle;
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
 use tower_http::{
-    cors::CorsLayer,
+    cors::{AllowOrigin, Any, CorsLayer},
     services::{ServeDir, ServeFile},
     trace::TraceLayer,
 };
 
-use crate::{bind::mb_bind, config::Config, state::AppState, ws::ws_handler};
+use crate::{
+    api,
+    auth::redact,
+    bind::mb_bind,
+    config::{Config, Cors},
+    control::{Request, SimControl},
+    metrics::metrics_handler,
+    replay::replay_handler,
+    room::{rooms_handler, Room},
+    shutdown,
+    sse::{room_sse_handler, sse_handler},
+    state::AppState,
+    ws::{room_ws_handler, ws_handler},
+};
 
-pub fn make_app(config: &Config) -> Result<(axum::Router, JoinHandle<()>), Box<dyn Error>> {
-    let static_dir = config.static_path.clone();
-    let static_path = PathBuf::from_str(&static_dir)?;
-    let serve_static = ServeDir::new(static_path.clone())
+/// Build the router and start feeding the rooms, until `shutdown` turns true
+pub fn make_app(
+    config: &Config,
+    shutdown: watch::Receiver<bool>,
+) -> Result<(Router, JoinHandle<()>), Box<dyn Error>> {
+    let static_path = &config.static_path;
+    let serve_static = ServeDir::new(static_path)
         .not_found_service(ServeFile::new(static_path.join("index.html")));
 
-    tracing::debug!("serving static {}", static_dir);
+    tracing::debug!("serving static {}", static_path.display());
 
-    let state = AppState::new();
+    let mut rooms = Vec::with_capacity(config.rooms.len());
+    // Commands for the simulators running inside the server
+    let mut embedded: Vec<Option<mpsc::Receiver<Request>>> = Vec::with_capacity(config.rooms.len());
+    for room in &config.rooms {
+        let (control, commands) = match &room.control_at {
+            #[cfg(feature = "embedded")]
+            _ if room.embedded => {
+                let (control, commands) = SimControl::channel();
+                (Some(control), Some(commands))
+            }
+            Some(control_at) => (Some(SimControl::connect(control_at)?), None),
+            None => (None, None),
+        };
+        rooms.push(Room::new(room.name.clone(), control));
+        embedded.push(commands);
+    }
+    let state = AppState::new(
+        rooms,
+        config.recordings_path.clone(),
+        config.limits,
+        config.access.clone(),
+        shutdown.clone(),
+    );
 
-    let config_clone = config.clone();
-    let state_clone = Arc::clone(&state);
-    let mb_handler = tokio::task::spawn_blocking(move || mb_bind(config_clone, state_clone));
+    let mut mb_handlers = Vec::with_capacity(config.rooms.len());
+    for ((room_config, room), commands) in config.rooms.iter().zip(&state.rooms).zip(embedded) {
+        let handler = match commands {
+            #[cfg(feature = "embedded")]
+            Some(commands) => {
+                crate::embedded::spawn(room_config, Arc::clone(room), commands, shutdown.clone())?
+            }
+            _ => mb_bind(room_config, Arc::clone(room), shutdown.clone())?,
+        };
+        mb_handlers.push(handler);
+    }
+    let rooms = state.rooms.clone();
+    let mb_handler = tokio::spawn(async move {
+        for handler in futures::future::join_all(mb_handlers).await {
+            if let Err(e) = handler {
+                tracing::error!("microbiome task died: {}", e);
+            }
+        }
+        // No more frames are coming, so recordings can be closed
+        for room in rooms {
+            match room.stop_recording().await {
+                Some(Ok(info)) => {
+                    tracing::info!("saved recording {} of room {}", info.id, room.name)
+                }
+                Some(Err(e)) => {
+                    tracing::error!("failed to save the recording of room {}: {}", room.name, e)
+                }
+                None => (),
+            }
+        }
+    });
 
-    let app = axum::Router::new()
+    let app = Router::new()
         .route("/ping", get(|| async { "pong" }))
         .route("/ws", get(ws_handler))
+        .route("/ws/:room", get(room_ws_handler))
+        .route("/sse", get(sse_handler))
+        .route("/sse/:room", get(room_sse_handler))
+        .route("/rooms", get(rooms_handler))
+        .route("/api/stats", get(api::stats_handler))
+        .route("/api/state/latest", get(api::latest_state_handler))
+        .route(
+            "/api/config",
+            get(api::get_config_handler).patch(api::patch_config_handler),
+        )
+        .route("/api/control/pause", post(api::pause_handler))
+        .route("/api/control/resume", post(api::resume_handler))
+        .route("/api/control/step", post(api::step_handler))
+        .route("/api/control/reset", post(api::reset_handler))
+        .route("/api/snapshot", post(api::take_snapshot_handler))
+        .route("/api/snapshot/:id", get(api::get_snapshot_handler))
+        .route("/api/record/start", post(api::start_recording_handler))
+        .route("/api/record/stop", post(api::stop_recording_handler))
+        .route("/api/recordings", get(api::recordings_handler))
+        .route("/ws/replay/:id", get(replay_handler))
+        .route("/metrics", get(metrics_handler))
         .with_state(state)
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::very_permissive())
+        .layer(
+            // Like the default span, without the tokens clients put in URIs
+            TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| {
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %redact(request.uri()),
+                    version = ?request.version(),
+                )
+            }),
+        )
+        .layer(match &config.cors {
+            Cors::Any => CorsLayer::very_permissive(),
+            Cors::Origins(origins) => CorsLayer::new()
+                .allow_origin(AllowOrigin::list(origins.iter().cloned()))
+                .allow_methods(Any)
+                .allow_headers(Any),
+        })
         .fallback_service(serve_static);
 
     Ok((app, mb_handler))
 }
+
+/// Serve the app on `listener` until `shutdown` turns true
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move { shutdown::requested(&mut shutdown).await })
+    .await
+}
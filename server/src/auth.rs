@@ -0,0 +1,140 @@
+//! Who may do what, going by the token a request presents
+//!
+//! Requests present their token as a bearer `Authorization` header or, for browsers opening
+//! websockets, a `token` query parameter. Requests without one get the anonymous role.
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts, StatusCode, Uri},
+};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{api::Error, state::AppState};
+
+/// What a client may do, each role allowed what the ones before it are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Watches rooms
+    Spectator,
+    /// Joins rooms and steers cells
+    Player,
+    /// Controls simulators, changes their config, takes snapshots and records rooms
+    Admin,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Spectator => "spectator",
+            Role::Player => "player",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Roles of the configured tokens
+#[derive(Debug, Clone)]
+pub struct Access {
+    tokens: HashMap<String, Role>,
+    /// Role of requests without a token
+    pub anonymous: Role,
+}
+
+impl Access {
+    pub fn new(tokens: HashMap<String, Role>, anonymous: Role) -> Self {
+        Self { tokens, anonymous }
+    }
+
+    /// Role of a request presenting `token`, none if the token is unknown
+    pub fn role(&self, token: Option<&str>) -> Option<Role> {
+        match token {
+            Some(token) => self.tokens.get(token).copied(),
+            None => Some(self.anonymous),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Role of whoever made the request, which is refused if its token is unknown
+pub struct Caller(pub Role);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Caller {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let bearer = match parts.headers.get(header::AUTHORIZATION) {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|x| x.strip_prefix("Bearer "))
+                    .ok_or_else(|| Error::new(StatusCode::UNAUTHORIZED, "expected a bearer token"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        let token = match bearer {
+            Some(token) => Some(token),
+            None => {
+                Query::<TokenQuery>::try_from_uri(&parts.uri)
+                    .map_err(|e| Error::new(StatusCode::BAD_REQUEST, e.body_text()))?
+                    .0
+                    .token
+            }
+        };
+        match state.access.role(token.as_deref()) {
+            Some(role) => Ok(Caller(role)),
+            None => Err(Error::new(StatusCode::UNAUTHORIZED, "unknown token")),
+        }
+    }
+}
+
+/// A request by an admin, refused for any other role
+pub struct Admin;
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Admin {
+    type Rejection = Error;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        match Caller::from_request_parts(parts, state).await? {
+            Caller(Role::Admin) => Ok(Admin),
+            Caller(role) => Err(Error::new(
+                StatusCode::FORBIDDEN,
+                format!("this takes the admin role, not {}", role.name()),
+            )),
+        }
+    }
+}
+
+/// `uri` with the value of its `token` query parameter hidden, for logs
+pub fn redact(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let query = query
+        .split('&')
+        .map(|x| match x.split_once('=') {
+            Some(("token", _)) => "token=redacted",
+            _ => x,
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{query}", uri.path())
+}
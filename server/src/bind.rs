@@ -1,99 +1,144 @@
-use axum::extract::ws::Message;
-use serde_json::json;
+use protocol::{sim::SimMessage, WorldState};
 use std::error::Error;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::{atomic::Ordering, Arc};
+use std::thread;
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
 
-use crate::config::Config;
-use crate::state::AppState;
+use crate::config::RoomConfig;
+use crate::room::{Room, SimMetrics};
+use crate::shutdown;
 
-fn load_socks(config: &Config) -> Result<zmq::Socket, Box<dyn Error>> {
+/// How long the simulator may go without publishing before clients are told it went silent
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the receiving thread blocks before checking whether the server is still listening
+const RECV_TIMEOUT_MS: i32 = 100;
+
+fn load_socks(config: &RoomConfig) -> Result<zmq::Socket, Box<dyn Error>> {
     let context = zmq::Context::new();
     let sub_sock = context.socket(zmq::SUB)?;
-    sub_sock.set_subscribe("mb_state".as_bytes())?;
-    sub_sock.connect(&config.sub_at)?;
+    sub_sock.set_subscribe(config.topic.as_bytes())?;
+    sub_sock.set_rcvtimeo(RECV_TIMEOUT_MS)?;
+    let sub_at = config.sub_at.as_deref().ok_or("room has no sub_at")?;
+    sub_sock.connect(sub_at)?;
 
-    tracing::debug!("microbiome sock listening at {}", config.sub_at);
+    tracing::debug!(
+        "microbiome sock for room {} listening at {}",
+        config.name,
+        sub_at
+    );
 
     Ok(sub_sock)
 }
 
-pub fn build_websocket_msg(msgb: Vec<Vec<u8>>) -> Result<Option<Message>, Box<dyn Error>> {
-    let cmd_str = String::from_utf8(msgb[1].to_vec())?;
-
-    tracing::debug!(cmd_str);
-
-    let json_msg = match cmd_str.as_str() {
-        "state" => {
-            let value = serde_json::from_slice::<serde_json::Value>(&msgb[2])?;
-
-            json!({
-                "event": cmd_str,
-                "data": value,
-            })
+/// Hand every message of the simulator over to the runtime
+///
+/// zmq sockets block and can't move between tasks, so they get a thread of their own.
+fn recv_messages(sub_sock: zmq::Socket, tx: mpsc::Sender<Vec<Vec<u8>>>) {
+    while !tx.is_closed() {
+        match sub_sock.recv_multipart(0) {
+            Ok(msgb) => {
+                if tx.blocking_send(msgb).is_err() {
+                    break;
+                }
+            }
+            Err(zmq::Error::EAGAIN) => (),
+            Err(e) => tracing::error!("failed to receive from microbiome: {}", e),
         }
-        _ => return Ok(None),
-    };
-
-    let msgs = serde_json::to_string(&json_msg)?;
-
-    let msg = Message::from(msgs);
-    Ok(Some(msg))
+    }
 }
 
-pub fn mb_bind(config: Config, state: Arc<Mutex<AppState>>) {
-    let rt = match tokio::runtime::Runtime::new() {
-        Ok(r) => r,
-        Err(e) => return tracing::error!("failed to create tokio runtime in mb_bind: {}", e),
-    };
-
-    let sub_sock = match load_socks(&config) {
-        Ok(s) => s,
-        Err(e) => {
-            tracing::error!("failed to load microbiome sub sock: {}", e);
-            return;
-        }
-    };
+/// Publish a frame of the room's simulator to its websockets
+pub fn publish_state(room: &Room, state: WorldState) {
+    let tick = state.tick;
+    if room.sim_seen(tick) {
+        tracing::info!("room {} is publishing at tick {}", room.name, tick);
+    }
+    room.publish_frame(state);
+}
 
-    let mut socks = [sub_sock.as_poll_item(zmq::POLLIN)];
+/// Publish the simulator's messages to websockets and keep track of when it was last heard from
+///
+/// On shutdown the receiving thread is stopped, and what it already received is still published.
+async fn ingest(
+    mut rx: mpsc::Receiver<Vec<Vec<u8>>>,
+    room: Arc<Room>,
+    topic: String,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    // A simulator speaking another protocol version fails every frame, so only log when things change
+    let mut last_error = None;
 
     loop {
-        // println!("polling");
-        match zmq::poll(&mut socks, 10) {
-            Err(e) => {
-                tracing::error!("failed to poll microbiome: {}", e);
+        let msgb = tokio::select! {
+            msgb = rx.recv() => msgb,
+            _ = shutdown::requested(&mut shutdown), if !rx.is_closed() => {
+                rx.close();
                 continue;
             }
-            _ => (),
-        }
-
-        while socks[0].is_readable() {
-            let msgb = match sub_sock.recv_multipart(zmq::DONTWAIT) {
-                Ok(msgb) => msgb,
-                Err(_) => break,
-            };
-
-            if msgb.len() < 3 {
-                continue;
+        };
+        let Some(msgb) = msgb else {
+            break;
+        };
+        match SimMessage::from_frames(&msgb, &topic) {
+            Ok(SimMessage::State { state }) => {
+                last_error = None;
+                publish_state(&room, state);
             }
-
-            match build_websocket_msg(msgb) {
-                Ok(Some(msg)) => {
-                    let mut s = state.blocking_lock();
-                    match rt.block_on(s.broadcast_to_websockets(msg)) {
-                        Err(e) => {
-                            tracing::error!("failed to broadcast message to websockets: {}", e)
-                        }
-                        _ => (),
-                    }
-                }
-                Ok(None) => {
-                    tracing::error!("build null message");
-                }
-                Err(e) => {
-                    tracing::error!("failed to build websocket message: {}", e);
+            Ok(SimMessage::Events { events }) => room.publish_events(events),
+            Ok(SimMessage::Metrics {
+                stats,
+                tick_seconds,
+            }) => room.set_sim_metrics(SimMetrics {
+                stats,
+                tick_seconds,
+            }),
+            Err(e) => {
+                room.frame_errors.fetch_add(1, Ordering::Relaxed);
+                let e = e.to_string();
+                if last_error.as_ref() != Some(&e) {
+                    tracing::error!("failed to read a frame for room {}: {}", room.name, e);
+                    last_error = Some(e);
                 }
             }
         }
     }
 }
+
+/// Mark the simulator as offline once it has been silent for too long, until shutdown
+pub async fn watch_liveness(room: Arc<Room>, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(SILENCE_TIMEOUT / 4);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown::requested(&mut shutdown) => break,
+        }
+        if room.check_liveness(SILENCE_TIMEOUT) {
+            tracing::warn!("room {} went silent", room.name);
+        }
+    }
+}
+
+/// Feed a room from its simulator
+pub fn mb_bind(
+    config: &RoomConfig,
+    room: Arc<Room>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let sub_sock = load_socks(config)?;
+
+    let (tx, rx) = mpsc::channel(64);
+    thread::spawn(move || recv_messages(sub_sock, tx));
+
+    let topic = config.topic.clone();
+    Ok(tokio::spawn(async move {
+        tokio::join!(
+            ingest(rx, Arc::clone(&room), topic, shutdown.clone()),
+            watch_liveness(room, shutdown)
+        );
+    }))
+}
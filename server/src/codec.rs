@@ -0,0 +1,288 @@
+//! Encodings of websocket messages, picked by each browser with a subprotocol
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    OnceLock,
+};
+
+use axum::{
+    extract::ws::{Message, WebSocket},
+    http::HeaderValue,
+};
+use protocol::ws::{ClientMessage, ServerMessage, JSON_SUBPROTOCOL, MSGPACK_SUBPROTOCOL};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Subprotocols the server speaks, in order of preference when a browser offers several
+pub const SUBPROTOCOLS: [&str; 2] = [MSGPACK_SUBPROTOCOL, JSON_SUBPROTOCOL];
+
+/// Messages that failed to encode, wherever they were built
+static ENCODE_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of messages that failed to encode since the server started
+pub fn encode_errors() -> u64 {
+    ENCODE_ERRORS.load(Ordering::Relaxed)
+}
+
+/// How messages are sent to a browser
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Text frames of JSON, for browsers that ask for no subprotocol
+    #[default]
+    Json,
+    /// Binary frames of MessagePack, with every float narrowed to 32 bits
+    MsgPack,
+}
+
+impl Format {
+    /// The format of the subprotocol negotiated on `socket`
+    pub fn of(socket: &WebSocket) -> Self {
+        match socket.protocol().map(HeaderValue::as_bytes) {
+            Some(p) if p == MSGPACK_SUBPROTOCOL.as_bytes() => Format::MsgPack,
+            _ => Format::Json,
+        }
+    }
+
+    /// **Returns** `None` if the message couldn't be encoded, which is logged and counted
+    pub fn encode(self, msg: &ServerMessage) -> Option<Message> {
+        let json = to_json(msg)?;
+        Some(match self {
+            Format::Json => Message::Text(json.to_string()),
+            Format::MsgPack => {
+                let mut out = Vec::new();
+                write_msgpack(&mut out, &json);
+                Message::Binary(out)
+            }
+        })
+    }
+}
+
+/// **Returns** `None` if `msg` couldn't be turned into JSON, which is logged and counted
+fn to_json(msg: &impl Serialize) -> Option<Value> {
+    match serde_json::to_value(msg) {
+        Ok(json) => Some(json),
+        Err(e) => {
+            tracing::error!("failed to encode a message: {}", e);
+            ENCODE_ERRORS.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Bytes of the payload of a message
+pub fn len(msg: &Message) -> u64 {
+    let len = match msg {
+        Message::Text(text) => text.len(),
+        Message::Binary(data) => data.len(),
+        _ => 0,
+    };
+    len as u64
+}
+
+/// Encode a JSON value as MessagePack, narrowing floats to 32 bits
+///
+/// Positions and masses don't need more precision on screen, and it halves the size of every float.
+fn write_msgpack(out: &mut Vec<u8>, value: &Value) {
+    use rmp::encode::*;
+
+    // Writing to a Vec can't fail
+    match value {
+        Value::Null => write_nil(out).unwrap(),
+        Value::Bool(b) => write_bool(out, *b).unwrap(),
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                write_uint(out, n).unwrap();
+            } else if let Some(n) = n.as_i64() {
+                write_sint(out, n).unwrap();
+            } else {
+                write_f32(out, n.as_f64().unwrap_or_default() as f32).unwrap();
+            }
+        }
+        Value::String(s) => write_str(out, s).unwrap(),
+        Value::Array(items) => {
+            write_array_len(out, items.len() as u32).unwrap();
+            for item in items {
+                write_msgpack(out, item);
+            }
+        }
+        Value::Object(fields) => {
+            write_map_len(out, fields.len() as u32).unwrap();
+            for (key, value) in fields {
+                write_str(out, key).unwrap();
+                write_msgpack(out, value);
+            }
+        }
+    }
+}
+
+/// Read a message of a browser, JSON in text frames or MessagePack in binary ones
+///
+/// **Returns** `None` for frames that carry no message, such as pings
+pub fn decode(msg: &Message) -> Option<Result<ClientMessage, String>> {
+    match msg {
+        Message::Text(text) => Some(serde_json::from_str(text).map_err(|e| e.to_string())),
+        Message::Binary(data) => Some(rmp_serde::from_slice(data).map_err(|e| e.to_string())),
+        _ => None,
+    }
+}
+
+/// A message shared by many browsers, encoded at most once per format
+#[derive(Debug)]
+pub struct Encoded {
+    msg: ServerMessage,
+    json: OnceLock<Option<Message>>,
+    msgpack: OnceLock<Option<Message>>,
+}
+
+impl Encoded {
+    pub fn new(msg: ServerMessage) -> Self {
+        Self {
+            msg,
+            json: OnceLock::new(),
+            msgpack: OnceLock::new(),
+        }
+    }
+
+    /// **Returns** `None` if the message couldn't be encoded
+    pub fn get(&self, format: Format) -> Option<Message> {
+        let cell = match format {
+            Format::Json => &self.json,
+            Format::MsgPack => &self.msgpack,
+        };
+        cell.get_or_init(|| format.encode(&self.msg)).clone()
+    }
+
+    /// The JSON text of the message, for transports other than websockets
+    pub fn json(&self) -> Option<String> {
+        match self.get(Format::Json)? {
+            Message::Text(text) => Some(text),
+            _ => unreachable!("JSON is sent in text frames"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{NpcKind, NpcState, WorldState};
+    use serde_json::json;
+
+    use super::*;
+
+    fn state() -> ServerMessage {
+        ServerMessage::State(WorldState {
+            tick: 7,
+            size: 500.0,
+            cell_perception_radius: 200.0,
+            food_perception_radius: 50.0,
+            npcs: vec![NpcState {
+                id: u64::MAX,
+                pos: [0.1, 123.456789],
+                mass: 25.0,
+                radius: 5.0,
+                color: "#abcdef".to_string(),
+                kind: NpcKind::Linear { dir: [-0.6, 0.8] },
+            }],
+            food: Vec::new(),
+        })
+    }
+
+    /// `value` with every float rounded to 32 bits, as MessagePack carries them
+    fn narrow(value: Value) -> Value {
+        match value {
+            Value::Number(n) if n.is_f64() => json!(n.as_f64().unwrap() as f32 as f64),
+            Value::Array(items) => Value::Array(items.into_iter().map(narrow).collect()),
+            Value::Object(fields) => {
+                Value::Object(fields.into_iter().map(|(k, v)| (k, narrow(v))).collect())
+            }
+            value => value,
+        }
+    }
+
+    #[test]
+    fn msgpack_carries_the_json_with_narrowed_floats() {
+        let json = serde_json::to_value(state()).unwrap();
+        let Some(Message::Binary(data)) = Format::MsgPack.encode(&state()) else {
+            panic!("expected a binary frame");
+        };
+        let decoded: Value = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(decoded, narrow(json.clone()));
+        assert_ne!(decoded, json);
+        assert_eq!(decoded["data"]["npcs"][0]["id"], u64::MAX);
+
+        let Some(Message::Text(text)) = Format::Json.encode(&state()) else {
+            panic!("expected a text frame");
+        };
+        assert_eq!(serde_json::from_str::<Value>(&text).unwrap(), json);
+    }
+
+    #[test]
+    fn negative_ints_are_signed() {
+        for (n, bytes) in [
+            (-5, vec![0xfb]),
+            (-200, vec![0xd1, 0xff, 0x38]),
+            (
+                i64::MIN,
+                [vec![0xd3], i64::MIN.to_be_bytes().to_vec()].concat(),
+            ),
+        ] {
+            let mut out = Vec::new();
+            write_msgpack(&mut out, &json!(n));
+            assert_eq!(out, bytes, "{n}");
+            assert_eq!(rmp_serde::from_slice::<i64>(&out).unwrap(), n);
+        }
+    }
+
+    #[test]
+    fn browsers_may_send_either_format() {
+        let msg = ClientMessage::Seek { time_ms: 1500 };
+        let text = Message::Text(serde_json::to_string(&msg).unwrap());
+        let binary = Message::Binary(rmp_serde::to_vec_named(&msg).unwrap());
+        for frame in [text, binary] {
+            let decoded = decode(&frame).unwrap().unwrap();
+            assert!(matches!(decoded, ClientMessage::Seek { time_ms: 1500 }));
+        }
+
+        assert!(decode(&Message::Text("{\"event\": \"fly\"}".to_string()))
+            .unwrap()
+            .is_err());
+        assert!(decode(&Message::Binary(vec![0xc1])).unwrap().is_err());
+        assert!(decode(&Message::Ping(Vec::new())).is_none());
+    }
+
+    #[test]
+    fn messages_are_encoded_once_per_format() {
+        let encoded = Encoded::new(state());
+        assert!(encoded.json.get().is_none() && encoded.msgpack.get().is_none());
+
+        let json = encoded.json().unwrap();
+        assert!(encoded.json.get().is_some() && encoded.msgpack.get().is_none());
+        assert_eq!(encoded.get(Format::Json), Some(Message::Text(json)));
+
+        let msgpack = encoded.get(Format::MsgPack);
+        assert!(matches!(msgpack, Some(Message::Binary(_))));
+        assert_eq!(encoded.msgpack.get().cloned().flatten(), msgpack);
+    }
+
+    #[test]
+    fn failures_to_encode_are_counted() {
+        let before = encode_errors();
+        // JSON only has string keys
+        let unencodable = std::collections::HashMap::from([((1, 2), 3)]);
+        assert_eq!(to_json(&unencodable), None);
+        assert!(encode_errors() > before);
+
+        let metrics = crate::metrics::Metrics::default().render();
+        let exported = metrics
+            .lines()
+            .find_map(|line| line.strip_prefix("mb_ws_encode_errors_total "))
+            .unwrap();
+        assert!(exported.parse::<u64>().unwrap() > before);
+    }
+
+    #[test]
+    fn message_lengths_count_the_payload() {
+        assert_eq!(len(&Message::Text("héllo".to_string())), 6);
+        assert_eq!(len(&Message::Binary(vec![1, 2, 3])), 3);
+        assert_eq!(len(&Message::Ping(vec![1])), 0);
+    }
+}
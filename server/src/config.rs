@@ -1,20 +1,620 @@
-use std::env;
+//! Settings of the server, each layer overriding the one before: defaults, a TOML file, the
+//! environment and the command line
+
+use std::{collections::HashMap, error::Error, fs, path::PathBuf, time::Duration};
+
+use axum::http::HeaderValue;
+use clap::{builder::BoolishValueParser, Args, Parser};
+use protocol::{sim::TOPIC, Config as SimConfig};
+use serde::Deserialize;
+use tracing::Level;
+
+use crate::auth::{Access, Role};
+
+/// Settings of one layer, unset where it leaves them to the layers below
+///
+/// Flags fall back to their environment variables, and the file uses the same names in snake case:
+///
+/// ```toml
+/// port = 8080
+/// cors_origins = ["https://example.com"]
+///
+/// [[rooms]]
+/// name = "a"
+/// embedded = true
+///
+/// [limits]
+/// max_clients = 100
+/// ```
+#[derive(Debug, Default, Parser, Deserialize)]
+#[command(about = "Serves microbiome simulators to browsers", long_about = None)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// TOML file with settings, overridden by the environment and flags
+    #[arg(long, env = "MB_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+    /// Address to listen on [default: 127.0.0.1]
+    #[arg(long, env = "MB_SERVER_HOST")]
+    host: Option<String>,
+    /// Port to listen on [default: 8080]
+    #[arg(long, env = "MB_SERVER_PORT")]
+    port: Option<u16>,
+    /// Directory of the UI build [default: ui/dist]
+    #[arg(long, env = "MB_UI_PATH")]
+    static_path: Option<PathBuf>,
+    /// Directory recordings of rooms are kept in [default: recordings]
+    #[arg(long, env = "MB_RECORDINGS")]
+    recordings_path: Option<PathBuf>,
+    /// Most verbose level to log: error, warn, info, debug or trace [default: debug]
+    #[arg(long, env = "MB_LOG")]
+    log_level: Option<String>,
+    /// Origins browsers may call the server from, comma separated, `*` for any [default: *]
+    #[arg(long, env = "MB_CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Option<Vec<String>>,
+    /// Where the simulator of the default room publishes
+    #[arg(long, env = "MB_PUBSUB")]
+    pubsub: Option<String>,
+    /// Control socket of the simulator of the default room
+    #[arg(long, env = "MB_CONTROL")]
+    control: Option<String>,
+    /// Run the simulator of the default room inside the server
+    #[arg(
+        long,
+        env = "MB_EMBEDDED",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new()
+    )]
+    embedded: Option<bool>,
+    /// Rooms as a JSON list, instead of a single default room, e.g. '[{"name": "a", "embedded": true}]'
+    #[arg(long, env = "MB_ROOMS", value_parser = parse_rooms)]
+    rooms: Option<Rooms>,
+    /// How long to wait for connections and simulators to stop on exit [default: 5000]
+    #[arg(long, env = "MB_SHUTDOWN_TIMEOUT_MS")]
+    shutdown_timeout_ms: Option<u64>,
+    /// PEM certificate chain to serve HTTPS and WSS with, along with tls_key
+    #[arg(long, env = "MB_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of tls_cert
+    #[arg(long, env = "MB_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// Tokens of admins, who may also control simulators and change their config, comma separated
+    #[arg(long, env = "MB_ADMIN_TOKENS", value_delimiter = ',')]
+    admin_tokens: Option<Vec<String>>,
+    /// Tokens of players, who may also join rooms and steer cells, comma separated
+    #[arg(long, env = "MB_PLAYER_TOKENS", value_delimiter = ',')]
+    player_tokens: Option<Vec<String>>,
+    /// Role of clients without a token, `admin` to let anyone control the simulators [default: spectator]
+    #[arg(long, env = "MB_ANONYMOUS_ROLE")]
+    anonymous_role: Option<Role>,
+    #[command(flatten)]
+    #[serde(default)]
+    limits: LimitSettings,
+}
+
+/// Limits on each client, unset where a layer leaves them to the layers below
+#[derive(Debug, Default, Args, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitSettings {
+    /// Websockets and event streams watching rooms at once, unlimited if omitted
+    #[arg(long, env = "MB_MAX_CLIENTS")]
+    max_clients: Option<u64>,
+    /// Largest message a browser may send [default: 65536]
+    #[arg(long, env = "MB_MAX_MESSAGE_BYTES")]
+    max_message_bytes: Option<usize>,
+    /// How long a browser has to answer the server's hello [default: 5000]
+    #[arg(long, env = "MB_HANDSHAKE_TIMEOUT_MS")]
+    handshake_timeout_ms: Option<u64>,
+    /// How long a browser may take to accept a message before it is disconnected [default: 5000]
+    #[arg(long, env = "MB_SLOW_CLIENT_TIMEOUT_MS")]
+    slow_client_timeout_ms: Option<u64>,
+    /// Most frames per second sent on an event stream [default: 10]
+    #[arg(long, env = "MB_SSE_MAX_FPS")]
+    sse_max_fps: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(transparent)]
+struct Rooms(Vec<RoomConfig>);
+
+fn parse_rooms(s: &str) -> Result<Rooms, serde_json::Error> {
+    serde_json::from_str(s)
+}
+
+impl Settings {
+    fn read(path: &PathBuf) -> Result<Self, Box<dyn Error>> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("can't read {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("invalid {}: {e}", path.display()).into())
+    }
+
+    /// Fill what this layer leaves unset from `below`
+    fn or(self, below: Settings) -> Settings {
+        Settings {
+            config: self.config.or(below.config),
+            host: self.host.or(below.host),
+            port: self.port.or(below.port),
+            static_path: self.static_path.or(below.static_path),
+            recordings_path: self.recordings_path.or(below.recordings_path),
+            log_level: self.log_level.or(below.log_level),
+            cors_origins: self.cors_origins.or(below.cors_origins),
+            pubsub: self.pubsub.or(below.pubsub),
+            control: self.control.or(below.control),
+            embedded: self.embedded.or(below.embedded),
+            rooms: self.rooms.or(below.rooms),
+            shutdown_timeout_ms: self.shutdown_timeout_ms.or(below.shutdown_timeout_ms),
+            tls_cert: self.tls_cert.or(below.tls_cert),
+            tls_key: self.tls_key.or(below.tls_key),
+            admin_tokens: self.admin_tokens.or(below.admin_tokens),
+            player_tokens: self.player_tokens.or(below.player_tokens),
+            anonymous_role: self.anonymous_role.or(below.anonymous_role),
+            limits: LimitSettings {
+                max_clients: self.limits.max_clients.or(below.limits.max_clients),
+                max_message_bytes: self
+                    .limits
+                    .max_message_bytes
+                    .or(below.limits.max_message_bytes),
+                handshake_timeout_ms: self
+                    .limits
+                    .handshake_timeout_ms
+                    .or(below.limits.handshake_timeout_ms),
+                slow_client_timeout_ms: self
+                    .limits
+                    .slow_client_timeout_ms
+                    .or(below.limits.slow_client_timeout_ms),
+                sse_max_fps: self.limits.sse_max_fps.or(below.limits.sse_max_fps),
+            },
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub host: String,
-    pub port: String,
-    pub static_path: String,
-    pub sub_at: String,
+    pub port: u16,
+    pub static_path: PathBuf,
+    /// Directory recordings of rooms are kept in
+    pub recordings_path: PathBuf,
+    pub log_level: Level,
+    pub cors: Cors,
+    /// Simulators to serve, the first one to clients that don't pick a room
+    pub rooms: Vec<RoomConfig>,
+    /// How long to wait for connections and simulators to stop on exit
+    pub shutdown_timeout: Duration,
+    /// Certificate to serve HTTPS with, plain HTTP if none
+    pub tls: Option<Tls>,
+    /// Roles of the tokens clients present
+    pub access: Access,
+    pub limits: Limits,
+}
+
+/// PEM files of a certificate chain and its key, read again on SIGHUP
+#[derive(Debug, Clone)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Which other sites browsers may call the server from
+#[derive(Debug, Clone)]
+pub enum Cors {
+    Any,
+    /// Only these origins, none if empty
+    Origins(Vec<HeaderValue>),
+}
+
+/// Limits on each client
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Websockets and event streams watching rooms at once
+    pub max_clients: Option<u64>,
+    /// Largest message a browser may send
+    pub max_message_bytes: usize,
+    /// How long a browser has to answer the server's `hello`
+    pub handshake_timeout: Duration,
+    /// How long a browser may take to accept a message before it is disconnected
+    pub slow_client_timeout: Duration,
+    /// Most frames per second sent on an event stream
+    pub sse_max_fps: u32,
+}
+
+/// A simulator and the endpoints it listens on
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoomConfig {
+    pub name: String,
+    /// Where the simulator publishes, unless it is embedded
+    pub sub_at: Option<String>,
+    /// Topic the simulator publishes on, to tell apart simulators behind one endpoint
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    /// Control socket of the simulator, needed for players
+    pub control_at: Option<String>,
+    /// Run the simulator inside the server instead of subscribing to one
+    #[serde(default)]
+    pub embedded: bool,
+    /// Seed of an embedded simulator, random if omitted
+    #[cfg_attr(not(feature = "embedded"), allow(dead_code))]
+    pub seed: Option<u64>,
+    /// Config of an embedded simulator, its parameters defaulting to those of the simulator
+    #[cfg_attr(not(feature = "embedded"), allow(dead_code))]
+    pub sim: Option<SimConfig>,
+}
+
+impl RoomConfig {
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        match (self.embedded, &self.sub_at, &self.control_at) {
+            (true, _, _) if !cfg!(feature = "embedded") => Err(format!(
+                "room {} is embedded, but the server was built without the embedded feature",
+                self.name
+            )
+            .into()),
+            (true, Some(_), _) | (true, _, Some(_)) => Err(format!(
+                "room {} is embedded, it can't have sub_at or control_at",
+                self.name
+            )
+            .into()),
+            (false, None, _) => Err(format!("room {} needs a sub_at", self.name).into()),
+            (false, _, _) if self.sim.is_some() => Err(format!(
+                "room {} is not embedded, its simulator is configured where it runs",
+                self.name
+            )
+            .into()),
+            _ => match &self.sim {
+                Some(sim) => sim
+                    .validate()
+                    .map_err(|e| format!("room {} has an invalid sim: {e}", self.name).into()),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+fn default_topic() -> String {
+    TOPIC.to_string()
 }
 
 impl Config {
-    pub fn read_env() -> Result<Self, env::VarError> {
+    /// Read the config from the command line, the environment and the file they point to
+    ///
+    /// Exits with clap's usage message if the command line is invalid.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let settings = Settings::parse();
+        let file = match &settings.config {
+            Some(path) => Settings::read(path)?,
+            None => Settings::default(),
+        };
+        Self::resolve(settings.or(file))
+    }
+
+    /// Fill what `settings` leaves unset with defaults and check the result
+    pub fn resolve(settings: Settings) -> Result<Self, Box<dyn Error>> {
+        let rooms = match (settings.rooms, settings.embedded, settings.pubsub) {
+            (Some(Rooms(rooms)), _, _) => rooms,
+            (None, Some(true), _) => vec![RoomConfig {
+                name: "default".to_string(),
+                sub_at: None,
+                topic: default_topic(),
+                control_at: None,
+                embedded: true,
+                seed: None,
+                sim: None,
+            }],
+            (None, _, Some(pubsub)) => vec![RoomConfig {
+                name: "default".to_string(),
+                sub_at: Some(pubsub),
+                topic: default_topic(),
+                control_at: settings.control,
+                embedded: false,
+                seed: None,
+                sim: None,
+            }],
+            (None, _, None) => {
+                return Err("no simulator to serve, set pubsub (MB_PUBSUB), embedded \
+                            (MB_EMBEDDED) or rooms (MB_ROOMS)"
+                    .into())
+            }
+        };
+
+        if rooms.is_empty() {
+            return Err("rooms lists no rooms".into());
+        }
+        for (i, room) in rooms.iter().enumerate() {
+            room.validate()?;
+            if rooms[..i].iter().any(|x| x.name == room.name) {
+                return Err(format!("room {} is listed twice", room.name).into());
+            }
+        }
+
+        let static_path = settings
+            .static_path
+            .unwrap_or_else(|| PathBuf::from("ui/dist"));
+        if !static_path.is_dir() {
+            return Err(format!("static path {} is not a directory", static_path.display()).into());
+        }
+
+        let log_level = match settings.log_level {
+            Some(level) => level.parse().map_err(|_| {
+                format!("invalid log level {level}, expected error, warn, info, debug or trace")
+            })?,
+            None => Level::DEBUG,
+        };
+
+        let tls = match (settings.tls_cert, settings.tls_key) {
+            (Some(_), Some(_)) if !cfg!(feature = "tls") => {
+                return Err(
+                    "tls_cert is set, but the server was built without the tls feature".into(),
+                )
+            }
+            (Some(cert), Some(key)) => Some(Tls { cert, key }),
+            (None, None) => None,
+            _ => return Err("tls_cert and tls_key must be set together".into()),
+        };
+
         Ok(Self {
-            host: env::var("MB_SERVER_HOST")?,
-            port: env::var("MB_SERVER_PORT")?,
-            static_path: env::var("MB_UI_PATH")?,
-            sub_at: env::var("MB_PUBSUB")?,
+            host: settings.host.unwrap_or_else(|| "127.0.0.1".to_string()),
+            port: settings.port.unwrap_or(8080),
+            static_path,
+            recordings_path: settings
+                .recordings_path
+                .unwrap_or_else(|| PathBuf::from("recordings")),
+            log_level,
+            cors: parse_cors(settings.cors_origins)?,
+            rooms,
+            shutdown_timeout: Duration::from_millis(positive(
+                "shutdown_timeout_ms",
+                settings.shutdown_timeout_ms,
+                5000,
+            )?),
+            tls,
+            access: resolve_access(
+                settings.admin_tokens,
+                settings.player_tokens,
+                settings.anonymous_role,
+            )?,
+            limits: Limits::resolve(settings.limits)?,
         })
     }
 }
+
+fn resolve_access(
+    admin_tokens: Option<Vec<String>>,
+    player_tokens: Option<Vec<String>>,
+    anonymous: Option<Role>,
+) -> Result<Access, Box<dyn Error>> {
+    let mut tokens = HashMap::new();
+    for (role, role_tokens) in [(Role::Admin, admin_tokens), (Role::Player, player_tokens)] {
+        for token in role_tokens.unwrap_or_default() {
+            if token.is_empty() {
+                return Err(format!("{} tokens can't be empty", role.name()).into());
+            }
+            if tokens.insert(token, role).is_some() {
+                return Err("a token is listed for both admins and players".into());
+            }
+        }
+    }
+    Ok(Access::new(tokens, anonymous.unwrap_or(Role::Spectator)))
+}
+
+fn parse_cors(origins: Option<Vec<String>>) -> Result<Cors, Box<dyn Error>> {
+    let Some(origins) = origins else {
+        return Ok(Cors::Any);
+    };
+    if origins.iter().any(|x| x == "*") {
+        return match origins.len() {
+            1 => Ok(Cors::Any),
+            _ => Err("cors origins can't list `*` along with other origins".into()),
+        };
+    }
+    origins
+        .iter()
+        .map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) if origin.contains("://") && !origin.ends_with('/') => Ok(value),
+            _ => Err(format!(
+                "invalid cors origin {origin}, expected a scheme and host like https://example.com"
+            )
+            .into()),
+        })
+        .collect::<Result<_, _>>()
+        .map(Cors::Origins)
+}
+
+/// **Returns** `value` if it is set, `default` otherwise, refusing 0
+fn positive(name: &str, value: Option<u64>, default: u64) -> Result<u64, String> {
+    match value {
+        Some(0) => Err(format!("{name} must be greater than 0")),
+        Some(value) => Ok(value),
+        None => Ok(default),
+    }
+}
+
+impl Limits {
+    fn resolve(settings: LimitSettings) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            max_clients: settings
+                .max_clients
+                .map(|max| positive("max_clients", Some(max), 0))
+                .transpose()?,
+            max_message_bytes: positive(
+                "max_message_bytes",
+                settings.max_message_bytes.map(|x| x as u64),
+                64 * 1024,
+            )? as usize,
+            handshake_timeout: Duration::from_millis(positive(
+                "handshake_timeout_ms",
+                settings.handshake_timeout_ms,
+                5000,
+            )?),
+            slow_client_timeout: Duration::from_millis(positive(
+                "slow_client_timeout_ms",
+                settings.slow_client_timeout_ms,
+                5000,
+            )?),
+            sse_max_fps: positive("sse_max_fps", settings.sse_max_fps.map(u64::from), 10)? as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(toml: &str) -> Settings {
+        let mut settings: Settings = toml::from_str(toml).unwrap();
+        settings.static_path = Some(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        settings
+    }
+
+    fn room(toml: &str) -> RoomConfig {
+        toml::from_str(&format!("name = \"a\"\n{toml}")).unwrap()
+    }
+
+    #[test]
+    fn unset_settings_fall_back_to_defaults() {
+        let config = Config::resolve(settings("embedded = true")).unwrap();
+        assert_eq!(config.host, "127.0.0.1");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.log_level, Level::DEBUG);
+        assert!(matches!(config.cors, Cors::Any));
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(5));
+        assert!(config.tls.is_none());
+        assert_eq!(config.access.anonymous, Role::Spectator);
+        assert_eq!(config.limits.max_clients, None);
+        assert_eq!(config.limits.max_message_bytes, 64 * 1024);
+        assert_eq!(config.limits.sse_max_fps, 10);
+        assert_eq!(config.rooms.len(), 1);
+        assert!(config.rooms[0].embedded);
+    }
+
+    #[test]
+    fn flags_override_the_environment_which_overrides_the_file() {
+        // The only test reading the environment, so setting it races nothing
+        std::env::set_var("MB_SERVER_PORT", "9000");
+        std::env::set_var("MB_LOG", "warn");
+        let cli = Settings::try_parse_from(["server", "--port", "9100"]);
+        std::env::remove_var("MB_SERVER_PORT");
+        std::env::remove_var("MB_LOG");
+
+        let file = settings(
+            r#"
+            host = "0.0.0.0"
+            port = 8000
+            log_level = "error"
+            pubsub = "tcp://sim:5556"
+
+            [limits]
+            sse_max_fps = 30
+            "#,
+        );
+        let config = Config::resolve(cli.unwrap().or(file)).unwrap();
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.log_level, Level::WARN);
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.limits.sse_max_fps, 30);
+        assert_eq!(config.limits.handshake_timeout, Duration::from_secs(5));
+        assert_eq!(config.rooms[0].sub_at.as_deref(), Some("tcp://sim:5556"));
+    }
+
+    #[test]
+    fn rooms_replace_the_default_room() {
+        let config = Config::resolve(settings(
+            r#"
+            embedded = true
+
+            [[rooms]]
+            name = "a"
+            sub_at = "tcp://a:5556"
+
+            [[rooms]]
+            name = "b"
+            sub_at = "tcp://b:5556"
+            topic = "b"
+            "#,
+        ))
+        .unwrap();
+        let rooms: Vec<_> = config.rooms.iter().map(|x| (&*x.name, &*x.topic)).collect();
+        assert_eq!(rooms, [("a", TOPIC), ("b", "b")]);
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        for (toml, error) in [
+            ("", "no simulator to serve"),
+            ("rooms = []", "rooms lists no rooms"),
+            (
+                "embedded = true\nlog_level = \"loud\"",
+                "invalid log level loud",
+            ),
+            (
+                "embedded = true\nshutdown_timeout_ms = 0",
+                "shutdown_timeout_ms must be",
+            ),
+            (
+                "embedded = true\ncors_origins = [\"*\", \"https://a.com\"]",
+                "along with",
+            ),
+            (
+                "embedded = true\ncors_origins = [\"a.com\"]",
+                "invalid cors origin",
+            ),
+            (
+                "embedded = true\ntls_cert = \"cert.pem\"",
+                "must be set together",
+            ),
+            (
+                "embedded = true\nadmin_tokens = [\"\"]",
+                "admin tokens can't be empty",
+            ),
+            (
+                "embedded = true\nadmin_tokens = [\"a\"]\nplayer_tokens = [\"a\"]",
+                "both admins and players",
+            ),
+            (
+                "rooms = [{ name = \"a\", embedded = true }, { name = \"a\", embedded = true }]",
+                "room a is listed twice",
+            ),
+        ] {
+            let e = Config::resolve(settings(toml)).unwrap_err().to_string();
+            assert!(e.contains(error), "{toml}: {e}");
+        }
+
+        let mut missing = settings("embedded = true");
+        missing.static_path = Some(PathBuf::from("missing"));
+        let e = Config::resolve(missing).unwrap_err().to_string();
+        assert_eq!(e, "static path missing is not a directory");
+    }
+
+    #[test]
+    fn rooms_must_either_subscribe_or_be_embedded() {
+        room("embedded = true\nseed = 1\nsim = { size = 500.0 }")
+            .validate()
+            .unwrap();
+        room("sub_at = \"tcp://a:5556\"\ncontrol_at = \"tcp://a:5555\"")
+            .validate()
+            .unwrap();
+
+        for (toml, error) in [
+            (
+                "embedded = true\nsub_at = \"tcp://a:5556\"",
+                "room a is embedded, it can't have sub_at or control_at",
+            ),
+            (
+                "embedded = true\ncontrol_at = \"tcp://a:5555\"",
+                "room a is embedded, it can't have sub_at or control_at",
+            ),
+            ("control_at = \"tcp://a:5555\"", "room a needs a sub_at"),
+            (
+                "sub_at = \"tcp://a:5556\"\nsim = {}",
+                "room a is not embedded, its simulator is configured where it runs",
+            ),
+            (
+                "embedded = true\nsim = { base_speed = -1.0 }",
+                "room a has an invalid sim: ",
+            ),
+        ] {
+            let e = room(toml).validate().unwrap_err().to_string();
+            assert!(e.starts_with(error), "{toml}: {e}");
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::{error::Error, thread};
+
+use protocol::{
+    control::{Command, Reply},
+    PROTOCOL_VERSION,
+};
+use tokio::sync::{mpsc, oneshot};
+
+/// How long to wait for the simulator to answer a command
+const CONTROL_TIMEOUT_MS: i32 = 2000;
+
+pub type ControlError = Box<dyn Error + Send + Sync>;
+pub type Request = (Command, oneshot::Sender<Result<Reply, ControlError>>);
+
+/// Bridge to the simulator's control socket
+///
+/// zmq sockets can't be shared between tasks, so a dedicated thread owns the REQ socket and
+/// forwards commands one at a time. It says hello before the first command, and again after the
+/// simulator stops answering since it may have been restarted in the meantime.
+#[derive(Clone)]
+pub struct SimControl {
+    tx: mpsc::Sender<Request>,
+}
+
+impl SimControl {
+    pub fn connect(control_at: &str) -> Result<Self, Box<dyn Error>> {
+        let context = zmq::Context::new();
+        let sock = context.socket(zmq::REQ)?;
+        // Let a command be sent after a lost reply instead of wedging the socket
+        sock.set_req_relaxed(true)?;
+        sock.set_req_correlate(true)?;
+        sock.set_rcvtimeo(CONTROL_TIMEOUT_MS)?;
+        sock.set_linger(0)?;
+        sock.connect(control_at)?;
+
+        tracing::debug!("microbiome control sock connected to {}", control_at);
+
+        let (tx, mut rx) = mpsc::channel::<Request>(64);
+        thread::spawn(move || {
+            let mut greeted = false;
+            while let Some((cmd, reply_tx)) = rx.blocking_recv() {
+                let reply = if greeted {
+                    request(&sock, &cmd)
+                } else {
+                    hello(&sock).and_then(|_| request(&sock, &cmd))
+                };
+                greeted = reply.is_ok();
+                let _ = reply_tx.send(reply);
+            }
+        });
+
+        Ok(Self { tx })
+    }
+
+    /// Control a simulator running inside the server, which answers the requests sent down the channel
+    #[cfg(feature = "embedded")]
+    pub fn channel() -> (Self, mpsc::Receiver<Request>) {
+        let (tx, rx) = mpsc::channel(64);
+        (Self { tx }, rx)
+    }
+
+    /// Send a command to the simulator and wait for its reply
+    pub async fn request(&self, cmd: Command) -> Result<Reply, ControlError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx
+            .send((cmd, reply_tx))
+            .await
+            .map_err(|_| "control thread is gone")?;
+        reply_rx.await.map_err(|_| "control thread is gone")?
+    }
+}
+
+fn request(sock: &zmq::Socket, cmd: &Command) -> Result<Reply, ControlError> {
+    sock.send(serde_json::to_vec(cmd)?, 0)?;
+    let msg = match sock.recv_bytes(0) {
+        Ok(msg) => msg,
+        Err(zmq::Error::EAGAIN) => return Err("simulator did not answer in time".into()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(serde_json::from_slice(&msg)?)
+}
+
+/// Check that the simulator speaks the same protocol version
+fn hello(sock: &zmq::Socket) -> Result<(), ControlError> {
+    let cmd = Command::Hello {
+        version: PROTOCOL_VERSION,
+    };
+    match request(sock, &cmd)? {
+        Reply::Hello { version } if version == PROTOCOL_VERSION => Ok(()),
+        Reply::Hello { version } => Err(format!(
+            "simulator speaks protocol version {version}, expected {PROTOCOL_VERSION}"
+        )
+        .into()),
+        Reply::Error { message } => Err(message.into()),
+        reply => Err(format!("unexpected reply to hello: {reply:?}").into()),
+    }
+}
@@ -0,0 +1,89 @@
+//! Simulators running inside the server, for a single process setup without zmq
+
+use std::{error::Error, sync::Arc, thread, time::Duration};
+
+use microbiome::{control::Simulation, invariants::FRAME_DURATION, Microbiome};
+use protocol::sim::SimMessage;
+use tokio::{
+    runtime,
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+use crate::{
+    bind::{publish_state, watch_liveness},
+    config::RoomConfig,
+    control::Request,
+    room::{Room, SimMetrics},
+    shutdown,
+};
+
+/// Step the simulator at its frame rate, publishing every frame to the room and answering the
+/// commands of its players in between, until shutdown
+async fn run(
+    mut sim: Simulation,
+    mut commands: mpsc::Receiver<Request>,
+    room: Arc<Room>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_millis(FRAME_DURATION));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let stepped = sim.tick();
+                publish_state(&room, sim.microbiome().state());
+                if stepped && !sim.microbiome().events().is_empty() {
+                    room.publish_events(sim.microbiome().events().to_vec());
+                }
+                if let Some(SimMessage::Metrics { stats, tick_seconds }) = sim.metrics() {
+                    room.set_sim_metrics(SimMetrics { stats, tick_seconds });
+                }
+            }
+            Some((cmd, reply_tx)) = commands.recv() => {
+                let _ = reply_tx.send(Ok(sim.handle(cmd)));
+            }
+            _ = shutdown::requested(&mut shutdown) => break,
+        }
+    }
+}
+
+/// Start the simulator of an embedded room, taking commands from the receiving end of its
+/// [`SimControl`](crate::control::SimControl)
+///
+/// Steps and commands take as long as they take, so the simulator gets a thread of its own
+/// rather than holding up a worker of the server.
+pub fn spawn(
+    config: &RoomConfig,
+    room: Arc<Room>,
+    commands: mpsc::Receiver<Request>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let seed = config.seed.unwrap_or_else(rand::random);
+    let mb = Microbiome::with_config(config.sim.clone().unwrap_or_default(), seed);
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()?;
+    tracing::debug!("running the simulator of room {} in process", config.name);
+
+    let (done_tx, done_rx) = oneshot::channel();
+    let sim_room = Arc::clone(&room);
+    let sim_shutdown = shutdown.clone();
+    thread::Builder::new()
+        .name(format!("room {}", config.name))
+        .spawn(move || {
+            runtime.block_on(run(Simulation::new(mb), commands, sim_room, sim_shutdown));
+            let _ = done_tx.send(());
+        })?;
+
+    Ok(tokio::spawn(async move {
+        tokio::join!(
+            async {
+                let _ = done_rx.await;
+            },
+            watch_liveness(room, shutdown)
+        );
+    }))
+}
@@ -0,0 +1,25 @@
+//! Server between microbiome simulators and browsers
+//!
+//! The binary runs it from its config. Tools and tests can build the same app with
+//! [`app::make_app`] and [`app::serve`] it on a listener of their own.
+
+mod api;
+pub mod app;
+pub mod auth;
+mod bind;
+mod codec;
+pub mod config;
+mod control;
+#[cfg(feature = "embedded")]
+mod embedded;
+mod metrics;
+mod record;
+mod replay;
+mod room;
+pub mod shutdown;
+mod sse;
+mod state;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod view;
+mod ws;
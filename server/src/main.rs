@@ -1,24 +1,29 @@
-use std::{net::SocketAddr, process};
+use std::process;
 
-use app::make_app;
-use config::Config;
-
-mod app;
-mod bind;
-mod config;
-mod state;
-mod ws;
+use microbiome_backend::{
+    app::{make_app, serve},
+    config::Config,
+    shutdown,
+};
+use tokio::{sync::watch, time::timeout};
 
 #[tokio::main]
 async fn main() {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration: {e}");
+            process::exit(2);
+        }
+    };
     tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
+        .with_max_level(config.log_level)
         .init();
 
-    let config = Config::read_env().unwrap();
-    let addr = config.host.clone() + ":" + config.port.as_str();
+    let addr = format!("{}:{}", config.host, config.port);
 
-    let (app, mb_thread) = match make_app(&config) {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let (app, mb_thread) = match make_app(&config, shutdown_rx.clone()) {
         Ok(a) => a,
         Err(e) => {
             tracing::error!("failed to create router: {}", e);
@@ -26,6 +31,29 @@ async fn main() {
         }
     };
 
+    #[cfg(feature = "tls")]
+    let tls = match &config.tls {
+        Some(tls) => match microbiome_backend::tls::load(tls).await {
+            Ok(rustls) => {
+                tokio::spawn(microbiome_backend::tls::reload_on_hangup(
+                    rustls.clone(),
+                    tls.clone(),
+                    shutdown_rx.clone(),
+                ));
+                Some(rustls)
+            }
+            Err(e) => {
+                tracing::error!(
+                    "failed to read the certificate {}: {}",
+                    tls.cert.display(),
+                    e
+                );
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
     let app_thread = tokio::spawn(async move {
         let listener = match tokio::net::TcpListener::bind(&addr).await {
             Ok(l) => {
@@ -38,20 +66,36 @@ async fn main() {
             }
         };
 
-        match axum::serve(
-            listener,
-            app.into_make_service_with_connect_info::<SocketAddr>(),
-        )
-        .await
-        {
-            Err(e) => tracing::error!("app failed: {}", e),
-            _ => (),
+        #[cfg(feature = "tls")]
+        let served = match tls {
+            Some(tls) => microbiome_backend::tls::serve(listener, app, tls, shutdown_rx).await,
+            None => serve(listener, app, shutdown_rx).await,
+        };
+        #[cfg(not(feature = "tls"))]
+        let served = serve(listener, app, shutdown_rx).await;
+        if let Err(e) = served {
+            tracing::error!("app failed: {}", e);
         }
     });
 
-    match tokio::join!(mb_thread, app_thread) {
-        (Err(e), _) => tracing::error!("microbiome thread died: {}", e),
-        (_, Err(e)) => tracing::error!("app thread died: {}", e),
-        _ => (),
+    shutdown::signal().await;
+    // Stop accepting connections, close websockets and let the simulators' tasks wind down
+    let _ = shutdown_tx.send(true);
+
+    match timeout(config.shutdown_timeout, async {
+        tokio::join!(mb_thread, app_thread)
+    })
+    .await
+    {
+        Ok((Err(e), _)) => tracing::error!("microbiome thread died: {}", e),
+        Ok((_, Err(e))) => tracing::error!("app thread died: {}", e),
+        Ok(_) => tracing::info!("shut down"),
+        Err(_) => {
+            tracing::warn!(
+                "gave up waiting for tasks to stop after {:?}",
+                config.shutdown_timeout
+            );
+            process::exit(1);
+        }
     }
 }
@@ -0,0 +1,269 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::{codec::encode_errors, room::SimMetrics, state::AppState};
+
+/// Upper bounds in seconds of the broadcast latency buckets
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 2.5];
+
+/// Counters of the websocket fan-out, exported in Prometheus text format on `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Websockets currently receiving frames
+    pub clients: AtomicU64,
+    /// Event streams currently receiving frames
+    pub sse_clients: AtomicU64,
+    /// Frames lagging clients skipped because a newer one arrived first
+    pub frames_skipped: AtomicU64,
+    /// Clients disconnected for taking too long to accept a message
+    pub slow_disconnects: AtomicU64,
+    /// Bytes of the messages sent to websockets replaying recordings
+    pub replay_bytes_sent: AtomicU64,
+    /// Time from a frame arriving to a client accepting it
+    pub broadcast_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        write_header(
+            &mut out,
+            "mb_ws_clients",
+            "gauge",
+            "Websockets currently receiving frames",
+        );
+        write_sample(
+            &mut out,
+            "mb_ws_clients",
+            "",
+            self.clients.load(Ordering::Relaxed),
+        );
+        write_header(
+            &mut out,
+            "mb_sse_clients",
+            "gauge",
+            "Event streams currently receiving frames",
+        );
+        write_sample(
+            &mut out,
+            "mb_sse_clients",
+            "",
+            self.sse_clients.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "mb_ws_frames_skipped_total",
+            "Frames lagging clients skipped because a newer one arrived first",
+            &self.frames_skipped,
+        );
+        write_counter(
+            &mut out,
+            "mb_ws_slow_disconnects_total",
+            "Clients disconnected for taking too long to accept a message",
+            &self.slow_disconnects,
+        );
+        write_counter(
+            &mut out,
+            "mb_replay_sent_bytes_total",
+            "Bytes of the messages sent to websockets replaying recordings",
+            &self.replay_bytes_sent,
+        );
+        write_header(
+            &mut out,
+            "mb_ws_encode_errors_total",
+            "counter",
+            "Messages that failed to encode",
+        );
+        write_sample(&mut out, "mb_ws_encode_errors_total", "", encode_errors());
+        self.broadcast_latency.render(
+            &mut out,
+            "mb_ws_broadcast_latency_seconds",
+            "Time from a frame arriving to a client accepting it",
+        );
+        out
+    }
+}
+
+/// Cumulative histogram of durations, with fixed buckets
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    /// Sum of the observations in microseconds
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        write_header(out, name, "histogram", help);
+        let count = self.count.load(Ordering::Relaxed);
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let labels = format!("{{le=\"{bound}\"}}");
+            write_sample(
+                out,
+                &format!("{name}_bucket"),
+                &labels,
+                bucket.load(Ordering::Relaxed),
+            );
+        }
+        write_sample(out, &format!("{name}_bucket"), "{le=\"+Inf\"}", count);
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        write_sample(out, &format!("{name}_sum"), "", sum);
+        write_sample(out, &format!("{name}_count"), "", count);
+    }
+}
+
+/// Name, help and value of a gauge of the simulators' metrics
+type SimGauge = (&'static str, &'static str, fn(&SimMetrics) -> f64);
+
+/// Metrics of every room, labelled with its name
+fn render_rooms(out: &mut String, state: &AppState) {
+    let labels: Vec<String> = state
+        .rooms
+        .iter()
+        .map(|room| format!("{{room=\"{}\"}}", escape_label(&room.name)))
+        .collect();
+
+    write_header(
+        out,
+        "mb_frames_received_total",
+        "counter",
+        "Frames the simulator published",
+    );
+    for (room, labels) in state.rooms.iter().zip(&labels) {
+        let value = room.frames_received.load(Ordering::Relaxed);
+        write_sample(out, "mb_frames_received_total", labels, value);
+    }
+    write_header(
+        out,
+        "mb_frame_errors_total",
+        "counter",
+        "Messages of the simulator that could not be read",
+    );
+    for (room, labels) in state.rooms.iter().zip(&labels) {
+        let value = room.frame_errors.load(Ordering::Relaxed);
+        write_sample(out, "mb_frame_errors_total", labels, value);
+    }
+    write_header(
+        out,
+        "mb_ws_sent_bytes_total",
+        "counter",
+        "Bytes of the messages sent to the websockets in the room, summed over them",
+    );
+    for (room, labels) in state.rooms.iter().zip(&labels) {
+        let value = room.bytes_sent.load(Ordering::Relaxed);
+        write_sample(out, "mb_ws_sent_bytes_total", labels, value);
+    }
+
+    // Simulators that haven't reported yet are left out rather than reported as 0
+    let sims: Vec<_> = state
+        .rooms
+        .iter()
+        .zip(&labels)
+        .filter_map(|(room, labels)| Some((room.sim_metrics()?, labels)))
+        .collect();
+    let gauges: [SimGauge; 7] = [
+        (
+            "mb_sim_tick_duration_seconds",
+            "Average time the simulator took to step over the last second",
+            |sim| sim.tick_seconds,
+        ),
+        ("mb_sim_tick", "Tick of the simulator", |sim| {
+            sim.stats.tick as f64
+        }),
+        ("mb_sim_npcs", "Cells alive", |sim| sim.stats.npcs as f64),
+        ("mb_sim_food", "Food in the world", |sim| {
+            sim.stats.food as f64
+        }),
+        ("mb_sim_npc_mass", "Total mass of the cells", |sim| {
+            sim.stats.npc_mass
+        }),
+        ("mb_sim_food_mass", "Total mass of the food", |sim| {
+            sim.stats.food_mass
+        }),
+        ("mb_sim_max_npc_mass", "Mass of the largest cell", |sim| {
+            sim.stats.max_npc_mass
+        }),
+    ];
+    for (name, help, value) in gauges {
+        write_header(out, name, "gauge", help);
+        for (sim, labels) in &sims {
+            write_sample(out, name, labels, value(sim));
+        }
+    }
+}
+
+/// Memory of the server, on platforms that report it in `/proc`
+fn render_process(out: &mut String) {
+    let Some(rss) = resident_memory() else {
+        return;
+    };
+    write_header(
+        out,
+        "process_resident_memory_bytes",
+        "gauge",
+        "Resident memory size in bytes",
+    );
+    write_sample(out, "process_resident_memory_bytes", "", rss);
+}
+
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    // Writing to a String can't fail
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+fn write_sample(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "{name}{labels} {value}");
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    write_header(out, name, "counter", help);
+    write_sample(out, name, "", value.load(Ordering::Relaxed));
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut out = state.metrics.render();
+    render_rooms(&mut out, &state);
+    render_process(&mut out);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
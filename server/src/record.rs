@@ -0,0 +1,258 @@
+//! Recording the frames of a room to disk, and reading them back for replays
+//!
+//! A recording is a gzipped file of JSON lines: a header naming the room and when it started,
+//! then every frame with the time it arrived at since the start. Next to it, a small JSON file
+//! describes the recording for listing without reading it whole.
+
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use protocol::{http::RecordingInfo, ws::ServerMessage, WorldState};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{codec::Encoded, view::IndexedWorld};
+
+pub type RecordError = Box<dyn Error + Send + Sync>;
+
+/// How many frames may wait on the disk before new ones are dropped
+const RECORD_BUFFER: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    room: String,
+    started_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedFrame<S> {
+    time_ms: u64,
+    state: S,
+}
+
+fn recording_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id}.jsonl.gz"))
+}
+
+fn info_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id}.json"))
+}
+
+fn write_info(dir: &Path, info: &RecordingInfo) -> Result<(), RecordError> {
+    fs::write(info_path(dir, info.id), serde_json::to_vec_pretty(info)?)?;
+    Ok(())
+}
+
+/// Frames of a room on their way to disk
+pub struct Recorder {
+    /// The recording as it was started
+    info: RecordingInfo,
+    start: Instant,
+    tx: mpsc::Sender<(u64, Arc<IndexedWorld>)>,
+    writer: JoinHandle<Result<RecordingInfo, RecordError>>,
+}
+
+impl Recorder {
+    /// Start a new recording of a room in `dir`
+    pub fn start(dir: &Path, room: &str) -> Result<Self, RecordError> {
+        fs::create_dir_all(dir)?;
+
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        // Ids are start times, moved along if two recordings start at once
+        let mut id = started_at;
+        while info_path(dir, id).exists() {
+            id += 1;
+        }
+
+        let mut info = RecordingInfo {
+            id,
+            room: room.to_string(),
+            started_at,
+            frames: 0,
+            duration_ms: 0,
+            finished: false,
+        };
+        write_info(dir, &info)?;
+        let started = info.clone();
+
+        let file = File::create(recording_path(dir, id))?;
+        let mut out = GzEncoder::new(BufWriter::new(file), Compression::default());
+        let header = Header {
+            room: info.room.clone(),
+            started_at,
+        };
+        serde_json::to_writer(&mut out, &header)?;
+        out.write_all(b"\n")?;
+
+        let (tx, mut rx) = mpsc::channel::<(u64, Arc<IndexedWorld>)>(RECORD_BUFFER);
+        let dir = dir.to_path_buf();
+        let writer = tokio::task::spawn_blocking(move || {
+            while let Some((time_ms, world)) = rx.blocking_recv() {
+                let frame = RecordedFrame {
+                    time_ms,
+                    state: world.state(),
+                };
+                serde_json::to_writer(&mut out, &frame)?;
+                out.write_all(b"\n")?;
+                info.frames += 1;
+                info.duration_ms = time_ms;
+            }
+            out.finish()?.flush()?;
+            info.finished = true;
+            write_info(&dir, &info)?;
+            Ok(info)
+        });
+
+        Ok(Self {
+            info: started,
+            start: Instant::now(),
+            tx,
+            writer,
+        })
+    }
+
+    pub fn info(&self) -> &RecordingInfo {
+        &self.info
+    }
+
+    /// Queue a frame for writing, dropping it if the disk can't keep up
+    pub fn record(&self, world: Arc<IndexedWorld>) {
+        let time_ms = self.start.elapsed().as_millis() as u64;
+        if self.tx.try_send((time_ms, world)).is_err() {
+            tracing::warn!("recording {} fell behind, dropped a frame", self.info.id);
+        }
+    }
+
+    /// Write out the remaining frames and close the recording
+    pub async fn finish(self) -> Result<RecordingInfo, RecordError> {
+        drop(self.tx);
+        self.writer.await?
+    }
+}
+
+/// Every recording in `dir`, oldest first
+pub fn list(dir: &Path) -> Result<Vec<RecordingInfo>, RecordError> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut recordings = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == "json") {
+            match serde_json::from_slice::<RecordingInfo>(&fs::read(&path)?) {
+                Ok(info) => recordings.push(info),
+                Err(e) => tracing::warn!("skipping {}: {}", path.display(), e),
+            }
+        }
+    }
+    recordings.sort_unstable_by_key(|x| x.id);
+    Ok(recordings)
+}
+
+/// A recording read back into memory, with every frame ready to send
+pub struct Replay {
+    pub info: RecordingInfo,
+    /// Time of each frame since the start, along with it as a `state` message
+    pub frames: Vec<(u64, Encoded)>,
+}
+
+/// Read a recording back. A recording that was cut short is read up to where it ends.
+pub fn load(dir: &Path, id: u64) -> Result<Replay, RecordError> {
+    let mut info = serde_json::from_slice::<RecordingInfo>(&fs::read(info_path(dir, id))?)?;
+    let file = File::open(recording_path(dir, id))?;
+    let mut lines = BufReader::new(GzDecoder::new(file)).lines();
+
+    let header = lines.next().ok_or("recording is empty")??;
+    serde_json::from_str::<Header>(&header)?;
+
+    let mut frames = Vec::new();
+    for line in lines {
+        let frame = match line.map(|x| serde_json::from_str::<RecordedFrame<WorldState>>(&x)) {
+            Ok(Ok(frame)) => frame,
+            Ok(Err(e)) => return Err(e.into()),
+            Err(e) => {
+                tracing::warn!("recording {} ends early: {}", id, e);
+                break;
+            }
+        };
+        let msg = Encoded::new(ServerMessage::State(frame.state));
+        frames.push((frame.time_ms, msg));
+    }
+
+    info.frames = frames.len() as u64;
+    info.duration_ms = frames.last().map_or(0, |(x, _)| *x);
+    Ok(Replay { info, frames })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    /// A directory of its own for each test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mb-record-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn world(tick: u64) -> WorldState {
+        WorldState {
+            tick,
+            size: 100.0,
+            cell_perception_radius: 10.0,
+            food_perception_radius: 5.0,
+            npcs: Vec::new(),
+            food: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn recordings_read_back_as_they_were_recorded() {
+        let dir = temp_dir("round-trip");
+        let recorder = Recorder::start(&dir, "dish").unwrap();
+        assert!(!recorder.info().finished);
+        for tick in 0..5 {
+            recorder.record(Arc::new(IndexedWorld::new(world(tick))));
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        let info = recorder.finish().await.unwrap();
+        assert!(info.finished);
+        assert_eq!(info.frames, 5);
+
+        let listed = list(&dir).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, info.id);
+        assert!(listed[0].finished);
+
+        let replay = load(&dir, info.id).unwrap();
+        assert_eq!(replay.info.room, "dish");
+        assert_eq!(replay.info.frames, 5);
+        assert_eq!(replay.info.duration_ms, info.duration_ms);
+        let mut last_ms = 0;
+        for (tick, (time_ms, msg)) in replay.frames.iter().enumerate() {
+            assert!(*time_ms >= last_ms);
+            last_ms = *time_ms;
+            let msg: Value = serde_json::from_str(&msg.json().unwrap()).unwrap();
+            assert_eq!(msg["event"], "state");
+            assert_eq!(msg["data"]["tick"], tick as u64);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_recordings_fail_to_load() {
+        let dir = temp_dir("missing");
+        assert!(list(&dir).unwrap().is_empty());
+        assert!(load(&dir, 1).is_err());
+    }
+}
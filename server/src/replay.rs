@@ -0,0 +1,390 @@
+//! Playing recordings back over a websocket
+
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use protocol::{
+    ws::{ClientMessage, ServerMessage},
+    PROTOCOL_VERSION,
+};
+
+use crate::{
+    auth::Caller,
+    codec::{self, Format, SUBPROTOCOLS},
+    record::{self, RecordError, Replay},
+    shutdown,
+    state::AppState,
+    ws::handshake,
+};
+
+/// Fastest a replay can be played at
+const MAX_SPEED: f64 = 64.0;
+
+/// Where a replay is and how it's being played
+struct Player {
+    replay: Arc<Replay>,
+    format: Format,
+    /// Next frame to send
+    next: usize,
+    playing: bool,
+    speed: f64,
+    /// Time of the replay at `since`
+    time_ms: u64,
+    since: Instant,
+}
+
+impl Player {
+    fn new(replay: Arc<Replay>, format: Format) -> Self {
+        Self {
+            replay,
+            format,
+            next: 0,
+            playing: true,
+            speed: 1.0,
+            time_ms: 0,
+            since: Instant::now(),
+        }
+    }
+
+    fn now_ms(&self) -> u64 {
+        if self.playing {
+            self.time_ms + (self.since.elapsed().as_secs_f64() * 1000.0 * self.speed) as u64
+        } else {
+            self.time_ms
+        }
+    }
+
+    /// Carry on playing from a time of the replay
+    fn rebase(&mut self, time_ms: u64) {
+        self.time_ms = time_ms;
+        self.since = Instant::now();
+    }
+
+    /// How long until the next frame is due, or `None` if there is nothing left to play
+    fn until_next(&self) -> Option<Duration> {
+        if !self.playing {
+            return None;
+        }
+        let (time_ms, _) = self.replay.frames.get(self.next)?;
+        let wait_ms = time_ms.saturating_sub(self.now_ms()) as f64 / self.speed;
+        Some(Duration::from_secs_f64(wait_ms / 1000.0))
+    }
+
+    /// Move to a time of the replay
+    ///
+    /// **Returns** the frame showing at that time, if any
+    fn seek(&mut self, time_ms: u64) -> Option<Message> {
+        let time_ms = time_ms.min(self.replay.info.duration_ms);
+        self.next = self.replay.frames.partition_point(|(x, _)| *x <= time_ms);
+        self.rebase(time_ms);
+        let (_, msg) = self.replay.frames.get(self.next.checked_sub(1)?)?;
+        msg.get(self.format)
+    }
+
+    fn status(&self) -> ServerMessage {
+        ServerMessage::ReplayStatus {
+            id: self.replay.info.id,
+            time_ms: self.now_ms(),
+            duration_ms: self.replay.info.duration_ms,
+            playing: self.playing,
+            speed: self.speed,
+        }
+    }
+
+    /// Act on a message from the browser
+    ///
+    /// **Returns** the messages to send back
+    fn handle(&mut self, msg: ClientMessage) -> Vec<Message> {
+        let mut out = Vec::new();
+        match msg {
+            ClientMessage::Play if self.next == self.replay.frames.len() => {
+                out.extend(self.seek(0));
+                self.playing = true;
+            }
+            ClientMessage::Play => {
+                self.rebase(self.now_ms());
+                self.playing = true;
+            }
+            ClientMessage::Pause => {
+                self.rebase(self.now_ms());
+                self.playing = false;
+            }
+            ClientMessage::Seek { time_ms } => out.extend(self.seek(time_ms)),
+            ClientMessage::Speed { speed } if !(speed > 0.0 && speed <= MAX_SPEED) => {
+                let msg = format!("speed must be above 0 and at most {MAX_SPEED}");
+                return self
+                    .format
+                    .encode(&ServerMessage::error(msg))
+                    .into_iter()
+                    .collect();
+            }
+            ClientMessage::Speed { speed } => {
+                self.rebase(self.now_ms());
+                self.speed = speed;
+            }
+            _ => {
+                let msg = ServerMessage::error("not available in replays");
+                return self.format.encode(&msg).into_iter().collect();
+            }
+        }
+        out.extend(self.format.encode(&self.status()));
+        out
+    }
+}
+
+/// The recording with `id`, read from disk unless someone is already watching it
+///
+/// Only finished recordings are shared, as the others may still grow.
+async fn open(state: &AppState, id: u64) -> Result<Arc<Replay>, RecordError> {
+    if let Some(replay) = state
+        .replays
+        .lock()
+        .unwrap()
+        .get(&id)
+        .and_then(|x| x.upgrade())
+    {
+        return Ok(replay);
+    }
+    let dir = state.recordings.clone();
+    let replay = Arc::new(tokio::task::spawn_blocking(move || record::load(&dir, id)).await??);
+    if replay.info.finished {
+        let mut replays = state.replays.lock().unwrap();
+        // Someone else may have read it meanwhile
+        if let Some(shared) = replays.get(&id).and_then(|x| x.upgrade()) {
+            return Ok(shared);
+        }
+        replays.retain(|_, x| x.strong_count() > 0);
+        replays.insert(id, Arc::downgrade(&replay));
+    }
+    Ok(replay)
+}
+
+/// Play a recording to a browser, who needs a known token if it presents one like in rooms
+pub async fn replay_handler(
+    ws: WebSocketUpgrade,
+    Path(id): Path<u64>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    _: Caller,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if let Some(reason) = state.refusal() {
+        tracing::warn!("turning away {addr}: {reason}");
+        return (StatusCode::SERVICE_UNAVAILABLE, reason).into_response();
+    }
+    let replay = match open(&state, id).await {
+        Ok(replay) => replay,
+        Err(e) => {
+            tracing::debug!("failed to load recording {}: {}", id, e);
+            return (StatusCode::NOT_FOUND, format!("no recording with id {id}")).into_response();
+        }
+    };
+
+    tracing::debug!("{addr} is replaying recording {id}");
+
+    ws.protocols(SUBPROTOCOLS)
+        .max_message_size(state.limits.max_message_bytes)
+        .on_upgrade(move |socket| {
+            let player = Player::new(replay, Format::of(&socket));
+            play(socket, addr, player, state)
+        })
+}
+
+/// Send the frames of a replay at the pace they were recorded, adjusted by the browser
+async fn play(mut socket: WebSocket, who: SocketAddr, mut player: Player, state: Arc<AppState>) {
+    let format = player.format;
+    let hello = ServerMessage::Hello {
+        version: PROTOCOL_VERSION,
+    };
+    let Some(hello) = format.encode(&hello) else {
+        return;
+    };
+    if socket.send(hello).await.is_err() {
+        return;
+    }
+    if let Err(e) = handshake(&mut socket, state.limits.handshake_timeout).await {
+        tracing::debug!("{who} failed the handshake: {e}");
+        if let Some(msg) = format.encode(&ServerMessage::error(e)) {
+            let _ = socket.send(msg).await;
+        }
+        let _ = socket.close().await;
+        return;
+    }
+
+    // Replays count towards the clients the server allows like any other websocket
+    state.metrics.clients.fetch_add(1, Ordering::Relaxed);
+    stream(&mut socket, &mut player, &state).await;
+    state.metrics.clients.fetch_sub(1, Ordering::Relaxed);
+
+    tracing::debug!(
+        "{who} stopped replaying recording {}",
+        player.replay.info.id
+    );
+}
+
+/// Send frames until the browser leaves or the server shuts down
+async fn stream(socket: &mut WebSocket, player: &mut Player, state: &AppState) {
+    let format = player.format;
+    // Start the clock once the browser is ready to watch
+    player.rebase(0);
+    if let Some(msg) = format.encode(&player.status()) {
+        if !send(socket, msg, state).await {
+            return;
+        }
+    }
+
+    let mut shutdown = state.shutdown.clone();
+    loop {
+        let until_next = player.until_next();
+        let out = tokio::select! {
+            _ = shutdown::requested(&mut shutdown) => {
+                let _ = socket.send(shutdown::close_message()).await;
+                break;
+            }
+            _ = tokio::time::sleep(until_next.unwrap_or_default()), if until_next.is_some() => {
+                let (_, msg) = &player.replay.frames[player.next];
+                let mut out = Vec::from_iter(msg.get(format));
+                player.next += 1;
+                if player.next == player.replay.frames.len() {
+                    player.rebase(player.replay.info.duration_ms);
+                    player.playing = false;
+                    out.extend(format.encode(&player.status()));
+                }
+                out
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(msg)) => match codec::decode(&msg) {
+                    Some(Ok(msg)) => player.handle(msg),
+                    Some(Err(e)) => {
+                        let msg = ServerMessage::error(format!("invalid message: {e}"));
+                        Vec::from_iter(format.encode(&msg))
+                    }
+                    None => continue,
+                },
+            },
+        };
+
+        for msg in out {
+            if !send(socket, msg, state).await {
+                return;
+            }
+        }
+    }
+}
+
+/// Send a message to a browser, counting its bytes
+///
+/// **Returns** whether the browser accepted the message
+async fn send(socket: &mut WebSocket, msg: Message, state: &AppState) -> bool {
+    let len = codec::len(&msg);
+    let sent = socket.send(msg).await.is_ok();
+    if sent {
+        state
+            .metrics
+            .replay_bytes_sent
+            .fetch_add(len, Ordering::Relaxed);
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{http::RecordingInfo, WorldState};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::codec::Encoded;
+
+    /// A replay of a frame every 100ms, 0 to 200
+    fn player() -> Player {
+        let frames = (0..3)
+            .map(|tick| {
+                let state = WorldState {
+                    tick,
+                    size: 100.0,
+                    cell_perception_radius: 10.0,
+                    food_perception_radius: 5.0,
+                    npcs: Vec::new(),
+                    food: Vec::new(),
+                };
+                (tick * 100, Encoded::new(ServerMessage::State(state)))
+            })
+            .collect();
+        let info = RecordingInfo {
+            id: 1,
+            room: "dish".to_string(),
+            started_at: 0,
+            frames: 3,
+            duration_ms: 200,
+            finished: true,
+        };
+        Player::new(Arc::new(Replay { info, frames }), Format::Json)
+    }
+
+    fn json(msg: &Message) -> Value {
+        match msg {
+            Message::Text(text) => serde_json::from_str(text).unwrap(),
+            msg => panic!("expected text, got {msg:?}"),
+        }
+    }
+
+    #[test]
+    fn seeking_shows_the_frame_at_that_time() {
+        let mut player = player();
+        for (time_ms, tick, next) in [(150, 1, 2), (0, 0, 1), (200, 2, 3), (10_000, 2, 3)] {
+            let msg = player.seek(time_ms).unwrap();
+            assert_eq!(json(&msg)["data"]["tick"], tick, "at {time_ms}");
+            assert_eq!(player.next, next, "at {time_ms}");
+        }
+
+        let out = player.handle(ClientMessage::Seek { time_ms: 100 });
+        assert_eq!(json(&out[0])["data"]["tick"], 1);
+        let status = json(&out[1]);
+        assert_eq!(status["event"], "replay_status");
+        assert_eq!(status["data"]["duration_ms"], 200);
+    }
+
+    #[test]
+    fn play_starts_over_at_the_end() {
+        let mut player = player();
+        player.seek(200);
+        player.playing = false;
+        let out = player.handle(ClientMessage::Play);
+        assert_eq!(json(&out[0])["data"]["tick"], 0);
+        assert!(player.playing);
+        assert_eq!(player.next, 1);
+    }
+
+    #[test]
+    fn speed_scales_the_wait_for_frames() {
+        let mut player = player();
+        player.seek(0);
+        let wait = player.until_next().unwrap();
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+
+        let out = player.handle(ClientMessage::Speed { speed: 4.0 });
+        assert_eq!(json(&out[0])["data"]["speed"], 4.0);
+        let wait = player.until_next().unwrap();
+        assert!(wait > Duration::from_millis(15) && wait <= Duration::from_millis(25));
+
+        for speed in [0.0, -1.0, MAX_SPEED * 2.0, f64::NAN] {
+            let out = player.handle(ClientMessage::Speed { speed });
+            assert_eq!(json(&out[0])["event"], "error", "{speed}");
+        }
+        assert_eq!(player.speed, 4.0);
+
+        player.handle(ClientMessage::Pause);
+        assert!(player.until_next().is_none());
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{extract::State, Json};
+use protocol::{
+    http::{RecordingInfo, RoomInfo},
+    ws::ServerMessage,
+    Event, Snapshot, Stats, WorldState,
+};
+use tokio::sync::{broadcast, watch};
+
+use crate::{
+    codec::Encoded, control::SimControl, record::Recorder, state::AppState, view::IndexedWorld,
+};
+
+/// How many snapshots a room keeps around for the API, dropping the oldest first
+const MAX_SNAPSHOTS: usize = 16;
+
+/// How many messages of events a client may fall behind by before it misses some
+const EVENTS_BUFFER: usize = 64;
+
+/// A state message numbered in order of arrival, so clients can tell how many they skipped
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub seq: u64,
+    /// The whole state, sent as is to clients without a viewport
+    pub msg: Option<Arc<Encoded>>,
+    /// The same state, for culling to the viewport of the other clients
+    pub world: Option<Arc<IndexedWorld>>,
+    /// When the frame arrived from the simulator
+    pub published: Option<Instant>,
+}
+
+/// When the simulator was last heard from
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimStatus {
+    pub online: bool,
+    pub last_frame: Option<Instant>,
+    /// Tick of the last frame
+    pub tick: Option<u64>,
+}
+
+/// One simulator and the clients watching it. Clients each run their own writer task reading the
+/// latest frame, so a slow browser never holds up the others or the simulator feed.
+pub struct Room {
+    pub name: String,
+    frames: watch::Sender<Frame>,
+    status: watch::Sender<SimStatus>,
+    /// What happened during each step, for the clients subscribed to it
+    events: broadcast::Sender<Arc<Encoded>>,
+    pub control: Option<SimControl>,
+    /// Websockets and event streams currently in the room
+    pub clients: AtomicU64,
+    /// Bytes of the messages sent to the websockets in the room, summed over them
+    pub bytes_sent: AtomicU64,
+    /// Snapshots taken through the API by id, oldest first
+    snapshots: Mutex<VecDeque<(u64, Arc<Snapshot>)>>,
+    next_snapshot: AtomicU64,
+    recorder: Mutex<Option<Recorder>>,
+    /// Held while a recording starts, for one to start at a time
+    starting_recording: tokio::sync::Mutex<()>,
+    /// Frames the simulator published
+    pub frames_received: AtomicU64,
+    /// Messages of the simulator that could not be read
+    pub frame_errors: AtomicU64,
+    /// What the simulator last reported about itself
+    sim_metrics: Mutex<Option<SimMetrics>>,
+}
+
+/// How a room's simulator is doing, as it last reported
+#[derive(Debug, Clone, Copy)]
+pub struct SimMetrics {
+    pub stats: Stats,
+    /// Average time a step took
+    pub tick_seconds: f64,
+}
+
+impl Room {
+    pub fn new(name: String, control: Option<SimControl>) -> Self {
+        Self {
+            name,
+            frames: watch::Sender::new(Frame::default()),
+            status: watch::Sender::new(SimStatus::default()),
+            events: broadcast::Sender::new(EVENTS_BUFFER),
+            control,
+            clients: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            snapshots: Mutex::new(VecDeque::with_capacity(MAX_SNAPSHOTS)),
+            next_snapshot: AtomicU64::new(1),
+            recorder: Mutex::new(None),
+            starting_recording: tokio::sync::Mutex::new(()),
+            frames_received: AtomicU64::new(0),
+            frame_errors: AtomicU64::new(0),
+            sim_metrics: Mutex::new(None),
+        }
+    }
+
+    /// Replace the latest frame. Clients still busy with an older one skip straight to this one.
+    pub fn publish_frame(&self, state: WorldState) {
+        let published = Instant::now();
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+        let msg = Arc::new(Encoded::new(ServerMessage::State(state.clone())));
+        let world = Arc::new(IndexedWorld::new(state));
+        if let Some(recorder) = &*self.recorder.lock().unwrap() {
+            recorder.record(Arc::clone(&world));
+        }
+        self.frames.send_modify(|frame| {
+            frame.seq += 1;
+            frame.msg = Some(msg);
+            frame.world = Some(world);
+            frame.published = Some(published);
+        });
+    }
+
+    /// Follow the latest frame, starting with the current one
+    pub fn subscribe_frames(&self) -> watch::Receiver<Frame> {
+        let mut frames = self.frames.subscribe();
+        frames.mark_changed();
+        frames
+    }
+
+    /// Send what happened during a step to the clients subscribed to events
+    pub fn publish_events(&self, events: Vec<Event>) {
+        // Nobody may be subscribed
+        let _ = self
+            .events
+            .send(Arc::new(Encoded::new(ServerMessage::Events(events))));
+    }
+
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Arc<Encoded>> {
+        self.events.subscribe()
+    }
+
+    /// Record a frame from the simulator, telling clients if it was offline until now
+    ///
+    /// **Returns** whether it just came online
+    pub fn sim_seen(&self, tick: u64) -> bool {
+        self.status.send_if_modified(|status| {
+            let came_back = !status.online;
+            status.online = true;
+            status.last_frame = Some(Instant::now());
+            status.tick = Some(tick);
+            came_back
+        })
+    }
+
+    /// Mark the simulator as offline if it has been silent for longer than `timeout`
+    ///
+    /// **Returns** whether it just went offline
+    pub fn check_liveness(&self, timeout: Duration) -> bool {
+        self.status.send_if_modified(|status| {
+            let silent = status.online && status.last_frame.is_some_and(|x| x.elapsed() > timeout);
+            if silent {
+                status.online = false;
+            }
+            silent
+        })
+    }
+
+    /// Follow the simulator's status, starting with the current one
+    pub fn subscribe_status(&self) -> watch::Receiver<SimStatus> {
+        let mut status = self.status.subscribe();
+        status.mark_changed();
+        status
+    }
+
+    /// Keep a snapshot for later, forgetting the oldest one if there are too many
+    ///
+    /// **Returns** the id to fetch it with
+    pub fn keep_snapshot(&self, snapshot: Snapshot) -> u64 {
+        let id = self.next_snapshot.fetch_add(1, Ordering::Relaxed);
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if snapshots.len() == MAX_SNAPSHOTS {
+            snapshots.pop_front();
+        }
+        snapshots.push_back((id, Arc::new(snapshot)));
+        id
+    }
+
+    pub fn snapshot(&self, id: u64) -> Option<Arc<Snapshot>> {
+        let snapshots = self.snapshots.lock().unwrap();
+        snapshots
+            .iter()
+            .find(|(x, _)| *x == id)
+            .map(|(_, snapshot)| Arc::clone(snapshot))
+    }
+
+    pub fn set_sim_metrics(&self, metrics: SimMetrics) {
+        *self.sim_metrics.lock().unwrap() = Some(metrics);
+    }
+
+    pub fn sim_metrics(&self) -> Option<SimMetrics> {
+        *self.sim_metrics.lock().unwrap()
+    }
+
+    /// Start recording the room's frames into `dir`
+    pub async fn start_recording(
+        &self,
+        dir: &Path,
+    ) -> Result<RecordingInfo, Box<dyn Error + Send + Sync>> {
+        let _starting = self.starting_recording.lock().await;
+        if self.recorder.lock().unwrap().is_some() {
+            return Err(format!("room {} is already being recorded", self.name).into());
+        }
+        // Creating the files blocks, so it's done off the runtime and without holding the lock
+        let (dir, name) = (dir.to_path_buf(), self.name.clone());
+        let started = tokio::task::spawn_blocking(move || Recorder::start(&dir, &name)).await??;
+        let info = started.info().clone();
+        *self.recorder.lock().unwrap() = Some(started);
+        Ok(info)
+    }
+
+    /// Stop recording the room, if it is being recorded
+    pub async fn stop_recording(
+        &self,
+    ) -> Option<Result<RecordingInfo, Box<dyn Error + Send + Sync>>> {
+        let recorder = self.recorder.lock().unwrap().take()?;
+        Some(recorder.finish().await)
+    }
+
+    pub fn info(&self) -> RoomInfo {
+        let status = *self.status.borrow();
+        RoomInfo {
+            name: self.name.clone(),
+            online: status.online,
+            tick: status.tick,
+            clients: self.clients.load(Ordering::Relaxed),
+            players: self.control.is_some(),
+            recording: self.recorder.lock().unwrap().as_ref().map(|x| x.info().id),
+        }
+    }
+}
+
+pub async fn rooms_handler(State(state): State<Arc<AppState>>) -> Json<Vec<RoomInfo>> {
+    Json(state.rooms.iter().map(|x| x.info()).collect())
+}
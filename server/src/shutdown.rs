@@ -0,0 +1,48 @@
+//! Stopping the server's tasks together when it is asked to exit
+
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use tokio::sync::watch;
+
+/// Resolves once the server starts shutting down
+pub async fn requested(shutdown: &mut watch::Receiver<bool>) {
+    // The sender lives as long as the server, so an error means it is going away too
+    let _ = shutdown.wait_for(|x| *x).await;
+}
+
+/// Resolves on the first SIGINT or SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("failed to listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("received ctrl-c, shutting down"),
+        _ = terminate => tracing::info!("received SIGTERM, shutting down"),
+    }
+}
+
+/// Tells browsers the server is going away, so they know to reconnect later
+pub fn close_message() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::AWAY,
+        reason: "the server is shutting down".into(),
+    }))
+}
@@ -0,0 +1,194 @@
+//! Server-sent events, for dashboards behind proxies that block websockets
+//!
+//! Streams carry the same `state`, `events` and `sim_status` messages as websockets, as JSON in the
+//! data of events named after them, read from the same frames of the room.
+
+use std::{convert::Infallible, sync::atomic::Ordering, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use protocol::ws::ServerMessage;
+use serde::Deserialize;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch,
+    },
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    codec::Encoded,
+    room::{Frame, Room, SimStatus},
+    shutdown,
+    state::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    /// Frames per second the client wants, lowered to the server's limit
+    fps: Option<u32>,
+}
+
+/// One client's view of a room, counted as watching it until the stream is dropped
+struct Watcher {
+    state: Arc<AppState>,
+    room: Arc<Room>,
+    frames: watch::Receiver<Frame>,
+    status: watch::Receiver<SimStatus>,
+    events: broadcast::Receiver<Arc<Encoded>>,
+    shutdown: watch::Receiver<bool>,
+    /// Time to wait between frames
+    interval: Duration,
+    /// When the next frame may be sent
+    next_frame: Instant,
+}
+
+impl Watcher {
+    fn new(state: Arc<AppState>, room: Arc<Room>, interval: Duration) -> Self {
+        state.metrics.sse_clients.fetch_add(1, Ordering::Relaxed);
+        let clients = room.clients.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::debug!("{} clients watching {}", clients, room.name);
+        Self {
+            frames: room.subscribe_frames(),
+            status: room.subscribe_status(),
+            events: room.subscribe_events(),
+            shutdown: state.shutdown.clone(),
+            state,
+            room,
+            interval,
+            next_frame: Instant::now(),
+        }
+    }
+
+    /// Wait for the next change of the simulator's status, the next events or the next frame,
+    /// skipping the frames that arrive quicker than the client's frame rate
+    ///
+    /// **Returns** `None` once the stream should end
+    async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            let Self {
+                frames,
+                status,
+                events,
+                shutdown,
+                next_frame,
+                ..
+            } = self;
+            tokio::select! {
+                _ = shutdown::requested(shutdown) => return None,
+                changed = status.changed() => {
+                    changed.ok()?;
+                    let status = *status.borrow_and_update();
+                    let msg = ServerMessage::SimStatus {
+                        online: status.online,
+                        tick: status.tick,
+                    };
+                    if let Some(data) = Encoded::new(msg).json() {
+                        return Some(event("sim_status", data));
+                    }
+                }
+                // Events aren't frames, so they come through whatever the frame rate
+                msg = events.recv() => match msg {
+                    Ok(msg) => {
+                        if let Some(data) = msg.json() {
+                            return Some(event("events", data));
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::debug!(
+                            "an event stream of {} missed {} messages of events",
+                            self.room.name,
+                            missed
+                        );
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                changed = async {
+                    sleep_until(*next_frame).await;
+                    frames.changed().await
+                } => {
+                    changed.ok()?;
+                    // Nothing to send until the simulator publishes its first frame
+                    let Some(msg) = frames.borrow_and_update().msg.clone() else {
+                        continue;
+                    };
+                    self.next_frame = Instant::now() + self.interval;
+                    if let Some(data) = msg.json() {
+                        return Some(event("state", data));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.state
+            .metrics
+            .sse_clients
+            .fetch_sub(1, Ordering::Relaxed);
+        let clients = self.room.clients.fetch_sub(1, Ordering::Relaxed) - 1;
+        tracing::debug!(
+            "event stream closed, {} left in {}",
+            clients,
+            self.room.name
+        );
+    }
+}
+
+fn event(name: &str, data: String) -> Event {
+    Event::default().event(name).data(data)
+}
+
+/// Stream the default room
+pub async fn sse_handler(
+    Query(query): Query<StreamQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let room = Arc::clone(state.default_room());
+    stream(query, state, room)
+}
+
+/// Stream the room named in the path
+pub async fn room_sse_handler(
+    Path(name): Path<String>,
+    Query(query): Query<StreamQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let Some(room) = state.room(&name).cloned() else {
+        return (StatusCode::NOT_FOUND, format!("no room named {name}")).into_response();
+    };
+    stream(query, state, room)
+}
+
+/// Start streaming a room, unless the server already has as many clients as it allows
+fn stream(query: StreamQuery, state: Arc<AppState>, room: Arc<Room>) -> Response {
+    if let Some(reason) = state.refusal() {
+        tracing::warn!("turning away an event stream: {reason}");
+        return (StatusCode::SERVICE_UNAVAILABLE, reason).into_response();
+    }
+    let max_fps = state.limits.sse_max_fps;
+    let fps = match query.fps {
+        Some(0) => return (StatusCode::BAD_REQUEST, "fps must be greater than 0").into_response(),
+        Some(fps) => fps.min(max_fps),
+        None => max_fps,
+    };
+    let interval = Duration::from_secs_f64(1.0 / fps as f64);
+
+    let watcher = Watcher::new(state, room, interval);
+    let events = futures::stream::unfold(watcher, |mut watcher| async move {
+        let event = watcher.next_event().await?;
+        Some((Ok::<_, Infallible>(event), watcher))
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
@@ -1,51 +1,69 @@
-use std::{collections::HashMap, error::Error, net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Mutex, Weak},
+};
 
-use axum::extract::ws::{Message, WebSocket};
-use futures::{stream::SplitSink, SinkExt};
-use tokio::sync::Mutex;
+use tokio::sync::watch;
 
+use crate::{auth::Access, config::Limits, metrics::Metrics, record::Replay, room::Room};
+
+/// Shared by every connection
 pub struct AppState {
-    pub websockets: HashMap<SocketAddr, SplitSink<WebSocket, Message>>,
+    /// Rooms in the order they were configured, the first one being the default
+    pub rooms: Vec<Arc<Room>>,
+    pub metrics: Metrics,
+    /// Directory recordings of rooms are kept in
+    pub recordings: PathBuf,
+    /// Finished recordings being replayed by id, shared by everyone watching them
+    pub replays: Mutex<HashMap<u64, Weak<Replay>>>,
+    pub limits: Limits,
+    /// Roles of the tokens clients present
+    pub access: Access,
+    /// Turns true once the server starts shutting down
+    pub shutdown: watch::Receiver<bool>,
 }
 
 impl AppState {
-    pub fn new() -> Arc<Mutex<AppState>> {
+    pub fn new(
+        rooms: Vec<Room>,
+        recordings: PathBuf,
+        limits: Limits,
+        access: Access,
+        shutdown: watch::Receiver<bool>,
+    ) -> Arc<AppState> {
         let state = AppState {
-            websockets: HashMap::new(),
+            rooms: rooms.into_iter().map(Arc::new).collect(),
+            metrics: Metrics::default(),
+            recordings,
+            replays: Mutex::new(HashMap::new()),
+            limits,
+            access,
+            shutdown,
         };
 
-        Arc::new(Mutex::new(state))
+        Arc::new(state)
     }
 
-    pub fn add_websocket(&mut self, who: SocketAddr, websocket: SplitSink<WebSocket, Message>) {
-        self.websockets.insert(who, websocket);
-        tracing::debug!("{} websockets connected", self.websockets.len());
+    pub fn room(&self, name: &str) -> Option<&Arc<Room>> {
+        self.rooms.iter().find(|x| x.name == name)
     }
 
-    pub fn drop_websocket(&mut self, who: &SocketAddr) {
-        self.websockets.remove(who);
-        tracing::debug!("{} websockets connected", self.websockets.len());
+    /// Room of clients that don't pick one
+    pub fn default_room(&self) -> &Arc<Room> {
+        &self.rooms[0]
     }
 
-    pub async fn broadcast_to_websockets(&mut self, msg: Message) -> Result<(), Box<dyn Error>> {
-        for sock in self.websockets.values_mut() {
-            match sock.feed(msg.clone()).await {
-                Err(e) => {
-                    eprintln!("failed to feed websocket: {}", e);
-                    continue;
-                }
-                _ => (),
-            }
-
-            match sock.flush().await {
-                Err(e) => {
-                    eprintln!("failed to flush websocket: {}", e);
-                    continue;
-                }
-                _ => (),
-            }
+    /// Why a new websocket or event stream is turned away, if it is
+    pub fn refusal(&self) -> Option<&'static str> {
+        if *self.shutdown.borrow() {
+            return Some("the server is shutting down");
+        }
+        let clients = self.metrics.clients.load(Ordering::Relaxed)
+            + self.metrics.sse_clients.load(Ordering::Relaxed);
+        match self.limits.max_clients {
+            Some(max) if clients >= max => Some("the server is full"),
+            _ => None,
         }
-
-        Ok(())
     }
 }
@@ -0,0 +1,184 @@
+//! Serving HTTPS and WSS straight from a certificate on disk, without a reverse proxy
+
+use std::{io, net::SocketAddr};
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use tokio::{net::TcpListener, sync::watch};
+
+use crate::{config::Tls, shutdown};
+
+/// Read the certificate and its key
+pub async fn load(tls: &Tls) -> io::Result<RustlsConfig> {
+    // Only ring is built in, but the provider has to be picked before the first config
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    RustlsConfig::from_pem_file(&tls.cert, &tls.key).await
+}
+
+/// Read the certificate again on every SIGHUP, keeping the one in use if that fails, until
+/// `shutdown` turns true
+///
+/// New connections get the new certificate, open ones keep theirs.
+pub async fn reload_on_hangup(config: RustlsConfig, tls: Tls, mut shutdown: watch::Receiver<bool>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(e) => {
+                tracing::error!("failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        loop {
+            tokio::select! {
+                _ = sighup.recv() => (),
+                _ = shutdown::requested(&mut shutdown) => break,
+            }
+            match config.reload_from_pem_file(&tls.cert, &tls.key).await {
+                Ok(()) => tracing::info!("reloaded the certificate {}", tls.cert.display()),
+                Err(e) => tracing::error!(
+                    "failed to reload the certificate {}, keeping the old one: {}",
+                    tls.cert.display(),
+                    e
+                ),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (config, tls);
+        shutdown::requested(&mut shutdown).await;
+    }
+}
+
+/// Serve the app over TLS on `listener` until `shutdown` turns true
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    config: RustlsConfig,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let handle = Handle::new();
+    let stop = handle.clone();
+    tokio::spawn(async move {
+        shutdown::requested(&mut shutdown).await;
+        // Websockets close themselves on shutdown, like with plain HTTP
+        stop.graceful_shutdown(None);
+    });
+    axum_server::from_tcp_rustls(listener.into_std()?, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mb-tls-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// **Returns** the PEM certificate and key of a new self signed certificate
+    fn self_signed() -> (String, String) {
+        let pair = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        (pair.cert.pem(), pair.key_pair.serialize_pem())
+    }
+
+    fn write(dir: &Path, (cert, key): &(String, String)) -> Tls {
+        let tls = Tls {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+        fs::write(&tls.cert, cert).unwrap();
+        fs::write(&tls.key, key).unwrap();
+        tls
+    }
+
+    #[tokio::test]
+    async fn certificates_load_with_their_key() {
+        let dir = temp_dir("load");
+        let tls = write(&dir, &self_signed());
+        load(&tls).await.unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bad_certificates_are_refused() {
+        let dir = temp_dir("bad");
+        let (cert, _) = self_signed();
+        let (_, other_key) = self_signed();
+        let mismatched = write(&dir, &(cert.clone(), other_key));
+        assert!(load(&mismatched).await.is_err());
+
+        let garbage = write(&dir, &(cert.clone(), "not a key".to_string()));
+        assert!(load(&garbage).await.is_err());
+
+        let missing = Tls {
+            cert: dir.join("missing.pem"),
+            key: dir.join("key.pem"),
+        };
+        assert!(load(&missing).await.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hangups_reload_the_certificate() {
+        use std::{process::Command, time::Duration};
+
+        use tokio::signal::unix::{signal, SignalKind};
+
+        // Handled from here on, so a hangup sent before the reloader listens can't kill the tests
+        let _sighup = signal(SignalKind::hangup()).unwrap();
+        let hangup = || {
+            let status = Command::new("kill")
+                .args(["-HUP", &std::process::id().to_string()])
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+
+        let dir = temp_dir("reload");
+        let tls = write(&dir, &self_signed());
+        let config = load(&tls).await.unwrap();
+        let first = config.get_inner();
+        let (stop, shutdown) = watch::channel(false);
+        let reloader = tokio::spawn(reload_on_hangup(config.clone(), tls.clone(), shutdown));
+
+        write(&dir, &self_signed());
+        let mut reloaded = config.get_inner();
+        for _ in 0..50 {
+            hangup();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            reloaded = config.get_inner();
+            if !Arc::ptr_eq(&first, &reloaded) {
+                break;
+            }
+        }
+        assert!(!Arc::ptr_eq(&first, &reloaded), "never reloaded");
+
+        // A broken pair keeps the certificate in use
+        fs::write(&tls.key, "not a key").unwrap();
+        hangup();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(Arc::ptr_eq(&reloaded, &config.get_inner()));
+
+        stop.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), reloader)
+            .await
+            .unwrap()
+            .unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,424 @@
+//! Culling frames down to what each browser is looking at
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use protocol::{
+    ws::{LeaderboardEntry, Perception, ServerMessage, ViewState, Viewport},
+    FoodState, NpcKind, NpcState, Stats, WorldState,
+};
+
+use crate::codec::Encoded;
+
+/// Width and height of the cells of the grid entities are bucketed into
+const GRID_CELL: f64 = 50.0;
+
+/// How many players and cells a leaderboard ranks
+const LEADERBOARD_LEN: usize = 10;
+
+/// Entities bucketed by position into a grid, so a viewport only looks at the cells it overlaps
+#[derive(Debug, Default)]
+struct Grid {
+    cols: usize,
+    cells: Vec<Vec<usize>>,
+    /// Largest radius of any entity, by which queries are widened to catch entities poking in
+    max_radius: f64,
+}
+
+impl Grid {
+    fn new<'a>(size: f64, entities: impl Iterator<Item = (&'a [f64; 2], f64)>) -> Self {
+        let cols = (size / GRID_CELL).ceil().max(1.0) as usize;
+        let mut grid = Self {
+            cols,
+            cells: vec![Vec::new(); cols * cols],
+            max_radius: 0.0,
+        };
+        for (i, (pos, radius)) in entities.enumerate() {
+            let cell = grid.col(pos[1]) * cols + grid.col(pos[0]);
+            grid.cells[cell].push(i);
+            grid.max_radius = grid.max_radius.max(radius);
+        }
+        grid
+    }
+
+    fn col(&self, x: f64) -> usize {
+        ((x / GRID_CELL).max(0.0) as usize).min(self.cols - 1)
+    }
+
+    /// Indices of the entities bucketed in the cells that could overlap the given bounds, in the
+    /// order they came in
+    fn query(&self, min: [f64; 2], max: [f64; 2]) -> Vec<usize> {
+        let r = self.max_radius;
+        let (x0, x1) = (self.col(min[0] - r), self.col(max[0] + r));
+        let (y0, y1) = (self.col(min[1] - r), self.col(max[1] + r));
+        let mut found = Vec::new();
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                found.extend_from_slice(&self.cells[y * self.cols + x]);
+            }
+        }
+        // Keep the simulator's drawing order
+        found.sort_unstable();
+        found
+    }
+}
+
+/// A frame along with the index needed to cull it for each viewport
+#[derive(Debug)]
+pub struct IndexedWorld {
+    state: WorldState,
+    npcs: Grid,
+    food: Grid,
+    /// Messages derived from the frame, made for the first client subscribed to them
+    stats: OnceLock<Encoded>,
+    leaderboard: OnceLock<Encoded>,
+}
+
+impl IndexedWorld {
+    pub fn new(state: WorldState) -> Self {
+        let npcs = Grid::new(state.size, state.npcs.iter().map(|x| (&x.pos, x.radius)));
+        let food = Grid::new(state.size, state.food.iter().map(|x| (&x.pos, x.radius)));
+        Self {
+            state,
+            npcs,
+            food,
+            stats: OnceLock::new(),
+            leaderboard: OnceLock::new(),
+        }
+    }
+
+    pub fn state(&self) -> &WorldState {
+        &self.state
+    }
+
+    /// A `stats` message of the frame
+    pub fn stats(&self) -> &Encoded {
+        self.stats.get_or_init(|| {
+            let state = &self.state;
+            let stats = Stats {
+                tick: state.tick,
+                npcs: state.npcs.len(),
+                food: state.food.len(),
+                npc_mass: state.npcs.iter().map(|x| x.mass).sum(),
+                max_npc_mass: state.npcs.iter().map(|x| x.mass).fold(0.0, f64::max),
+                food_mass: state.food.iter().map(|x| x.mass).sum(),
+            };
+            Encoded::new(ServerMessage::Stats(stats))
+        })
+    }
+
+    /// A `leaderboard` message of the frame, ranking players by the mass of all their cells
+    pub fn leaderboard(&self) -> &Encoded {
+        self.leaderboard.get_or_init(|| {
+            let mut entries: Vec<LeaderboardEntry> = Vec::new();
+            // Index of each player's entry
+            let mut players = HashMap::new();
+            for npc in &self.state.npcs {
+                let entry = match &npc.kind {
+                    NpcKind::Controlled { owner, name, .. } => {
+                        let i = *players.entry(*owner).or_insert_with(|| {
+                            entries.push(LeaderboardEntry {
+                                id: *owner,
+                                name: name.clone(),
+                                mass: 0.0,
+                                cells: 0,
+                            });
+                            entries.len() - 1
+                        });
+                        &mut entries[i]
+                    }
+                    _ => {
+                        entries.push(LeaderboardEntry {
+                            id: npc.id,
+                            name: None,
+                            mass: 0.0,
+                            cells: 0,
+                        });
+                        entries.last_mut().unwrap()
+                    }
+                };
+                entry.mass += npc.mass;
+                entry.cells += 1;
+            }
+            entries.sort_by(|a, b| b.mass.total_cmp(&a.mass));
+            entries.truncate(LEADERBOARD_LEN);
+            Encoded::new(ServerMessage::Leaderboard(entries))
+        })
+    }
+
+    /// Cells with the given id, or owned by the owner with that id
+    fn cells(&self, id: u64) -> impl Iterator<Item = &NpcState> {
+        self.state.npcs.iter().filter(move |x| {
+            x.id == id || matches!(x.kind, NpcKind::Controlled { owner, .. } if owner == id)
+        })
+    }
+
+    /// Center of the entity with the given id, or of the cells of the owner with that id,
+    /// weighted by mass
+    pub fn locate(&self, id: u64) -> Option<[f64; 2]> {
+        let mut mass = 0.0;
+        let mut sum = [0.0, 0.0];
+        for npc in self.cells(id) {
+            mass += npc.mass;
+            sum[0] += npc.pos[0] * npc.mass;
+            sum[1] += npc.pos[1] * npc.mass;
+        }
+        (mass > 0.0).then(|| [sum[0] / mass, sum[1] / mass])
+    }
+
+    /// The entities overlapping the viewport, positioned relative to it
+    ///
+    /// With `fog` set to an entity or owner id, only the cells with that id and what they perceive
+    /// are kept. Like the simulator, a cell perceives entities whose center is within its
+    /// perception radius.
+    pub fn view(&self, viewport: &Viewport, fog: Option<u64>) -> ViewState {
+        let (min, max) = viewport.bounds();
+        let zoom = viewport.zoom;
+        let visible = |pos: &[f64; 2], radius: f64| {
+            pos[0] + radius >= min[0]
+                && pos[0] - radius <= max[0]
+                && pos[1] + radius >= min[1]
+                && pos[1] - radius <= max[1]
+        };
+        let project = |pos: &[f64; 2]| [(pos[0] - min[0]) * zoom, (pos[1] - min[1]) * zoom];
+
+        let perceivers = fog.map(|id| self.cells(id).map(|x| (x.id, x.pos)).collect::<Vec<_>>());
+        let perceived = |pos: &[f64; 2], radius: f64| {
+            let Some(perceivers) = &perceivers else {
+                return true;
+            };
+            perceivers.iter().any(|(_, x)| {
+                let (dx, dy) = (pos[0] - x[0], pos[1] - x[1]);
+                dx * dx + dy * dy <= radius * radius
+            })
+        };
+        let is_perceiver = |id: u64| {
+            perceivers
+                .as_ref()
+                .is_some_and(|x| x.iter().any(|(x, _)| *x == id))
+        };
+
+        let npcs = self
+            .npcs
+            .query(min, max)
+            .into_iter()
+            .map(|i| &self.state.npcs[i])
+            .filter(|x| visible(&x.pos, x.radius))
+            .filter(|x| is_perceiver(x.id) || perceived(&x.pos, self.state.cell_perception_radius))
+            .map(|x| NpcState {
+                pos: project(&x.pos),
+                radius: x.radius * zoom,
+                ..x.clone()
+            })
+            .collect();
+        let food = self
+            .food
+            .query(min, max)
+            .into_iter()
+            .map(|i| &self.state.food[i])
+            .filter(|x| visible(&x.pos, x.radius))
+            .filter(|x| perceived(&x.pos, self.state.food_perception_radius))
+            .map(|x| FoodState {
+                pos: project(&x.pos),
+                radius: x.radius * zoom,
+                ..x.clone()
+            })
+            .collect();
+
+        let perception = perceivers
+            .iter()
+            .flatten()
+            .map(|(_, pos)| Perception {
+                pos: project(pos),
+                cells: self.state.cell_perception_radius * zoom,
+                food: self.state.food_perception_radius * zoom,
+            })
+            .collect();
+
+        ViewState {
+            tick: self.state.tick,
+            size: self.state.size,
+            origin: min,
+            zoom,
+            perception,
+            npcs,
+            food,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::Action;
+
+    use super::*;
+
+    fn npc(id: u64, pos: [f64; 2], radius: f64) -> NpcState {
+        NpcState {
+            id,
+            pos,
+            mass: radius * radius,
+            radius,
+            color: String::new(),
+            kind: NpcKind::Linear { dir: [1.0, 0.0] },
+        }
+    }
+
+    fn player(id: u64, owner: u64, pos: [f64; 2], radius: f64) -> NpcState {
+        NpcState {
+            kind: NpcKind::Controlled {
+                owner,
+                name: None,
+                action: Action::default(),
+            },
+            ..npc(id, pos, radius)
+        }
+    }
+
+    fn food(id: u64, pos: [f64; 2]) -> FoodState {
+        FoodState {
+            id,
+            pos,
+            mass: 1.0,
+            radius: 1.0,
+            color: String::new(),
+        }
+    }
+
+    fn world(npcs: Vec<NpcState>, food: Vec<FoodState>) -> IndexedWorld {
+        IndexedWorld::new(WorldState {
+            tick: 3,
+            size: 200.0,
+            cell_perception_radius: 30.0,
+            food_perception_radius: 10.0,
+            npcs,
+            food,
+        })
+    }
+
+    fn ids(view: &ViewState) -> (Vec<u64>, Vec<u64>) {
+        (
+            view.npcs.iter().map(|x| x.id).collect(),
+            view.food.iter().map(|x| x.id).collect(),
+        )
+    }
+
+    /// From 40 to 60 on both axes, at twice the size
+    const VIEWPORT: Viewport = Viewport {
+        center: [50.0, 50.0],
+        zoom: 2.0,
+        size: [40.0, 40.0],
+    };
+
+    #[test]
+    fn views_keep_what_overlaps_them() {
+        let world = world(
+            vec![
+                npc(1, [50.0, 50.0], 2.0),
+                npc(2, [100.0, 100.0], 2.0),
+                // Poking in from outside, and just missing
+                npc(3, [62.0, 50.0], 3.0),
+                npc(4, [64.0, 50.0], 3.0),
+            ],
+            vec![
+                // Right on the border, and just past it
+                food(5, [60.0, 40.0]),
+                food(6, [61.5, 40.0]),
+                food(7, [39.0, 61.0]),
+            ],
+        );
+        let view = world.view(&VIEWPORT, None);
+        assert_eq!(ids(&view), (vec![1, 3], vec![5, 7]));
+
+        // Positioned and sized on screen
+        assert_eq!(view.origin, [40.0, 40.0]);
+        assert_eq!(view.npcs[0].pos, [20.0, 20.0]);
+        assert_eq!(view.npcs[0].radius, 4.0);
+        assert_eq!(view.food[0].pos, [40.0, 0.0]);
+        assert_eq!(view.tick, 3);
+        assert!(view.perception.is_empty());
+    }
+
+    #[test]
+    fn entities_straddling_grid_cells_are_found() {
+        // Grid cells end at 50, the view starts past that
+        let viewport = Viewport {
+            center: [55.0, 25.0],
+            zoom: 1.0,
+            size: [6.0, 50.0],
+        };
+        let world = world(
+            vec![
+                npc(1, [49.0, 25.0], 5.0),
+                npc(2, [50.0, 25.0], 3.0),
+                npc(3, [45.0, 25.0], 1.0),
+                // Outside of the biome, bucketed at its edges
+                npc(4, [-10.0, 25.0], 1.0),
+                npc(5, [250.0, 25.0], 1.0),
+            ],
+            Vec::new(),
+        );
+        assert_eq!(ids(&world.view(&viewport, None)).0, [1, 2]);
+
+        let everything = Viewport {
+            center: [100.0, 100.0],
+            zoom: 1.0,
+            size: [400.0, 400.0],
+        };
+        assert_eq!(ids(&world.view(&everything, None)).0, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn followed_players_are_located_by_mass() {
+        let world = world(
+            vec![
+                player(1, 7, [10.0, 10.0], 1.0),
+                npc(2, [100.0, 100.0], 2.0),
+                player(3, 7, [40.0, 10.0], 3f64.sqrt()),
+            ],
+            Vec::new(),
+        );
+        let [x, y] = world.locate(7).unwrap();
+        assert!((x - 32.5).abs() < 1e-9 && (y - 10.0).abs() < 1e-9);
+        assert_eq!(world.locate(2), Some([100.0, 100.0]));
+        assert_eq!(world.locate(3), Some([40.0, 10.0]));
+        assert_eq!(world.locate(99), None);
+    }
+
+    #[test]
+    fn fog_keeps_what_the_followed_cells_perceive() {
+        let world = world(
+            vec![
+                player(1, 7, [50.0, 50.0], 2.0),
+                // Within the cell perception radius of 30, exactly on it and past it
+                npc(2, [70.0, 50.0], 1.0),
+                npc(3, [50.0, 80.0], 1.0),
+                npc(4, [50.0, 81.0], 1.0),
+            ],
+            // Within the food perception radius of 10 and past it
+            vec![food(5, [55.0, 55.0]), food(6, [50.0, 61.0])],
+        );
+        let everything = Viewport {
+            center: [100.0, 100.0],
+            zoom: 1.0,
+            size: [200.0, 200.0],
+        };
+        assert_eq!(
+            ids(&world.view(&everything, None)),
+            (vec![1, 2, 3, 4], vec![5, 6])
+        );
+
+        let view = world.view(&everything, Some(7));
+        assert_eq!(ids(&view), (vec![1, 2, 3], vec![5]));
+        assert_eq!(
+            view.perception,
+            [Perception {
+                pos: [50.0, 50.0],
+                cells: 30.0,
+                food: 10.0,
+            }]
+        );
+
+        // Fog of a cell nobody has shows nothing
+        assert_eq!(ids(&world.view(&everything, Some(99))), (vec![], vec![]));
+    }
+}
@@ -1,72 +1,565 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        ConnectInfo, State,
+        ConnectInfo, Path, State,
     },
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 use axum_extra::{headers, TypedHeader};
-use futures::{stream::StreamExt, SinkExt};
-use std::{net::SocketAddr, sync::Arc};
-use tokio::sync::Mutex;
+use futures::{
+    stream::{SplitSink, StreamExt},
+    SinkExt,
+};
+use protocol::{
+    control::{Command, Reply},
+    ws::{ClientMessage, MessageKind, ServerMessage, Viewport},
+    Action, PROTOCOL_VERSION,
+};
+use std::{net::SocketAddr, sync::atomic::Ordering, sync::Arc, time::Duration};
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, watch,
+    },
+    time::{sleep_until, timeout, Instant},
+};
+
+use crate::{
+    auth::{Caller, Role},
+    codec::{self, Encoded, Format, SUBPROTOCOLS},
+    control::SimControl,
+    room::Room,
+    shutdown,
+    state::AppState,
+};
+
+/// What part of the biome a client wants to see
+#[derive(Debug, Clone, Copy, Default)]
+struct Camera {
+    /// The whole biome is sent as long as there is none
+    viewport: Option<Viewport>,
+    /// Entity or owner to keep the viewport centered on
+    follow: Option<u64>,
+    /// Whether to only send what the followed cells perceive
+    fog: bool,
+}
+
+/// Lowest frame rate a client can subscribe to, a frame every 100 seconds
+const MIN_FPS: f64 = 0.01;
+
+/// Which messages a client wants, and how often it wants frames
+#[derive(Debug, Clone)]
+struct Subscription {
+    kinds: Vec<MessageKind>,
+    /// Least time between two frames, if limited
+    interval: Option<Duration>,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self {
+            kinds: vec![MessageKind::State],
+            interval: None,
+        }
+    }
+}
+
+impl Subscription {
+    fn wants(&self, kind: MessageKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+
+    /// Whether any of the kinds it wants are made from frames
+    fn wants_frames(&self) -> bool {
+        self.kinds.iter().any(|x| *x != MessageKind::Events)
+    }
+}
+
+/// What a client asked to receive
+#[derive(Debug, Clone, Default)]
+struct Prefs {
+    camera: Camera,
+    subscription: Subscription,
+}
+
+/// Wait for the browser to answer the server's `hello` with the same protocol version
+pub async fn handshake(socket: &mut WebSocket, limit: Duration) -> Result<(), String> {
+    let msg = match timeout(limit, socket.recv()).await {
+        Ok(Some(Ok(msg))) => msg,
+        Ok(_) => return Err("expected hello".to_string()),
+        Err(_) => return Err("timed out waiting for hello".to_string()),
+    };
+
+    match codec::decode(&msg) {
+        Some(Ok(ClientMessage::Hello { version })) if version == PROTOCOL_VERSION => Ok(()),
+        Some(Ok(ClientMessage::Hello { version })) => Err(format!(
+            "protocol version {version} is not supported, expected {PROTOCOL_VERSION}"
+        )),
+        _ => Err("expected hello".to_string()),
+    }
+}
+
+fn is_prefs_message(msg: &ClientMessage) -> bool {
+    matches!(
+        msg,
+        ClientMessage::View(_)
+            | ClientMessage::Follow { .. }
+            | ClientMessage::Fog { .. }
+            | ClientMessage::ClearView
+            | ClientMessage::Subscribe { .. }
+    )
+}
+
+/// Whether the message is about the cells of a player, which spectators can't have
+fn is_player_message(msg: &ClientMessage) -> bool {
+    matches!(
+        msg,
+        ClientMessage::Join { .. }
+            | ClientMessage::Steer { .. }
+            | ClientMessage::Split
+            | ClientMessage::Eject
+            | ClientMessage::Leave
+    )
+}
+
+/// Point a client's camera where it asked, or change what it is subscribed to
+///
+/// **Returns** an error to send back to the browser, if any
+fn update_prefs(msg: ClientMessage, prefs: &watch::Sender<Prefs>) -> Option<ServerMessage> {
+    match msg {
+        ClientMessage::View(viewport)
+            if !(viewport.zoom.is_finite() && viewport.zoom > 0.0)
+                || viewport.size.iter().any(|x| !x.is_finite() || *x < 0.0) =>
+        {
+            return Some(ServerMessage::error("invalid viewport"))
+        }
+        ClientMessage::View(viewport) => prefs.send_modify(|x| x.camera.viewport = Some(viewport)),
+        ClientMessage::Follow { .. } if prefs.borrow().camera.viewport.is_none() => {
+            return Some(ServerMessage::error("send a view first"))
+        }
+        ClientMessage::Follow { id } => prefs.send_modify(|x| x.camera.follow = id),
+        ClientMessage::Fog { enabled: true } if prefs.borrow().camera.follow.is_none() => {
+            return Some(ServerMessage::error("follow a cell first"))
+        }
+        ClientMessage::Fog { enabled } => prefs.send_modify(|x| x.camera.fog = enabled),
+        ClientMessage::ClearView => prefs.send_modify(|x| x.camera = Camera::default()),
+        ClientMessage::Subscribe { max_fps, .. }
+            if max_fps.is_some_and(|x| x.is_nan() || x < MIN_FPS) =>
+        {
+            return Some(ServerMessage::error(format!(
+                "max_fps must be at least {MIN_FPS}"
+            )))
+        }
+        ClientMessage::Subscribe { max_fps, kinds } => prefs.send_modify(|x| {
+            x.subscription = Subscription {
+                kinds,
+                interval: max_fps.map(|fps| Duration::from_secs_f64(1.0 / fps)),
+            }
+        }),
+        _ => (),
+    }
+    None
+}
+
+/// Forward a message from a browser to the simulator, keeping track of the player it controls
+///
+/// **Returns** a message to send back to the browser, if any
+async fn handle_client_message(
+    msg: ClientMessage,
+    player: &mut Option<u64>,
+    control: &SimControl,
+) -> Option<ServerMessage> {
+    let cmd = match (msg, *player) {
+        (ClientMessage::Hello { .. }, _) => {
+            return Some(ServerMessage::error("already said hello"))
+        }
+        // Handled by the client's prefs
+        (
+            ClientMessage::View(_)
+            | ClientMessage::Follow { .. }
+            | ClientMessage::Fog { .. }
+            | ClientMessage::ClearView
+            | ClientMessage::Subscribe { .. },
+            _,
+        ) => return None,
+        (
+            ClientMessage::Play
+            | ClientMessage::Pause
+            | ClientMessage::Seek { .. }
+            | ClientMessage::Speed { .. },
+            _,
+        ) => return Some(ServerMessage::error("only replays can be played")),
+        (ClientMessage::Join { .. }, Some(_)) => {
+            return Some(ServerMessage::error("already joined"))
+        }
+        (ClientMessage::Join { name }, None) => Command::Join { name },
+        (ClientMessage::Steer { target }, Some(owner)) => Command::Steer {
+            owner,
+            action: Action {
+                target: Some(target),
+                dir: None,
+            },
+        },
+        (ClientMessage::Split, Some(owner)) => Command::Split { owner },
+        (ClientMessage::Eject, Some(owner)) => Command::Eject { owner },
+        (ClientMessage::Leave, Some(owner)) => Command::Leave { owner },
+        (_, None) => return Some(ServerMessage::error("join first")),
+    };
+    let is_leave = matches!(cmd, Command::Leave { .. });
 
-use crate::state::AppState;
+    match control.request(cmd).await {
+        Ok(Reply::Spawned { id }) => {
+            *player = Some(id);
+            Some(ServerMessage::Joined { owner: id })
+        }
+        // The simulator only refuses player commands once all of the player's cells are gone
+        Ok(Reply::Error { message }) if player.is_some() => {
+            *player = None;
+            Some(ServerMessage::Left { reason: message })
+        }
+        Ok(Reply::Error { message }) => Some(ServerMessage::Error { message }),
+        Ok(_) if is_leave => {
+            *player = None;
+            Some(ServerMessage::Left {
+                reason: "left".to_string(),
+            })
+        }
+        Ok(_) => None,
+        Err(e) => Some(ServerMessage::error(e)),
+    }
+}
+
+fn user_agent_name(user_agent: Option<TypedHeader<headers::UserAgent>>) -> String {
+    if let Some(TypedHeader(user_agent)) = user_agent {
+        user_agent.to_string()
+    } else {
+        String::from("Unknown browser")
+    }
+}
 
+/// Connect to the default room
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    State(state): State<Arc<Mutex<AppState>>>,
-) -> impl IntoResponse {
-    let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
-        user_agent.to_string()
-    } else {
-        String::from("Unknown browser")
+    Caller(role): Caller,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let room = Arc::clone(state.default_room());
+    upgrade(ws, user_agent, addr, role, state, room)
+}
+
+/// Connect to the room named in the path
+pub async fn room_ws_handler(
+    ws: WebSocketUpgrade,
+    Path(name): Path<String>,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Caller(role): Caller,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let Some(room) = state.room(&name).cloned() else {
+        return (StatusCode::NOT_FOUND, format!("no room named {name}")).into_response();
     };
+    upgrade(ws, user_agent, addr, role, state, room)
+}
+
+/// Accept a browser into a room, unless the server already has as many clients as it allows
+fn upgrade(
+    ws: WebSocketUpgrade,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    addr: SocketAddr,
+    role: Role,
+    state: Arc<AppState>,
+    room: Arc<Room>,
+) -> Response {
+    if let Some(reason) = state.refusal() {
+        tracing::warn!("turning away {addr}: {reason}");
+        return (StatusCode::SERVICE_UNAVAILABLE, reason).into_response();
+    }
+    let limits = state.limits;
+    let user_agent = user_agent_name(user_agent);
+
+    tracing::debug!("`{user_agent}` at {addr} connected to {}", room.name);
+
+    ws.protocols(SUBPROTOCOLS)
+        .max_message_size(limits.max_message_bytes)
+        .on_upgrade(move |socket| handle_socket(socket, addr, role, state, room))
+}
+
+/// The next message of events, never resolving for clients that aren't subscribed to them
+async fn next_events(
+    events: &mut Option<broadcast::Receiver<Arc<Encoded>>>,
+) -> Result<Arc<Encoded>, RecvError> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Send one client the latest frames, culled to its viewport if it has one, along with the other
+/// messages it subscribed to, changes of the simulator's status and its replies, skipping frames it
+/// is too slow for or that come quicker than it asked for
+///
+/// **Returns** once the client is gone or too slow to keep up
+async fn write_messages(
+    mut sink: SplitSink<WebSocket, Message>,
+    room: Arc<Room>,
+    mut replies: mpsc::Receiver<Message>,
+    mut prefs: watch::Receiver<Prefs>,
+    format: Format,
+    state: Arc<AppState>,
+    who: SocketAddr,
+) {
+    let mut frames = room.subscribe_frames();
+    let mut status = room.subscribe_status();
+    let mut events = None;
+    let mut last_seq = frames.borrow().seq.saturating_sub(1);
+    let mut wants = Prefs::default();
+    let mut next_frame = Instant::now();
+    let mut shutdown = state.shutdown.clone();
+
+    loop {
+        // Frames carry when they were published, to measure how long they take to reach clients
+        let (msgs, published) = tokio::select! {
+            _ = shutdown::requested(&mut shutdown) => {
+                let limit = state.limits.slow_client_timeout;
+                let _ = timeout(limit, sink.send(shutdown::close_message())).await;
+                break;
+            }
+            Some(msg) = replies.recv() => (vec![msg], None),
+            changed = prefs.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                wants = prefs.borrow_and_update().clone();
+                if !wants.subscription.wants(MessageKind::Events) {
+                    events = None;
+                } else if events.is_none() {
+                    events = Some(room.subscribe_events());
+                }
+                continue;
+            }
+            changed = status.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let status = *status.borrow_and_update();
+                let msg = format.encode(&ServerMessage::SimStatus {
+                    online: status.online,
+                    tick: status.tick,
+                });
+                (Vec::from_iter(msg), None)
+            }
+            msg = next_events(&mut events) => match msg {
+                Ok(msg) => (Vec::from_iter(msg.get(format)), None),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::debug!("{} missed {} messages of events", who, missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            changed = async {
+                sleep_until(next_frame).await;
+                frames.changed().await
+            }, if wants.subscription.wants_frames() => {
+                if changed.is_err() {
+                    break;
+                }
+                let frame = frames.borrow_and_update().clone();
+                // Frames left out to keep to the client's frame rate weren't missed
+                if wants.subscription.interval.is_none() {
+                    let skipped = frame.seq.saturating_sub(last_seq + 1);
+                    state.metrics.frames_skipped.fetch_add(skipped, Ordering::Relaxed);
+                }
+                last_seq = frame.seq;
+                let (Some(world), Some(msg)) = (frame.world, frame.msg) else {
+                    continue;
+                };
+                if let Some(interval) = wants.subscription.interval {
+                    next_frame = Instant::now() + interval;
+                }
 
-    println!("`{user_agent}` at {addr} connected.");
+                let mut msgs = Vec::new();
+                if wants.subscription.wants(MessageKind::State) {
+                    let camera = &mut wants.camera;
+                    msgs.extend(match &mut camera.viewport {
+                        Some(viewport) => {
+                            // Stay where the followed cells were last seen once they are gone
+                            if let Some(center) = camera.follow.and_then(|id| world.locate(id)) {
+                                viewport.center = center;
+                            }
+                            let fog = camera.follow.filter(|_| camera.fog);
+                            format.encode(&ServerMessage::View(world.view(viewport, fog)))
+                        }
+                        None => msg.get(format),
+                    });
+                }
+                if wants.subscription.wants(MessageKind::Stats) {
+                    msgs.extend(world.stats().get(format));
+                }
+                if wants.subscription.wants(MessageKind::Leaderboard) {
+                    msgs.extend(world.leaderboard().get(format));
+                }
+                (msgs, frame.published)
+            }
+        };
+
+        for msg in msgs {
+            if !send(&mut sink, msg, &state, &room, who).await {
+                return;
+            }
+        }
+        if let Some(published) = published {
+            state.metrics.broadcast_latency.observe(published.elapsed());
+        }
+    }
+}
 
-    ws.on_upgrade(move |socket| handle_socket(socket, addr, Arc::clone(&state)))
+/// Send a message to a client, giving up on it if it takes too long to accept it
+///
+/// **Returns** whether the client accepted the message
+async fn send(
+    sink: &mut SplitSink<WebSocket, Message>,
+    msg: Message,
+    state: &AppState,
+    room: &Room,
+    who: SocketAddr,
+) -> bool {
+    let len = codec::len(&msg);
+    let slow_client_timeout = state.limits.slow_client_timeout;
+    match timeout(slow_client_timeout, sink.send(msg)).await {
+        Ok(Ok(())) => {
+            room.bytes_sent.fetch_add(len, Ordering::Relaxed);
+            true
+        }
+        Ok(Err(e)) => {
+            tracing::debug!("failed to send to websocket {}: {}", who, e);
+            false
+        }
+        Err(_) => {
+            tracing::warn!(
+                "disconnecting {}, it took over {:?} to take a message",
+                who,
+                slow_client_timeout
+            );
+            state
+                .metrics
+                .slow_disconnects
+                .fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
 }
 
-pub async fn handle_socket(mut socket: WebSocket, who: SocketAddr, state: Arc<Mutex<AppState>>) {
-    if socket
-        .send(Message::Ping("ping".as_bytes().to_vec()))
-        .await
-        .is_ok()
-    {
-        tracing::debug!("{who} websocket connected");
+pub async fn handle_socket(
+    mut socket: WebSocket,
+    who: SocketAddr,
+    role: Role,
+    state: Arc<AppState>,
+    room: Arc<Room>,
+) {
+    let format = Format::of(&socket);
+    let hello = ServerMessage::Hello {
+        version: PROTOCOL_VERSION,
+    };
+    let sent = match format.encode(&hello) {
+        Some(hello) => socket.send(hello).await.is_ok(),
+        None => false,
+    };
+    if sent {
+        tracing::debug!("{who} websocket connected as {format:?}");
     } else {
         tracing::error!("{who} websocket failed to connect");
         return;
     }
 
-    socket.flush().await.unwrap();
+    if let Err(e) = socket.flush().await {
+        tracing::debug!("{who} websocket closed before the handshake: {e}");
+        return;
+    }
+
+    if let Err(e) = handshake(&mut socket, state.limits.handshake_timeout).await {
+        tracing::debug!("{who} failed the handshake: {e}");
+        if let Some(msg) = format.encode(&ServerMessage::error(e)) {
+            let _ = socket.send(msg).await;
+        }
+        let _ = socket.close().await;
+        return;
+    }
 
     let (sender, mut receiver) = socket.split();
 
-    let mut s = (*state).lock().await;
-    s.add_websocket(who.clone(), sender);
-    drop(s);
+    let (reply_tx, reply_rx) = mpsc::channel(16);
+    let (prefs_tx, prefs_rx) = watch::channel(Prefs::default());
+    let mut writer = tokio::spawn(write_messages(
+        sender,
+        Arc::clone(&room),
+        reply_rx,
+        prefs_rx,
+        format,
+        Arc::clone(&state),
+        who,
+    ));
+    state.metrics.clients.fetch_add(1, Ordering::Relaxed);
+    let clients = room.clients.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::debug!("{} websockets connected to {}", clients, room.name);
 
-    let recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = receiver.next().await {
-            let msg_str = match msg.to_text() {
-                Ok(s) => s,
-                Err(e) => {
-                    tracing::error!("failed to parse websocket message: {}", e);
-                    break;
-                }
-            };
+    let control = room.control.clone();
+    let mut player = None;
+
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.next() => msg,
+            // The writer gave up on the client
+            _ = &mut writer => break,
+        };
+        let msg = match msg {
+            Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+            Some(Ok(msg)) => match codec::decode(&msg) {
+                Some(msg) => msg,
+                None => continue,
+            },
+        };
+
+        tracing::debug!("received from socket: {:?}", msg);
+
+        let reply = match (msg, &control) {
+            (Ok(msg), _) if is_prefs_message(&msg) => update_prefs(msg, &prefs_tx),
+            (Ok(msg), _) if is_player_message(&msg) && role < Role::Player => {
+                Some(ServerMessage::error(format!(
+                    "playing takes the player role, not {}",
+                    role.name()
+                )))
+            }
+            (Ok(msg), Some(control)) => handle_client_message(msg, &mut player, control).await,
+            (Ok(_), None) => Some(ServerMessage::error("players are disabled in this room")),
+            (Err(e), _) => Some(ServerMessage::error(format!("invalid message: {e}"))),
+        };
 
-            tracing::debug!("received from socket: {}", msg_str);
+        if let Some(reply) = reply.and_then(|reply| format.encode(&reply)) {
+            if reply_tx.send(reply).await.is_err() {
+                break;
+            }
         }
-    });
+    }
 
-    let (_,) = tokio::join!(recv_task);
+    writer.abort();
 
-    tracing::debug!("websocket disconnected for {}", who);
+    // Take the player's cells with it when the browser goes away
+    if let (Some(owner), Some(control)) = (player, &control) {
+        if let Err(e) = control.request(Command::Leave { owner }).await {
+            tracing::error!("failed to remove player {} for {}: {}", owner, who, e);
+        }
+    }
 
-    let mut s = (*state).lock().await;
-    s.drop_websocket(&who);
+    state.metrics.clients.fetch_sub(1, Ordering::Relaxed);
+    let clients = room.clients.fetch_sub(1, Ordering::Relaxed) - 1;
+    tracing::debug!(
+        "websocket disconnected for {}, {} left in {}",
+        who,
+        clients,
+        room.name
+    );
 }
@@ -0,0 +1,143 @@
+//! Checks what clients may do with and without tokens, in an embedded room
+#![cfg(feature = "embedded")]
+
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use protocol::{
+    ws::{ClientMessage, ServerMessage},
+    PROTOCOL_VERSION,
+};
+use tokio::time::timeout;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, Message},
+};
+
+mod common;
+
+use common::{start_server, Server};
+
+/// Longest wait for anything the server should do right away
+const PATIENCE: Duration = Duration::from_secs(5);
+
+const ROOM: &str = r#"
+    [[rooms]]
+    name = "dish"
+    embedded = true
+    seed = 1
+"#;
+
+/// Start the app with an admin token `sesame` and a player token `pawn`
+async fn start_guarded_server() -> Server {
+    start_server(&format!(
+        r#"
+        admin_tokens = ["sesame"]
+        player_tokens = ["pawn"]
+        {ROOM}
+        "#
+    ))
+    .await
+}
+
+async fn pause(server: &Server, path: &str, headers: &str) -> u16 {
+    server.request("POST", path, headers).await.0
+}
+
+/// Open a websocket at `path` and ask to join the room
+///
+/// **Returns** the server's answer, `joined` or an error
+async fn join(server: &Server, path: &str) -> Result<ServerMessage, tungstenite::Error> {
+    let (mut ws, _) = connect_async(format!("ws://{}{path}", server.addr)).await?;
+    for msg in [
+        ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+        },
+        ClientMessage::Join {
+            name: "tester".to_string(),
+        },
+    ] {
+        let msg = serde_json::to_string(&msg).unwrap();
+        ws.send(Message::Text(msg)).await.unwrap();
+    }
+    loop {
+        let msg = timeout(PATIENCE, ws.next())
+            .await
+            .expect("no answer to join")
+            .expect("the server closed the websocket")?;
+        let Message::Text(text) = msg else {
+            continue;
+        };
+        let msg = serde_json::from_str(&text).unwrap();
+        if let ServerMessage::Joined { .. } | ServerMessage::Error { .. } = msg {
+            return Ok(msg);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn anonymous_clients_only_watch_by_default() {
+    let server = start_server(ROOM).await;
+    assert_eq!(pause(&server, "/api/control/pause", "").await, 403);
+    assert!(matches!(
+        join(&server, "/ws").await.unwrap(),
+        ServerMessage::Error { .. }
+    ));
+    assert_eq!(server.request("GET", "/api/stats", "").await.0, 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn anyone_may_do_anything_when_opened_up() {
+    let server = start_server(&format!("anonymous_role = \"admin\"\n{ROOM}")).await;
+    assert_eq!(pause(&server, "/api/control/pause", "").await, 200);
+    assert!(matches!(
+        join(&server, "/ws").await.unwrap(),
+        ServerMessage::Joined { .. }
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_admins_control_the_simulator() {
+    let server = start_guarded_server().await;
+    let path = "/api/control/pause";
+
+    assert_eq!(pause(&server, path, "").await, 403);
+    let player = "Authorization: Bearer pawn\r\n";
+    assert_eq!(pause(&server, path, player).await, 403);
+    let unknown = "Authorization: Bearer open\r\n";
+    assert_eq!(pause(&server, path, unknown).await, 401);
+    let basic = "Authorization: Basic c2VzYW1l\r\n";
+    assert_eq!(pause(&server, path, basic).await, 401);
+
+    let admin = "Authorization: Bearer sesame\r\n";
+    assert_eq!(pause(&server, path, admin).await, 200);
+    assert_eq!(
+        pause(&server, "/api/control/pause?token=sesame", "").await,
+        200
+    );
+
+    // Reading is left to anyone
+    assert_eq!(server.request("GET", "/api/stats", "").await.0, 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spectators_can_watch_but_not_play() {
+    let server = start_guarded_server().await;
+
+    match join(&server, "/ws").await.unwrap() {
+        ServerMessage::Error { message } => {
+            assert!(message.contains("player role"), "{message}")
+        }
+        msg => panic!("a spectator joined: {msg:?}"),
+    }
+    for path in ["/ws?token=pawn", "/ws/dish?token=sesame"] {
+        assert!(matches!(
+            join(&server, path).await.unwrap(),
+            ServerMessage::Joined { .. }
+        ));
+    }
+    match join(&server, "/ws?token=open").await {
+        Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 401),
+        other => panic!("an unknown token was let in: {other:?}"),
+    }
+}
@@ -0,0 +1,86 @@
+//! Checks the bodies the HTTP API takes, against an embedded room
+#![cfg(feature = "embedded")]
+
+use serde_json::Value;
+
+mod common;
+
+use common::{start_server, Server};
+
+async fn start_open_server() -> Server {
+    start_server(
+        r#"
+        anonymous_role = "admin"
+
+        [[rooms]]
+        name = "dish"
+        embedded = true
+        seed = 1
+        "#,
+    )
+    .await
+}
+
+async fn post(server: &Server, path: &str, body: &str) -> (u16, Value) {
+    let (status, body) = server.send("POST", path, "", body).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bodies_may_be_left_out() {
+    let server = start_open_server().await;
+    server.request("POST", "/api/control/pause", "").await;
+
+    let (status, paused) = post(&server, "/api/control/step", "").await;
+    assert_eq!(status, 200);
+    let (_, stepped) = post(&server, "/api/control/step", r#"{"ticks": 3}"#).await;
+    assert_eq!(stepped["tick"], paused["tick"].as_u64().unwrap() + 3);
+
+    let (status, _) = post(&server, "/api/control/reset", "").await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn configs_the_simulator_cant_run_are_refused() {
+    let server = start_open_server().await;
+    let (_, before) = server.request("GET", "/api/config", "").await;
+
+    for body in [
+        r#"{"size": 5.0}"#,
+        r#"{"base_speed": -1.0}"#,
+        r#"{"initial_num_npcs": 100000000}"#,
+    ] {
+        let (status, error) = server.send("PATCH", "/api/config", "", body).await;
+        assert_eq!(status, 400, "{body}: {error}");
+    }
+    let (_, after) = server.request("GET", "/api/config", "").await;
+    assert_eq!(after, before);
+
+    // The simulator survives a reset with the config it kept
+    let (status, _) = post(&server, "/api/control/reset", "").await;
+    assert_eq!(status, 200);
+    let (status, _) = post(&server, "/api/control/step", "").await;
+    assert_eq!(status, 200);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_bodies_are_refused() {
+    let server = start_open_server().await;
+    server.request("POST", "/api/control/pause", "").await;
+    let (_, before) = post(&server, "/api/control/step", r#"{"ticks": 0}"#).await;
+
+    for (path, body) in [
+        ("/api/control/step", r#"{"ticks": "ten"}"#),
+        ("/api/control/step", "ticks=10"),
+        ("/api/control/reset", r#"{"seed": "x"}"#),
+    ] {
+        let (status, error) = post(&server, path, body).await;
+        assert_eq!(status, 400, "{path} {body}");
+        let message = error["message"].as_str().unwrap();
+        assert!(message.starts_with("invalid body"), "{message}");
+    }
+
+    // Nothing was stepped or reset
+    let (_, after) = post(&server, "/api/control/step", r#"{"ticks": 0}"#).await;
+    assert_eq!(after["tick"], before["tick"]);
+}
@@ -0,0 +1,79 @@
+//! The app on a port of its own, for the tests to talk to
+
+use std::net::SocketAddr;
+
+use microbiome_backend::{
+    app::{make_app, serve},
+    config::{Config, Settings},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+};
+
+/// The app on a port of its own, shut down when dropped
+pub struct Server {
+    pub addr: SocketAddr,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.shutdown.send(true);
+    }
+}
+
+/// Start the app with `settings`, in the TOML of a config file, serving files of this crate
+pub async fn start_server(settings: &str) -> Server {
+    let settings: Settings = toml::from_str(&format!(
+        r#"
+        static_path = "{}"
+        recordings_path = "{}"
+        {settings}
+        "#,
+        env!("CARGO_MANIFEST_DIR"),
+        std::env::temp_dir().display(),
+    ))
+    .unwrap();
+    let config = Config::resolve(settings).unwrap();
+
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let (app, _) = make_app(&config, shutdown_rx.clone()).unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, app, shutdown_rx));
+    Server { addr, shutdown }
+}
+
+impl Server {
+    /// Make a request with `headers`, each ending in a newline
+    ///
+    /// **Returns** the status and the body of the response
+    pub async fn request(&self, method: &str, path: &str, headers: &str) -> (u16, String) {
+        self.send(method, path, headers, "").await
+    }
+
+    /// Make a request with `headers` and a JSON `body`
+    ///
+    /// **Returns** the status and the body of the response
+    pub async fn send(&self, method: &str, path: &str, headers: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(self.addr).await.unwrap();
+        let content_type = match body {
+            "" => "",
+            _ => "Content-Type: application/json\r\n",
+        };
+        let request = format!(
+            "{method} {path} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n{content_type}{headers}\r\n{body}",
+            self.addr,
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, body.to_string())
+    }
+}
@@ -0,0 +1,66 @@
+//! Checks the simulator of an embedded room, which runs inside the server
+#![cfg(feature = "embedded")]
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::{sleep, timeout};
+
+mod common;
+
+use common::start_server;
+
+/// Longest wait for anything the server should do right away
+const PATIENCE: Duration = Duration::from_secs(5);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rooms_configure_their_simulator() {
+    let server = start_server(
+        r#"
+        [[rooms]]
+        name = "big"
+        embedded = true
+
+        [rooms.sim]
+        size = 1000.0
+        "#,
+    )
+    .await;
+    let (status, body) = server.request("GET", "/api/config", "").await;
+    assert_eq!(status, 200);
+    let config: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(config["size"], 1000.0);
+    assert_eq!(config["eat_diff"], 5.0);
+}
+
+// A single thread for the server and the tests, which a simulator stepping on it would hold up
+#[tokio::test]
+async fn long_commands_leave_the_server_responsive() {
+    let server = Arc::new(
+        start_server(
+            r#"
+            anonymous_role = "admin"
+
+            [[rooms]]
+            name = "dish"
+            embedded = true
+            seed = 1
+            "#,
+        )
+        .await,
+    );
+    let step = {
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            let body = r#"{"ticks": 10000}"#;
+            server.send("POST", "/api/control/step", "", body).await
+        })
+    };
+    sleep(Duration::from_millis(100)).await;
+
+    let (status, _) = timeout(PATIENCE, server.request("GET", "/rooms", ""))
+        .await
+        .expect("the server is stuck");
+    assert_eq!(status, 200);
+    assert!(!step.is_finished(), "the step was too short to tell");
+    assert_eq!(step.await.unwrap().0, 200);
+}
@@ -0,0 +1,373 @@
+//! Runs the app on a port of its own, fed by a stub simulator publishing scripted messages, and
+//! checks what websocket clients receive
+
+use std::{collections::VecDeque, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use protocol::{
+    sim::{SimMessage, TOPIC},
+    ws::{ClientMessage, ServerMessage},
+    Event, FoodState, WorldState, PROTOCOL_VERSION,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::{sleep, timeout, Instant},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+mod common;
+
+use common::{start_server, Server};
+
+/// Longest wait for anything the server should do right away
+const PATIENCE: Duration = Duration::from_secs(5);
+
+/// Longer than the server waits before telling clients the simulator went silent
+const SILENCE: Duration = Duration::from_secs(4);
+
+/// A zmq PUB socket standing in for a simulator
+///
+/// The server has a zmq context of its own, so it's reached over tcp rather than `inproc://`.
+struct StubSim {
+    socket: zmq::Socket,
+    endpoint: String,
+}
+
+impl StubSim {
+    /// Publish on a free port, or on `endpoint` to come back where an earlier one was
+    fn bind(endpoint: Option<&str>) -> Self {
+        let socket = zmq::Context::new().socket(zmq::PUB).unwrap();
+        socket.set_linger(0).unwrap();
+        socket
+            .bind(endpoint.unwrap_or("tcp://127.0.0.1:*"))
+            .unwrap();
+        let endpoint = socket.get_last_endpoint().unwrap().unwrap();
+        Self { socket, endpoint }
+    }
+
+    fn send_raw(&self, frames: &[&[u8]]) {
+        self.socket.send_multipart(frames, 0).unwrap();
+    }
+
+    fn publish(&self, msg: &SimMessage) {
+        let frames = msg.to_frames(TOPIC).unwrap();
+        self.socket.send_multipart(frames, 0).unwrap();
+    }
+
+    fn publish_state(&self, tick: u64) {
+        self.publish(&SimMessage::State { state: world(tick) });
+    }
+}
+
+/// A small world, with food depending on the tick so frames tell apart
+fn world(tick: u64) -> WorldState {
+    WorldState {
+        tick,
+        size: 100.0,
+        cell_perception_radius: 20.0,
+        food_perception_radius: 20.0,
+        npcs: Vec::new(),
+        food: (0..tick % 8)
+            .map(|id| FoodState {
+                id,
+                pos: [id as f64, 50.0],
+                mass: 1.0,
+                radius: 1.0,
+                color: "#22c55e".to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// Start the app with a single room named `sim`, subscribed to `sim`
+async fn start_sim_server(sim: &StubSim) -> Server {
+    let room = format!(
+        r#"
+        [[rooms]]
+        name = "sim"
+        sub_at = "{}"
+        "#,
+        sim.endpoint
+    );
+    start_server(&room).await
+}
+
+/// A browser watching the room, past the handshake
+struct Client {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// Statuses of the simulator received while waiting for states
+    statuses: VecDeque<(bool, Option<u64>)>,
+    last_tick: Option<u64>,
+    /// Bytes of the messages received after the server's hello
+    received: u64,
+}
+
+impl Client {
+    async fn connect(server: &Server) -> Self {
+        let url = format!("ws://{}/ws/sim", server.addr);
+        let (ws, _) = connect_async(url).await.unwrap();
+        let mut client = Self {
+            ws,
+            statuses: VecDeque::new(),
+            last_tick: None,
+            received: 0,
+        };
+        assert!(matches!(
+            client.next().await,
+            ServerMessage::Hello {
+                version: PROTOCOL_VERSION
+            }
+        ));
+        client.received = 0;
+        let hello = ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+        };
+        let hello = serde_json::to_string(&hello).unwrap();
+        client.ws.send(Message::Text(hello)).await.unwrap();
+        client
+    }
+
+    async fn next(&mut self) -> ServerMessage {
+        loop {
+            let msg = timeout(PATIENCE, self.ws.next())
+                .await
+                .expect("no message from the server")
+                .expect("the server closed the websocket")
+                .unwrap();
+            if let Message::Text(text) = msg {
+                self.received += text.len() as u64;
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Skip to the next state of another tick than the last one, failing on errors
+    async fn next_state(&mut self) -> WorldState {
+        loop {
+            match self.next().await {
+                ServerMessage::State(state) if Some(state.tick) != self.last_tick => {
+                    self.last_tick = Some(state.tick);
+                    return state;
+                }
+                ServerMessage::SimStatus { online, tick } => {
+                    self.statuses.push_back((online, tick));
+                }
+                ServerMessage::Error { message } => panic!("error from the server: {message}"),
+                _ => (),
+            }
+        }
+    }
+
+    /// Next status of the simulator, in the order they were sent
+    async fn next_status(&mut self) -> (bool, Option<u64>) {
+        if let Some(status) = self.statuses.pop_front() {
+            return status;
+        }
+        loop {
+            if let ServerMessage::SimStatus { online, tick } = self.next().await {
+                return (online, tick);
+            }
+        }
+    }
+}
+
+/// Publish `tick` until the client gets it, as subscribers miss whatever is published before they
+/// are connected. The client then skips the copies still on their way.
+async fn publish_until_seen(sim: &StubSim, client: &mut Client, tick: u64) -> WorldState {
+    let start = Instant::now();
+    loop {
+        sim.publish_state(tick);
+        if let Ok(state) = timeout(Duration::from_millis(100), client.next_state()).await {
+            return state;
+        }
+        assert!(start.elapsed() < PATIENCE, "tick {tick} never arrived");
+    }
+}
+
+/// Value of an unlabelled sample, or of the one for the `sim` room, on `/metrics`
+async fn metric(server: &Server, name: &str) -> Option<f64> {
+    let (_, body) = server.request("GET", "/metrics", "").await;
+    let labelled = format!("{name}{{room=\"sim\"}} ");
+    let plain = format!("{name} ");
+    body.lines()
+        .find_map(|line| {
+            line.strip_prefix(&labelled)
+                .or_else(|| line.strip_prefix(&plain))
+        })
+        .map(|value| value.trim().parse().unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_get_the_published_frames_in_order() {
+    let sim = StubSim::bind(None);
+    let server = start_sim_server(&sim).await;
+    let mut client = Client::connect(&server).await;
+
+    // Nothing was published yet
+    assert_eq!(client.next_status().await, (false, None));
+
+    assert_eq!(publish_until_seen(&sim, &mut client, 1).await, world(1));
+    assert_eq!(client.next_status().await, (true, Some(1)));
+    for tick in 2..=5 {
+        sim.publish_state(tick);
+        assert_eq!(client.next_state().await, world(tick));
+    }
+    assert_eq!(metric(&server, "mb_ws_clients").await, Some(1.0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sent_bytes_are_summed_over_the_clients_of_a_room() {
+    let sim = StubSim::bind(None);
+    let server = start_sim_server(&sim).await;
+    let mut clients = [
+        Client::connect(&server).await,
+        Client::connect(&server).await,
+    ];
+    for client in &mut clients {
+        publish_until_seen(&sim, client, 1).await;
+    }
+    for tick in 2..=4 {
+        sim.publish_state(tick);
+        for client in &mut clients {
+            assert_eq!(client.next_state().await.tick, tick);
+        }
+    }
+
+    // Take whatever else was sent, until the server has nothing more to say
+    for client in &mut clients {
+        while timeout(Duration::from_millis(300), client.next())
+            .await
+            .is_ok()
+        {}
+    }
+    let received: u64 = clients.iter().map(|client| client.received).sum();
+    let start = Instant::now();
+    while metric(&server, "mb_ws_sent_bytes_total").await != Some(received as f64) {
+        assert!(
+            start.elapsed() < PATIENCE,
+            "{received} bytes were never counted"
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn malformed_messages_are_counted_and_skipped() {
+    let sim = StubSim::bind(None);
+    let server = start_sim_server(&sim).await;
+    let mut client = Client::connect(&server).await;
+    publish_until_seen(&sim, &mut client, 1).await;
+
+    let version = PROTOCOL_VERSION.to_string();
+    let state = serde_json::to_vec(&SimMessage::State { state: world(2) }).unwrap();
+    let malformed: [&[&[u8]]; 5] = [
+        // Missing the payload
+        &[TOPIC.as_bytes(), version.as_bytes()],
+        // One frame too many
+        &[TOPIC.as_bytes(), version.as_bytes(), &state, b""],
+        &[TOPIC.as_bytes(), b"0", &state],
+        &[TOPIC.as_bytes(), version.as_bytes(), b"{\"msg\": \"state\""],
+        // Subscriptions match topics by prefix
+        &[b"mb_state_other", version.as_bytes(), &state],
+    ];
+    for frames in malformed {
+        sim.send_raw(frames);
+    }
+    // Another topic altogether is never subscribed to
+    sim.send_raw(&[b"other", version.as_bytes(), &state]);
+    sim.publish_state(3);
+
+    assert_eq!(client.next_state().await, world(3));
+    assert_eq!(metric(&server, "mb_frame_errors_total").await, Some(5.0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_are_told_when_the_simulator_goes_silent_and_comes_back() {
+    let sim = StubSim::bind(None);
+    let endpoint = sim.endpoint.clone();
+    let server = start_sim_server(&sim).await;
+    let mut client = Client::connect(&server).await;
+    assert_eq!(client.next_status().await, (false, None));
+    publish_until_seen(&sim, &mut client, 7).await;
+    assert_eq!(client.next_status().await, (true, Some(7)));
+
+    drop(sim);
+    let status = timeout(SILENCE, client.next_status()).await.unwrap();
+    assert_eq!(status, (false, Some(7)));
+
+    // A restarted simulator starts over, and the server's subscription reconnects to it
+    let sim = StubSim::bind(Some(&endpoint));
+    assert_eq!(publish_until_seen(&sim, &mut client, 1).await, world(1));
+    assert_eq!(client.next_status().await, (true, Some(1)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reconnecting_clients_get_the_latest_frame_right_away() {
+    let sim = StubSim::bind(None);
+    let server = start_sim_server(&sim).await;
+    let mut watching = Client::connect(&server).await;
+    let mut leaving = Client::connect(&server).await;
+    publish_until_seen(&sim, &mut watching, 1).await;
+    assert_eq!(leaving.next_state().await, world(1));
+
+    leaving.ws.close(None).await.unwrap();
+    drop(leaving);
+    sim.publish_state(2);
+    assert_eq!(watching.next_state().await, world(2));
+
+    // Without waiting for the simulator to publish again
+    let mut back = Client::connect(&server).await;
+    assert_eq!(back.next_state().await, world(2));
+    sim.publish_state(3);
+    assert_eq!(back.next_state().await, world(3));
+    assert_eq!(watching.next_state().await, world(3));
+
+    // The server notices the client is gone once it reads the close
+    let start = Instant::now();
+    while metric(&server, "mb_ws_clients").await != Some(2.0) {
+        assert!(
+            start.elapsed() < PATIENCE,
+            "the client that left was never let go"
+        );
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn event_streams_get_the_events_too() {
+    let sim = StubSim::bind(None);
+    let server = start_sim_server(&sim).await;
+    let mut stream = TcpStream::connect(server.addr).await.unwrap();
+    let request = format!("GET /sse/sim HTTP/1.1\r\nHost: {}\r\n\r\n", server.addr);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut lines = BufReader::new(stream).lines();
+
+    let ate = Event::AteFood {
+        tick: 1,
+        cell: 2,
+        food: 3,
+        mass: 1.0,
+    };
+    let start = Instant::now();
+    loop {
+        // Subscribers miss whatever is published before they are connected
+        sim.publish(&SimMessage::Events {
+            events: vec![ate.clone()],
+        });
+        let line = match timeout(Duration::from_millis(100), lines.next_line()).await {
+            Ok(line) => line.unwrap().expect("the server ended the stream"),
+            Err(_) => {
+                assert!(start.elapsed() < PATIENCE, "the events never arrived");
+                continue;
+            }
+        };
+        if line == "event: events" {
+            break;
+        }
+    }
+    let data = timeout(PATIENCE, lines.next_line()).await.unwrap().unwrap();
+    let msg = data.unwrap();
+    let msg = serde_json::from_str(msg.strip_prefix("data: ").unwrap()).unwrap();
+    assert!(matches!(msg, ServerMessage::Events(events) if events == vec![ate]));
+}
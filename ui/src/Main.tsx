@@ -1,15 +1,118 @@
-import React, { useCallback, useState } from "react";
-import { useWebSocket } from "./Socket";
+import React, { useCallback, useEffect, useRef, useState } from "react";
+import { useWebSocket, useWebSocketClient } from "./Socket";
 import Display from "./canvas/display";
+import type { ServerMessage } from "./protocol/ServerMessage";
+
+type ReplayStatus = Extract<ServerMessage, { event: "replay_status" }>["data"];
+
+/** Minimum time between two steer messages */
+const STEER_INTERVAL_MS = 50;
 
 const Main: React.FC = () => {
   const [d, setD] = useState<Display>();
+  const [player, setPlayer] = useState<number | null>(null);
+  const [name, setName] = useState("");
+  const [simOnline, setSimOnline] = useState(true);
+  const [fog, setFog] = useState(false);
+  const [replay, setReplay] = useState<ReplayStatus | null>(null);
+  const lastSteer = useRef(0);
+  const wsc = useWebSocketClient();
+
+  useWebSocket(
+    "state",
+    (data) => {
+      console.log("Data received", data);
+      d?.draw(data);
+    },
+    [d],
+  );
+
+  useWebSocket(
+    "view",
+    (data) => {
+      d?.draw(data);
+    },
+    [d],
+  );
+
+  useWebSocket(
+    "joined",
+    ({ owner }) => {
+      setPlayer(owner);
+      if (!d) return;
+      d.player = owner;
+      // Only take in the player's surroundings, following their cells around
+      wsc.send({
+        event: "view",
+        data: { center: [0, 0], zoom: 1, size: [d.w, d.h] },
+      });
+      wsc.send({ event: "follow", data: { id: owner } });
+    },
+    [d],
+  );
+
+  useWebSocket(
+    "left",
+    ({ reason }) => {
+      console.log(`Left the biome: ${reason}`);
+      setPlayer(null);
+      setFog(false);
+      if (d) d.player = null;
+      wsc.send({ event: "clear_view" });
+    },
+    [d],
+  );
+
+  useWebSocket("sim_status", ({ online, tick }) => {
+    console.log(`Simulator ${online ? "online" : "offline"} at tick ${tick}`);
+    setSimOnline(online);
+  });
 
-  useWebSocket("state", (data) => {
-    console.log("Data received", data);
-    d?.draw(data);
+  useWebSocket("replay_status", (status) => {
+    setReplay(status);
   });
 
+  useWebSocket("error", ({ message }) => {
+    console.error(`Server error: ${message}`);
+  });
+
+  useEffect(() => {
+    if (player === null) return;
+
+    const onKeyDown = (e: KeyboardEvent) => {
+      if (e.code === "Space") wsc.send({ event: "split" });
+      else if (e.code === "KeyW") wsc.send({ event: "eject" });
+      else if (e.code === "Escape") wsc.send({ event: "leave" });
+      else if (e.code === "KeyF") {
+        // Only see what the player's cells perceive
+        wsc.send({ event: "fog", data: { enabled: !fog } });
+        setFog(!fog);
+      }
+    };
+
+    window.addEventListener("keydown", onKeyDown);
+    return () => window.removeEventListener("keydown", onKeyDown);
+  }, [player, fog, wsc]);
+
+  const onMouseMove = (e: React.MouseEvent<HTMLCanvasElement>) => {
+    if (player === null || !d) return;
+
+    const now = performance.now();
+    if (now - lastSteer.current < STEER_INTERVAL_MS) return;
+    lastSteer.current = now;
+
+    const rect = e.currentTarget.getBoundingClientRect();
+    wsc.send({
+      event: "steer",
+      data: { target: d.toBiome(e.clientX - rect.left, e.clientY - rect.top) },
+    });
+  };
+
+  const join = (e: React.FormEvent) => {
+    e.preventDefault();
+    wsc.send({ event: "join", data: { name: name.trim() || "player" } });
+  };
+
   const canvasMounted = useCallback((node: HTMLCanvasElement) => {
     if (!node) return;
 
@@ -28,7 +131,73 @@ const Main: React.FC = () => {
       ) : (
         <canvas ref={canvasMounted} />
       )} */}
-      <canvas ref={canvasMounted} />
+      <canvas ref={canvasMounted} onMouseMove={onMouseMove} />
+      {!simOnline && (
+        <div className="absolute inset-0 flex animate-pulse items-center justify-center text-sm text-gray-600">
+          <span>Waiting on the simulator...</span>
+        </div>
+      )}
+      {replay && (
+        <div className="absolute inset-x-4 bottom-4 flex items-center gap-2 text-sm">
+          <button
+            onClick={() =>
+              wsc.send({ event: replay.playing ? "pause" : "play" })
+            }
+            className="rounded border border-gray-600 px-2 py-1"
+          >
+            {replay.playing ? "Pause" : "Play"}
+          </button>
+          <input
+            type="range"
+            min={0}
+            max={replay.duration_ms}
+            defaultValue={replay.time_ms}
+            key={replay.time_ms}
+            onChange={(e) =>
+              wsc.send({
+                event: "seek",
+                data: { time_ms: Number(e.target.value) },
+              })
+            }
+            className="flex-1"
+          />
+          <select
+            value={replay.speed}
+            onChange={(e) =>
+              wsc.send({
+                event: "speed",
+                data: { speed: Number(e.target.value) },
+              })
+            }
+            className="rounded border border-gray-600 bg-transparent px-2 py-1"
+          >
+            {[0.25, 0.5, 1, 2, 4].map((x) => (
+              <option key={x} value={x}>
+                {x}x
+              </option>
+            ))}
+          </select>
+        </div>
+      )}
+      {player === null && !replay && (
+        <form
+          onSubmit={join}
+          className="absolute right-4 top-4 flex gap-2 text-sm"
+        >
+          <input
+            value={name}
+            onChange={(e) => setName(e.target.value)}
+            placeholder="Name"
+            className="rounded border border-gray-600 bg-transparent px-2 py-1"
+          />
+          <button
+            type="submit"
+            className="rounded border border-gray-600 px-2 py-1"
+          >
+            Join
+          </button>
+        </form>
+      )}
     </div>
   );
 };
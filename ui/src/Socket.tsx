@@ -6,8 +6,21 @@ import React, {
   useEffect,
   useState,
 } from "react";
+import type { ClientMessage } from "./protocol/ClientMessage";
+import type { ServerMessage } from "./protocol/ServerMessage";
+import {
+  JSON_SUBPROTOCOL,
+  MSGPACK_SUBPROTOCOL,
+  PROTOCOL_VERSION,
+} from "./protocol/version";
+import { decode } from "./msgpack";
 
-type Handler = (data: string) => void;
+type Event = ServerMessage["event"];
+
+type EventData<E extends Event> =
+  Extract<ServerMessage, { event: E }> extends { data: infer D } ? D : never;
+
+type Handler = (data: any) => void;
 
 type MessageRouter = {
   [event: string]: Handler;
@@ -32,16 +45,39 @@ class WebSocketClient {
   }
 
   private connect() {
-    this.ws = new WebSocket(this.url);
+    // Binary frames are smaller and quicker to read, JSON is easier to inspect in devtools
+    const format = new URLSearchParams(location.search).get("format");
+    this.ws = new WebSocket(
+      this.url,
+      format === "json"
+        ? [JSON_SUBPROTOCOL]
+        : [MSGPACK_SUBPROTOCOL, JSON_SUBPROTOCOL],
+    );
+    this.ws.binaryType = "arraybuffer";
 
     this.ws.onopen = () => {
       console.log("WebSocket Connected");
+      this.send({ event: "hello", data: { version: PROTOCOL_VERSION } });
       this.reconnectAttempts = 0;
       this.reconnectDelay = 2000;
     };
 
     this.ws.onmessage = (e) => {
-      const { event, data } = JSON.parse(e.data);
+      const msg = (
+        typeof e.data === "string"
+          ? JSON.parse(e.data)
+          : decode(new Uint8Array(e.data))
+      ) as ServerMessage;
+      const { event, data } = msg;
+
+      if (event === "hello") {
+        if (data.version !== PROTOCOL_VERSION) {
+          console.error(
+            `Server speaks protocol version ${data.version}, expected ${PROTOCOL_VERSION}`,
+          );
+        }
+        return;
+      }
 
       if (event in this.router) {
         this.router[event]?.(data);
@@ -76,14 +112,19 @@ class WebSocketClient {
     }
   }
 
-  route(event: string, handler: Handler) {
+  route<E extends Event>(event: E, handler: (data: EventData<E>) => void) {
     this.router[event] = handler;
   }
 
-  unroute(event: string) {
+  unroute(event: Event) {
     delete this.router[event];
   }
 
+  send(msg: ClientMessage) {
+    if (this.ws?.readyState !== WebSocket.OPEN) return;
+    this.ws.send(JSON.stringify(msg));
+  }
+
   close() {
     this.ws?.close();
   }
@@ -91,9 +132,9 @@ class WebSocketClient {
 
 const WebSocketContext = createContext<WebSocketClient>(null);
 
-export const useWebSocket = (
-  event: string,
-  handler: (data: any) => void,
+export const useWebSocket = <E extends Event>(
+  event: E,
+  handler: (data: EventData<E>) => void,
   deps?: DependencyList,
 ) => {
   const wsc = useContext(WebSocketContext);
@@ -106,6 +147,8 @@ export const useWebSocket = (
   }, deps);
 };
 
+export const useWebSocketClient = () => useContext(WebSocketContext);
+
 export const WebSocketProvider: React.FC<{ children: ReactNode }> = ({
   children,
 }) => {
@@ -117,7 +160,18 @@ export const WebSocketProvider: React.FC<{ children: ReactNode }> = ({
         "https:": "wss:",
         "http:": "ws:",
       }[window.location.protocol];
-      const url = `${wsproto}//${location.host}/ws`;
+      const params = new URLSearchParams(location.search);
+      const room = params.get("room");
+      const replay = params.get("replay");
+      const path = replay
+        ? `/ws/replay/${encodeURIComponent(replay)}`
+        : room
+          ? `/ws/${encodeURIComponent(room)}`
+          : "/ws";
+      // Browsers can't set headers on websockets, so a token travels in the query
+      const token = params.get("token");
+      const query = token ? `?token=${encodeURIComponent(token)}` : "";
+      const url = `${wsproto}//${location.host}${path}${query}`;
       const wsc = new WebSocketClient(url);
       setWsc(wsc);
     })();
@@ -1 +1,6 @@
+import type { RoomInfo } from "../protocol/RoomInfo";
+
 export const getStatus = () => fetch("/api/status");
+
+export const getRooms = async (): Promise<RoomInfo[]> =>
+  (await fetch("/rooms")).json();
@@ -1,4 +1,8 @@
-import { Entity, FrameData } from "../types";
+import type { FoodState } from "../protocol/FoodState";
+import type { NpcState } from "../protocol/NpcState";
+import type { Perception } from "../protocol/Perception";
+import type { ViewState } from "../protocol/ViewState";
+import type { WorldState } from "../protocol/WorldState";
 
 class Display {
   canvas: HTMLCanvasElement;
@@ -6,6 +10,12 @@ class Display {
   w: number;
   h: number;
   pad: number;
+  /** Owner id of the cells steered from this browser */
+  player: number | null = null;
+  /** Point of the biome at the top left corner of the canvas */
+  origin: [number, number] = [0, 0];
+  /** Canvas pixels per unit of the biome */
+  zoom = 1;
 
   bind(canvas: HTMLCanvasElement, cx: CanvasRenderingContext2D) {
     this.canvas = canvas;
@@ -37,7 +47,7 @@ class Display {
     this.cx.clearRect(0, 0, this.w, this.h);
   }
 
-  drawEntity(entity: Entity) {
+  drawEntity(entity: NpcState | FoodState) {
     const { pos, radius, color } = entity;
     this.cx.beginPath();
     this.cx.fillStyle = color;
@@ -45,15 +55,71 @@ class Display {
     this.cx.fill();
   }
 
-  draw(frame: FrameData) {
+  drawNPC(npc: NpcState) {
+    this.drawEntity(npc);
+    if (npc.kind.type !== "controlled") return;
+
+    const { pos, radius } = npc;
+    if (npc.kind.owner === this.player) {
+      this.cx.lineWidth = 2;
+      this.cx.strokeStyle = "white";
+      this.cx.stroke();
+    }
+    if (npc.kind.name) {
+      this.cx.fillStyle = "white";
+      this.cx.font = `${Math.max(10, radius / 2)}px sans-serif`;
+      this.cx.textAlign = "center";
+      this.cx.textBaseline = "middle";
+      this.cx.fillText(npc.kind.name, pos[0], pos[1]);
+    }
+  }
+
+  drawPerception({ pos, cells, food }: Perception) {
+    const { cx } = this;
+    cx.save();
+    cx.lineWidth = 1;
+    cx.setLineDash([4, 4]);
+    for (const [radius, color] of [
+      [cells, "rgba(255, 255, 255, 0.4)"],
+      [food, "rgba(255, 255, 255, 0.2)"],
+    ] as const) {
+      cx.beginPath();
+      cx.strokeStyle = color;
+      cx.ellipse(pos[0], pos[1], radius, radius, 0, 0, 2 * Math.PI);
+      cx.stroke();
+    }
+    cx.restore();
+  }
+
+  /** Point of the biome under a point of the canvas */
+  toBiome(x: number, y: number): [number, number] {
+    return [this.origin[0] + x / this.zoom, this.origin[1] + y / this.zoom];
+  }
+
+  /** Draw the whole biome unscaled, or the part of it in the server's view */
+  draw(frame: WorldState | ViewState) {
     this.clear();
 
+    if ("origin" in frame) {
+      this.origin = frame.origin;
+      this.zoom = frame.zoom;
+    } else {
+      this.origin = [0, 0];
+      this.zoom = 1;
+    }
+
     for (const food of frame.food) {
       this.drawEntity(food);
     }
 
     for (const npc of frame.npcs.reverse()) {
-      this.drawEntity(npc);
+      this.drawNPC(npc);
+    }
+
+    if ("perception" in frame) {
+      for (const perception of frame.perception) {
+        this.drawPerception(perception);
+      }
     }
   }
 }
@@ -0,0 +1,97 @@
+// Decoder for the MessagePack the server sends on the mb.msgpack.v1 subprotocol
+
+const textDecoder = new TextDecoder();
+
+class Reader {
+  private pos = 0;
+  private readonly view: DataView;
+
+  constructor(private readonly bytes: Uint8Array) {
+    this.view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+  }
+
+  private advance(n: number): number {
+    const at = this.pos;
+    this.pos += n;
+    return at;
+  }
+
+  private str(len: number): string {
+    const at = this.advance(len);
+    return textDecoder.decode(this.bytes.subarray(at, at + len));
+  }
+
+  private array(len: number): unknown[] {
+    const out = new Array(len);
+    for (let i = 0; i < len; i++) out[i] = this.value();
+    return out;
+  }
+
+  private map(len: number): Record<string, unknown> {
+    const out: Record<string, unknown> = {};
+    for (let i = 0; i < len; i++) {
+      const key = this.value() as string;
+      out[key] = this.value();
+    }
+    return out;
+  }
+
+  value(): unknown {
+    const v = this.view;
+    const b = v.getUint8(this.advance(1));
+
+    if (b <= 0x7f) return b;
+    if (b >= 0xe0) return b - 0x100;
+    if ((b & 0xf0) === 0x80) return this.map(b & 0x0f);
+    if ((b & 0xf0) === 0x90) return this.array(b & 0x0f);
+    if ((b & 0xe0) === 0xa0) return this.str(b & 0x1f);
+
+    switch (b) {
+      case 0xc0:
+        return null;
+      case 0xc2:
+        return false;
+      case 0xc3:
+        return true;
+      case 0xca:
+        return v.getFloat32(this.advance(4));
+      case 0xcb:
+        return v.getFloat64(this.advance(8));
+      case 0xcc:
+        return v.getUint8(this.advance(1));
+      case 0xcd:
+        return v.getUint16(this.advance(2));
+      case 0xce:
+        return v.getUint32(this.advance(4));
+      case 0xcf:
+        return Number(v.getBigUint64(this.advance(8)));
+      case 0xd0:
+        return v.getInt8(this.advance(1));
+      case 0xd1:
+        return v.getInt16(this.advance(2));
+      case 0xd2:
+        return v.getInt32(this.advance(4));
+      case 0xd3:
+        return Number(v.getBigInt64(this.advance(8)));
+      case 0xd9:
+        return this.str(v.getUint8(this.advance(1)));
+      case 0xda:
+        return this.str(v.getUint16(this.advance(2)));
+      case 0xdb:
+        return this.str(v.getUint32(this.advance(4)));
+      case 0xdc:
+        return this.array(v.getUint16(this.advance(2)));
+      case 0xdd:
+        return this.array(v.getUint32(this.advance(4)));
+      case 0xde:
+        return this.map(v.getUint16(this.advance(2)));
+      case 0xdf:
+        return this.map(v.getUint32(this.advance(4)));
+      default:
+        throw new Error(`unsupported MessagePack type 0x${b.toString(16)}`);
+    }
+  }
+}
+
+export const decode = (bytes: Uint8Array): unknown =>
+  new Reader(bytes).value();
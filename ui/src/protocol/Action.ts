@@ -0,0 +1,16 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * How an externally controlled cell should move
+ *
+ * A `target` takes precedence over a `dir`. A cell with neither stays put.
+ */
+export type Action = { 
+/**
+ * Direction to move in, normalized by the simulator
+ */
+dir?: [number, number] | null, 
+/**
+ * Point in the biome to move towards
+ */
+target?: [number, number] | null, };
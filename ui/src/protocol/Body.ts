@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A cell or piece of food as seen by an observer
+ */
+export type Body = { id: number, pos: [number, number], mass: number, radius: number, };
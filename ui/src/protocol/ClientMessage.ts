@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { MessageKind } from "./MessageKind";
+import type { Viewport } from "./Viewport";
+
+/**
+ * Messages sent by browsers
+ */
+export type ClientMessage = { "event": "hello", "data": { version: number, } } | { "event": "join", "data": { name: string, } } | { "event": "steer", "data": { target: [number, number], } } | { "event": "split" } | { "event": "eject" } | { "event": "leave" } | { "event": "view", "data": Viewport } | { "event": "follow", "data": { id?: number | null, } } | { "event": "fog", "data": { enabled: boolean, } } | { "event": "clear_view" } | { "event": "subscribe", "data": { 
+/**
+ * Unlimited if null
+ */
+max_fps?: number | null, kinds: Array<MessageKind>, } } | { "event": "play" } | { "event": "pause" } | { "event": "seek", "data": { time_ms: number, } } | { "event": "speed", "data": { speed: number, } };
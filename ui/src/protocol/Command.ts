@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Action } from "./Action";
+import type { EntityKind } from "./EntityKind";
+import type { Snapshot } from "./Snapshot";
+import type { JsonValue } from "./serde_json/JsonValue";
+
+/**
+ * Commands accepted on the simulator's control socket
+ */
+export type Command = { "cmd": "hello", version: number, } | { "cmd": "pause" } | { "cmd": "resume" } | { "cmd": "step", ticks: number, } | { "cmd": "reset", seed?: number | null, } | { "cmd": "spawn", entity: EntityKind, pos?: [number, number] | null, mass?: number | null, } | { "cmd": "remove", id: number, } | { "cmd": "get_stats" } | { "cmd": "get_config" } | { "cmd": "set_config", values: { [key in string]: JsonValue }, } | { "cmd": "take_snapshot" } | { "cmd": "load_snapshot", snapshot: Snapshot, } | { "cmd": "join", name: string, } | { "cmd": "steer", owner: number, action: Action, } | { "cmd": "split", owner: number, } | { "cmd": "eject", owner: number, } | { "cmd": "leave", owner: number, };
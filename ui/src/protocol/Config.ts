@@ -0,0 +1,38 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Tunable parameters of a microbiome, defaulting to the constants of this module
+ */
+export type Config = { 
+/**
+ * Size of the biome
+ */
+size: number, 
+/**
+ * Base speed of cells, decreases with mass
+ */
+base_speed: number, 
+/**
+ * Perception radius of cells for other cells
+ */
+cell_perception_radius: number, 
+/**
+ * Perception radius of cells for food
+ */
+food_perception_radius: number, 
+/**
+ * How much bigger a cell must be to eat another
+ */
+eat_diff: number, 
+/**
+ * Number of NPCs spawned initially
+ */
+initial_num_npcs: number, 
+/**
+ * Number of food cells to start with
+ */
+initial_food_supply: number, 
+/**
+ * How much food is spawned per second
+ */
+food_spawn_rate: number, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Kinds of entities that can be spawned
+ */
+export type EntityKind = "npc" | "food";
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Something notable that happened during a step of the microbiome
+ */
+export type Event = { "event": "ate_food", tick: number, cell: number, food: number, mass: number, } | { "event": "ate_cell", tick: number, cell: number, prey: number, mass: number, } | { "event": "monopoly", tick: number, cell: number, };
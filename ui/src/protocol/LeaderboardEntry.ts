@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A player with all of its cells, or a cell nobody controls
+ */
+export type LeaderboardEntry = { 
+/**
+ * Owner of a player's cells, or id of the cell
+ */
+id: number, name?: string | null, mass: number, cells: number, };
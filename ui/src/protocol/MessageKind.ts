@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Messages a browser can subscribe to, besides those it always gets such as `sim_status`
+ */
+export type MessageKind = "state" | "events" | "stats" | "leaderboard";
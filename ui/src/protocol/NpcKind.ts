@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Action } from "./Action";
+
+/**
+ * How an NPC decides where to go
+ */
+export type NpcKind = { "type": "linear", dir: [number, number], } | { "type": "advanced", dir: [number, number], } | { "type": "controlled", owner: number, name: string | null, action: Action, };
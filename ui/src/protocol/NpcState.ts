@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { NpcKind } from "./NpcKind";
+
+export type NpcState = { id: number, pos: [number, number], mass: number, radius: number, color: string, kind: NpcKind, };
@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Body } from "./Body";
+
+/**
+ * What an externally controlled cell perceives, using the same circles as NPCs
+ */
+export type Observation = { tick: number, 
+/**
+ * The observer's own cells, largest first
+ */
+cells: Array<Body>, 
+/**
+ * Other cells within the cell perception radius of the largest own cell
+ */
+npcs: Array<Body>, 
+/**
+ * Food within the food perception radius of the largest own cell
+ */
+food: Array<Body>, };
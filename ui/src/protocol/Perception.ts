@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * What a cell perceives, in screen pixels like the rest of a [`ViewState`]
+ */
+export type Perception = { pos: [number, number], 
+/**
+ * Radius within which it sees other cells
+ */
+cells: number, 
+/**
+ * Radius within which it sees food
+ */
+food: number, };
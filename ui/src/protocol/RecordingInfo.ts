@@ -0,0 +1,18 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A recording of a room, listed by `GET /api/recordings` and played back at `/ws/replay/{id}`
+ */
+export type RecordingInfo = { id: number, room: string, 
+/**
+ * Unix time in milliseconds
+ */
+started_at: number, frames: number, 
+/**
+ * Time of the last frame since the start
+ */
+duration_ms: number, 
+/**
+ * Whether the recording was stopped, as opposed to still running or cut short
+ */
+finished: boolean, };
@@ -0,0 +1,9 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Config } from "./Config";
+import type { Snapshot } from "./Snapshot";
+import type { Stats } from "./Stats";
+
+/**
+ * Replies sent back for every [`Command`]
+ */
+export type Reply = { "reply": "hello", version: number, } | { "reply": "status", tick: number, paused: boolean, } | { "reply": "spawned", id: number, } | { "reply": "stats", stats: Stats, } | { "reply": "config", config: Config, } | { "reply": "snapshot", snapshot: Snapshot, } | { "reply": "error", message: string, };
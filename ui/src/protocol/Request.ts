@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Action } from "./Action";
+
+export type Request = { "msg": "register", version: number, } | { "msg": "act", action: Action, } | { "msg": "leave" };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Optional body of `POST /api/control/reset`
+ */
+export type ResetRequest = { 
+/**
+ * Seed to start over with, random if omitted
+ */
+seed?: number | null, };
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Observation } from "./Observation";
+
+export type Response = { "msg": "observation", agent: number, observation: Observation, } | { "msg": "dead", agent: number, } | { "msg": "left" } | { "msg": "error", message: string, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Position of the simulator's random number generator
+ */
+export type RngState = { seed: Array<number>, stream: number, word_pos: number, };
@@ -0,0 +1,30 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A simulator served by the server, listed by `GET /rooms`
+ */
+export type RoomInfo = { 
+/**
+ * Name to connect to at `/ws/{name}`
+ */
+name: string, 
+/**
+ * Whether the simulator is publishing
+ */
+online: boolean, 
+/**
+ * Tick of the last frame, if any arrived yet
+ */
+tick: number | null, 
+/**
+ * Websockets currently in the room
+ */
+clients: number, 
+/**
+ * Whether browsers can join as players
+ */
+players: boolean, 
+/**
+ * Id of the recording of the room, while it is being recorded
+ */
+recording: number | null, };
@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Event } from "./Event";
+import type { LeaderboardEntry } from "./LeaderboardEntry";
+import type { Stats } from "./Stats";
+import type { ViewState } from "./ViewState";
+import type { WorldState } from "./WorldState";
+
+/**
+ * Messages sent by the server
+ */
+export type ServerMessage = { "event": "hello", "data": { version: number, } } | { "event": "state", "data": WorldState } | { "event": "view", "data": ViewState } | { "event": "events", "data": Array<Event> } | { "event": "stats", "data": Stats } | { "event": "leaderboard", "data": Array<LeaderboardEntry> } | { "event": "sim_status", "data": { online: boolean, 
+/**
+ * Tick of the last frame, if any arrived yet
+ */
+tick: number | null, } } | { "event": "replay_status", "data": { id: number, time_ms: number, duration_ms: number, playing: boolean, speed: number, } } | { "event": "joined", "data": { owner: number, } } | { "event": "left", "data": { reason: string, } } | { "event": "error", "data": { message: string, } };
@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Event } from "./Event";
+import type { Stats } from "./Stats";
+import type { WorldState } from "./WorldState";
+
+/**
+ * Published as three frames: the topic, the protocol version and the JSON message
+ */
+export type SimMessage = { "msg": "state", state: WorldState, } | { "msg": "events", events: Array<Event>, } | { "msg": "metrics", stats: Stats, 
+/**
+ * Average time a step took since the last report, 0 if it was paused throughout
+ */
+tick_seconds: number, };
@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Whether a room's simulator is paused, answered by the `/api/control` endpoints
+ */
+export type SimState = { tick: number, paused: boolean, };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Config } from "./Config";
+import type { FoodState } from "./FoodState";
+import type { NpcState } from "./NpcState";
+import type { RngState } from "./RngState";
+
+/**
+ * Complete state of a microbiome, enough to restore it exactly
+ */
+export type Snapshot = { config: Config, rng: RngState, next_id: number, elapsed: number, npcs: Array<NpcState>, food: Array<FoodState>, };
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A snapshot kept by the server, answered by `POST /api/snapshot`
+ */
+export type SnapshotInfo = { 
+/**
+ * Id to fetch it with from `GET /api/snapshot/{id}`
+ */
+id: number, tick: number, };
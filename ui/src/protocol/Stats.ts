@@ -0,0 +1,6 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Population and mass metrics of the microbiome at a point in time
+ */
+export type Stats = { tick: number, npcs: number, food: number, npc_mass: number, max_npc_mass: number, food_mass: number, };
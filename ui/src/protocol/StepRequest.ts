@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Optional body of `POST /api/control/step`
+ */
+export type StepRequest = { 
+/**
+ * Ticks to advance, 1 if omitted
+ */
+ticks?: number | null, };
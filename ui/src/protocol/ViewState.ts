@@ -0,0 +1,25 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FoodState } from "./FoodState";
+import type { NpcState } from "./NpcState";
+import type { Perception } from "./Perception";
+
+/**
+ * Entities inside a browser's viewport, with positions and radii in screen pixels from its top left corner
+ */
+export type ViewState = { tick: number, 
+/**
+ * Width and height of the biome
+ */
+size: number, 
+/**
+ * Point of the biome at the top left corner of the view
+ */
+origin: [number, number], 
+/**
+ * Screen pixels per unit of the biome
+ */
+zoom: number, 
+/**
+ * Perception of each followed cell, when in fog of war
+ */
+perception: Array<Perception>, npcs: Array<NpcState>, food: Array<FoodState>, };
@@ -0,0 +1,18 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Part of the biome a browser is looking at
+ */
+export type Viewport = { 
+/**
+ * Point of the biome in the middle of the view
+ */
+center: [number, number], 
+/**
+ * Screen pixels per unit of the biome
+ */
+zoom: number, 
+/**
+ * Width and height of the view in screen pixels
+ */
+size: [number, number], };
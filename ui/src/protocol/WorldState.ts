@@ -0,0 +1,20 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FoodState } from "./FoodState";
+import type { NpcState } from "./NpcState";
+
+/**
+ * Everything needed to draw the biome after a step
+ */
+export type WorldState = { tick: number, 
+/**
+ * Width and height of the biome
+ */
+size: number, 
+/**
+ * How far cells see other cells
+ */
+cell_perception_radius: number, 
+/**
+ * How far cells see food
+ */
+food_perception_radius: number, npcs: Array<NpcState>, food: Array<FoodState>, };
@@ -0,0 +1,7 @@
+// This file was generated by ts-bindings. Do not edit this file manually.
+
+export const PROTOCOL_VERSION = 1;
+
+export const JSON_SUBPROTOCOL = "mb.json.v1";
+
+export const MSGPACK_SUBPROTOCOL = "mb.msgpack.v1";